@@ -0,0 +1,114 @@
+use crate::ast::*;
+use crate::keys::{key_fifths, step_alterations, step_index};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// Spell the pitches of one voice of a measure, written with `Accidentals: Key`,
+/// as explicit pitches.
+///
+/// A pitch without an accidental takes the key's sharp or flat for its step, unless
+/// an earlier pitch on the same step and octave had an accidental: as in printed
+/// music, that accidental (including a natural, `n`) lasts to the end of the measure.
+/// Keys without a signature (e.g. octatonic) leave every step natural.
+///
+/// `tied` holds the pitches tied over the barline into this measure, as spelled: a
+/// note continuing one of them keeps its pitch. It is replaced by the pitches this
+/// measure ties into the next.
+pub fn apply_key(events: &mut [Event], key: Option<Key>, tied: &mut Vec<Pitch>) -> Result<()> {
+    let fifths = key.and_then(|key| key_fifths(&key).ok()).unwrap_or(0);
+    let alterations = step_alterations(fifths);
+    let mut ties = Ties { last: Vec::new(), open: std::mem::take(tied) };
+    spell_events(events, &alterations, &mut HashMap::new(), &mut ties)?;
+    *tied = ties.open;
+    Ok(())
+}
+
+/// Motif bodies as they read in `key`, each spelled as a measure of its own.
+pub fn apply_key_to_motifs(motifs: &BTreeMap<String, Vec<Event>>, key: Option<Key>) -> Result<BTreeMap<String, Vec<Event>>> {
+    motifs.iter().map(|(name, body)| {
+        let mut body = body.clone();
+        apply_key(&mut body, key, &mut Vec::new()).map_err(|e| anyhow!("{} in motif '@{}'", e, name))?;
+        Ok((name.clone(), body))
+    }).collect()
+}
+
+/// Spelled pitches that a tie may continue.
+struct Ties {
+    last: Vec<Pitch>, // Sounded by the most recent note or chord
+    open: Vec<Pitch>, // Followed by `~`, waiting for the next note or chord
+}
+
+fn spell_events(
+    events: &mut [Event],
+    alterations: &[i32; 7],
+    carried: &mut HashMap<(char, i32), Accidental>,
+    ties: &mut Ties,
+) -> Result<()> {
+    for event in events {
+        match event {
+            Event::Note(note) => {
+                if let Some(grace) = &mut note.grace {
+                    for pitch in &mut grace.pitches {
+                        spell(pitch, alterations, carried)?;
+                    }
+                }
+                continue_or_spell(&mut note.pitch, alterations, carried, &ties.open)?;
+                ties.open.clear();
+                ties.last = vec![note.pitch];
+            }
+            Event::Chord(pitches, ..) => {
+                for pitch in pitches.iter_mut() {
+                    continue_or_spell(pitch, alterations, carried, &ties.open)?;
+                }
+                ties.open.clear();
+                ties.last = pitches.clone();
+            }
+            Event::Tuplet(tuplet) => spell_events(&mut tuplet.events, alterations, carried, ties)?,
+            Event::Tie => ties.open = std::mem::take(&mut ties.last),
+            Event::Rest(..) | Event::Drum(_) | Event::MotifCall(_) => {
+                ties.open.clear();
+                ties.last.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// A pitch tied from one on the same step and octave keeps that note's accidental,
+/// even across a barline; any other pitch is spelled as usual.
+fn continue_or_spell(
+    pitch: &mut Pitch,
+    alterations: &[i32; 7],
+    carried: &mut HashMap<(char, i32), Accidental>,
+    open: &[Pitch],
+) -> Result<()> {
+    if pitch.accidental.is_none()
+        && let Some(tied) = open.iter().find(|tied| (tied.step, tied.octave) == (pitch.step, pitch.octave))
+    {
+        pitch.accidental = tied.accidental;
+        return Ok(());
+    }
+    spell(pitch, alterations, carried)
+}
+
+fn spell(pitch: &mut Pitch, alterations: &[i32; 7], carried: &mut HashMap<(char, i32), Accidental>) -> Result<()> {
+    let position = (pitch.step, pitch.octave);
+    match pitch.accidental {
+        Some(accidental) => {
+            carried.insert(position, accidental);
+        }
+        None => {
+            pitch.accidental = match carried.get(&position) {
+                Some(&accidental) => Some(accidental),
+                None => match alterations[step_index(pitch.step)?] {
+                    0 => None,
+                    1 => Some(Accidental::Sharp),
+                    -1 => Some(Accidental::Flat),
+                    _ => return Err(anyhow!("Key-relative accidentals need a key with at most seven sharps or flats")),
+                },
+            };
+        }
+    }
+    Ok(())
+}
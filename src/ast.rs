@@ -1,16 +1,135 @@
+use std::collections::BTreeMap;
+
+/// Byte range of a node in the parsed source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        // Rules can end with implicit whitespace consumed before an absent optional item
+        Span::new(span.start(), span.start() + span.as_str().trim_end().len())
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Score {
     pub headers: Vec<Header>,
+    pub motifs: BTreeMap<String, Vec<Event>>, // Motif name -> body
     pub parts: Vec<Part>,
 }
 
+impl Score {
+    /// The same tree with every span reset, for comparing scores regardless of layout.
+    pub fn without_spans(mut self) -> Score {
+        for events in self.motifs.values_mut() {
+            clear_event_spans(events);
+        }
+        for part in &mut self.parts {
+            part.span = Span::default();
+            clear_block_spans(&mut part.content);
+        }
+        self
+    }
+}
+
+fn clear_block_spans(blocks: &mut [MeasureBlock]) {
+    for block in blocks {
+        match block {
+            MeasureBlock::Measure(measure) => {
+                measure.span = Span::default();
+                clear_event_spans(&mut measure.events);
+                for voice in &mut measure.voices {
+                    voice.span = Span::default();
+                    clear_event_spans(&mut voice.events);
+                }
+            }
+            MeasureBlock::Repeat(repeat) => {
+                clear_block_spans(&mut repeat.body);
+                for ending in &mut repeat.endings {
+                    clear_block_spans(&mut ending.body);
+                }
+            }
+            MeasureBlock::ContextChange(ContextChange::TempoRamp(ramp)) => ramp.span = Span::default(),
+            MeasureBlock::ContextChange(_) | MeasureBlock::Navigation(_) => {}
+        }
+    }
+}
+
+fn clear_event_spans(events: &mut [Event]) {
+    for event in events {
+        match event {
+            Event::Note(note) => note.span = Span::default(),
+            Event::Drum(hit) => hit.span = Span::default(),
+            Event::MotifCall(call) => call.span = Span::default(),
+            Event::Controller(controller) => controller.span = Span::default(),
+            Event::Bend(bend) => bend.span = Span::default(),
+            Event::Tuplet(tuplet) => clear_event_spans(&mut tuplet.events),
+            _ => {}
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Header {
     Title(String),
-    Tempo(u32),
+    Copyright(String),
+    Text(String), // Free-form note stored in the MIDI file
+    Tempo(Tempo),
     TimeSignature(u32, u32),
-    KeySignature(String, String),
+    KeySignature(Key),
     Swing(Option<(BaseDuration, f64)>),
+    Articulation(ArticulationSetting),
+    Ppq(u32), // MIDI resolution in ticks per quarter note
+    BendRange(u8), // Pitch bend range in semitones
+    Accidentals(AccidentalMode),
+}
+
+/// How pitches without an accidental are read.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum AccidentalMode {
+    #[default]
+    Explicit, // `F4` is always F natural
+    Key, // `F4` takes the key's sharp or flat, and accidentals last to the end of the measure
+}
+
+/// A key signature, e.g. `Key: Eb "Major"`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Key {
+    pub step: char,
+    pub accidental: Option<Accidental>, // Sharp or flat
+    pub mode: Mode,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    Major, // Also written "Ionian"
+    Minor, // Also written "Aeolian"
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    Octatonic, // Scales below have no key signature
+    WholeTone,
+    Chromatic,
+}
+
+/// Score-wide override of how an articulation is performed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArticulationSetting {
+    pub name: String, // "staccato", "accent", ...
+    pub gate: Option<f64>, // Fraction of the written duration that sounds
+    pub velocity: Option<i32>, // Added to the current dynamic's velocity
+    pub hold: Option<f64>, // Fermata length multiplier
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -18,24 +137,102 @@ pub struct Part {
     pub name: String,
     pub instrument: String,
     pub content: Vec<MeasureBlock>,
+    pub span: Span, // The part header
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum MeasureBlock {
     Measure(Measure),
     ContextChange(ContextChange),
+    Repeat(Repeat),
+    Navigation(Navigation),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Repeat {
+    pub times: u32, // Total number of passes through the body
+    pub body: Vec<MeasureBlock>,
+    pub endings: Vec<Ending>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ending {
+    pub numbers: Vec<u32>, // Passes on which this ending is played
+    pub body: Vec<MeasureBlock>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Navigation {
+    Segno,
+    Coda,
+    ToCoda,
+    Fine,
+    DaCapo(JumpEnd),
+    DalSegno(JumpEnd),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum JumpEnd {
+    Plain, // D.C. / D.S. - play to Fine if present, otherwise to the jump
+    AlFine,
+    AlCoda,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Measure {
+    pub events: Vec<Event>, // Voice 1
+    pub voices: Vec<Voice>, // Further voices (`V2:`, ...), each filling the whole measure
+    pub kind: MeasureKind,
+    pub span: Span,
+}
+
+/// An additional voice in a measure, played alongside voice 1 on the same track.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Voice {
+    pub number: u8,
     pub events: Vec<Event>,
+    pub span: Span,
+}
+
+/// How a measure's length is checked against the time signature.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum MeasureKind {
+    #[default]
+    Regular, // Must fill the bar
+    Pickup, // Anacrusis: shorter than a full bar, only as the first measure
+    Irregular, // Intentionally any length
+}
+
+/// A metronome mark: `Tempo: 92.5`, or `Tempo: 60 q.` for dotted-quarter beats.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Tempo {
+    pub bpm: f64,
+    pub beat: Option<Duration>, // Defaults to a quarter note
+}
+
+/// A gradual tempo change, e.g. `Rit: 60 over 8 exp`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TempoRamp {
+    pub target: Tempo,
+    pub beats: u32, // Length of the change, in the target's beats
+    pub curve: TempoCurve,
+    pub accelerando: bool, // Written `Accel:` rather than `Rit:`
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum TempoCurve {
+    #[default]
+    Linear,
+    Exponential, // Changes by the same ratio in each beat
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ContextChange {
-    Tempo(u32),
+    Tempo(Tempo),
+    TempoRamp(TempoRamp), // `Accel:` or `Rit:`
     TimeSignature(u32, u32),
-    KeySignature(String, String),
+    KeySignature(Key),
     Swing(Option<(BaseDuration, f64)>),
 }
 
@@ -43,10 +240,58 @@ pub enum ContextChange {
 pub enum Event {
     Note(Note),
     Chord(Vec<Pitch>, Option<Duration>, Option<String>, Option<String>),
-    Rest(Option<Duration>),
+    Rest(Option<Duration>, bool), // True when held by a fermata
+    Drum(DrumHit),
     Tie,
     Tuplet(Tuplet),
     Dynamic(String),
+    Crescendo, // Gradual change up to the next dynamic mark
+    Diminuendo,
+    MotifCall(MotifCall),
+    Pedal(bool), // `Ped` presses the sustain pedal, `*` releases it
+    Controller(Controller),
+    Slide(Slide), // Between two notes: `C4 h gliss G4 h`
+    Bend(Bend),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Slide {
+    Glissando,  // `gliss`: slides through the second half of the first note
+    Portamento, // `port`: slides just before the second note
+}
+
+/// A pitch bend in semitones, `Bend(-0.5)`, or a bend curve, `Bend(0 -> 2 over h)`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Bend {
+    pub semitones: f64,
+    pub ramp: Option<(f64, Duration)>, // Target and how long it takes to reach it
+    pub span: Span,
+}
+
+/// A MIDI control change, `CC(11, 90)`, or a ramp to a new value, `CC(11, 40 -> 127 over w)`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Controller {
+    pub number: u8,
+    pub value: u8,
+    pub ramp: Option<(u8, Duration)>, // Target value and how long it takes to reach it
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MotifCall {
+    pub name: String,
+    pub transforms: Vec<MotifTransform>, // Applied in order
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MotifTransform {
+    Transpose(i32),         // Semitones
+    TransposeDiatonic(i32), // Scale steps in the current key
+    Inversion,              // Mirrored around the first pitch
+    Retrograde,
+    Augmentation(u32),
+    Diminution(u32),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -54,7 +299,36 @@ pub struct Note {
     pub pitch: Pitch,
     pub duration: Option<Duration>,
     pub dynamic: Option<String>,
+    pub articulation: Option<String>, // One or more symbols, e.g. ".>"
+    pub grace: Option<GraceNotes>, // Written before the note: `{D5} C5 q`
+    pub ornament: Option<Ornament>, // Written after the note: `C5 q tr`
+    pub span: Span,
+}
+
+/// Grace notes leading into a note, e.g. `{D5}` or `{/B4 C#5}`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GraceNotes {
+    pub pitches: Vec<Pitch>,
+    pub acciaccatura: bool, // Crushed in before the beat instead of leaning on it
+}
+
+/// An ornament realized from the key's scale around the written note.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Ornament {
+    Trill,           // `tr`: alternates with the upper neighbour
+    Mordent,         // `mord`: note, lower neighbour, note
+    InvertedMordent, // `prall`: note, upper neighbour, note
+    Turn,            // `turn`: upper, note, lower, note
+}
+
+/// One or more percussion instruments struck together, e.g. `kick` or `[kick hh]`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DrumHit {
+    pub drums: Vec<String>, // Names from the General MIDI drum map
+    pub duration: Option<Duration>,
+    pub dynamic: Option<String>,
     pub articulation: Option<String>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -68,6 +342,11 @@ pub struct Pitch {
 pub enum Accidental {
     Sharp,
     Flat,
+    Natural, // `n`, cancels the key signature or an earlier accidental
+    QuarterSharp, // `+`, a quarter tone above
+    QuarterFlat, // `d`, a quarter tone below
+    ThreeQuarterSharp, // `#+`
+    ThreeQuarterFlat, // `db`
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -80,8 +359,8 @@ pub struct Tuplet {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Duration {
     Base(BaseDuration, u8), // u8 is number of dots
-    Subdivision(BaseDuration, u32),
-    Fraction(u32, u32),
+    Subdivision(BaseDuration, u32), // `q/3`: the base value split into n equal parts
+    Fraction(u32, u32), // `5/16`: a fraction of a whole note
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -91,4 +370,6 @@ pub enum BaseDuration {
     Quarter,
     Eighth,
     Sixteenth,
+    ThirtySecond,
+    SixtyFourth,
 }
@@ -0,0 +1,5 @@
+//! Language server for Melos scores, speaking LSP over stdin and stdout.
+
+fn main() -> anyhow::Result<()> {
+    melos::lsp::run()
+}
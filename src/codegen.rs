@@ -1,28 +1,59 @@
 use crate::ir::{IrScore, IrEventKind};
+use crate::keys::midi_key_signature;
 use anyhow::Result;
 use midly::{Smf, Header, Format, Timing, TrackEvent, TrackEventKind, MidiMessage, MetaMessage};
 use midly::num::{u4, u7, u15, u24, u28};
 
-pub fn generate(score: &IrScore) -> Result<Smf<'static>> {
+pub fn generate(score: &IrScore) -> Result<Smf<'_>> {
     let header = Header {
         format: Format::Parallel, // Type 1
         timing: Timing::Metrical(u15::new(score.ppq as u16)),
     };
 
     let mut tracks = Vec::new();
+    let multi_port = score.tracks.iter().any(|t| t.port != 0);
 
     for ir_track in &score.tracks {
         let mut events = Vec::new();
 
+        // Name part tracks after the part and its instrument
+        if let Some(instrument) = &ir_track.instrument {
+            events.push(AbsEvent {
+                time: 0,
+                kind: TrackEventKind::Meta(MetaMessage::TrackName(ir_track.name.as_bytes())),
+            });
+            events.push(AbsEvent {
+                time: 0,
+                kind: TrackEventKind::Meta(MetaMessage::InstrumentName(instrument.as_bytes())),
+            });
+        }
+
+        // Tell the player which output each track's channels belong to
+        if multi_port {
+            events.push(AbsEvent {
+                time: 0,
+                kind: TrackEventKind::Meta(MetaMessage::MidiPort(u7::new(ir_track.port))),
+            });
+        }
+
+        // Quarter tones play on member channels, which follow the track's own channel
+        // in everything but pitch bend
+        let channel_of = |member: u8| match member {
+            0 => ir_track.channel,
+            n => ir_track.member_channels[n as usize - 1],
+        };
+        let channels: Vec<u8> = std::iter::once(ir_track.channel).chain(ir_track.member_channels.iter().copied()).collect();
+
         // 1. Expand IR events into absolute MIDI events
         for event in &ir_track.events {
             match &event.kind {
-                IrEventKind::Note { pitch, velocity, duration } => {
+                IrEventKind::Note { pitch, velocity, duration, member, .. } => {
+                    let channel = channel_of(*member);
                     // Note On
                     events.push(AbsEvent {
                         time: event.time,
                         kind: TrackEventKind::Midi {
-                            channel: u4::new(ir_track.channel),
+                            channel: u4::new(channel),
                             message: MidiMessage::NoteOn {
                                 key: u7::new(*pitch),
                                 vel: u7::new(*velocity),
@@ -33,7 +64,7 @@ pub fn generate(score: &IrScore) -> Result<Smf<'static>> {
                     events.push(AbsEvent {
                         time: event.time + duration,
                         kind: TrackEventKind::Midi {
-                            channel: u4::new(ir_track.channel),
+                            channel: u4::new(channel),
                             message: MidiMessage::NoteOff {
                                 key: u7::new(*pitch),
                                 vel: u7::new(0),
@@ -42,23 +73,72 @@ pub fn generate(score: &IrScore) -> Result<Smf<'static>> {
                     });
                 }
                 IrEventKind::Tempo(bpm) => {
-                     let mpq = 60_000_000 / bpm;
+                     // The tempo meta event stores microseconds per quarter note in 24 bits
+                     let mpq = (60_000_000.0 / bpm).clamp(1.0, 0xFF_FFFF as f64) as u32;
                      events.push(AbsEvent {
                          time: event.time,
                          kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(mpq))),
                      });
                 }
                 IrEventKind::ProgramChange(program) => {
+                    for &channel in &channels {
+                        events.push(AbsEvent {
+                            time: event.time,
+                            kind: TrackEventKind::Midi {
+                                channel: u4::new(channel),
+                                message: MidiMessage::ProgramChange {
+                                    program: u7::new(*program),
+                                },
+                            },
+                        });
+                    }
+                }
+                IrEventKind::ControlChange { controller, value } => {
+                    for &channel in &channels {
+                        events.push(AbsEvent {
+                            time: event.time,
+                            kind: TrackEventKind::Midi {
+                                channel: u4::new(channel),
+                                message: MidiMessage::Controller {
+                                    controller: u7::new(*controller),
+                                    value: u7::new(*value),
+                                },
+                            },
+                        });
+                    }
+                }
+                IrEventKind::PitchBend { value, member } => {
                     events.push(AbsEvent {
                         time: event.time,
                         kind: TrackEventKind::Midi {
-                            channel: u4::new(ir_track.channel),
-                            message: MidiMessage::ProgramChange {
-                                program: u7::new(*program),
+                            channel: u4::new(channel_of(*member)),
+                            message: MidiMessage::PitchBend {
+                                bend: midly::PitchBend::from_int(*value),
                             },
                         },
                     });
                 }
+                IrEventKind::TrackName(name) => events.push(AbsEvent {
+                    time: event.time,
+                    kind: TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+                }),
+                IrEventKind::Copyright(notice) => events.push(AbsEvent {
+                    time: event.time,
+                    kind: TrackEventKind::Meta(MetaMessage::Copyright(notice.as_bytes())),
+                }),
+                IrEventKind::Text(text) => events.push(AbsEvent {
+                    time: event.time,
+                    kind: TrackEventKind::Meta(MetaMessage::Text(text.as_bytes())),
+                }),
+                IrEventKind::KeySignature(key) => {
+                    // Scales without a signature (e.g. octatonic) are left out
+                    if let Some((sharps, minor)) = midi_key_signature(key) {
+                        events.push(AbsEvent {
+                            time: event.time,
+                            kind: TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor)),
+                        });
+                    }
+                }
                 IrEventKind::TimeSignature(num, den) => {
                     // MIDI Time Signature:
                     // nn: numerator
@@ -83,8 +163,9 @@ pub fn generate(score: &IrScore) -> Result<Smf<'static>> {
             }
         }
 
-        // 2. Sort events by time
-        events.sort_by_key(|e| e.time);
+        // 2. Sort events by time. Notes ending at a tick are released before anything
+        // else happens there, so a pedal pressed on that tick does not catch them.
+        events.sort_by_key(|e| (e.time, !matches!(e.kind, TrackEventKind::Midi { message: MidiMessage::NoteOff { .. }, .. })));
 
         // 3. Convert to Delta
         let mut track = Vec::new();
@@ -0,0 +1,163 @@
+use crate::ast::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while compiling, pointing at the source it came from.
+///
+/// Errors are returned inside `anyhow::Error` and can be recovered with
+/// `err.downcast_ref::<Diagnostic>()`; warnings are collected alongside the result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>, // Byte range in the parsed source
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Error, message: message.into(), span: Some(span), hint: None }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, message: message.into(), span: Some(span), hint: None }
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Attach `span` to an error that does not carry a location yet.
+    pub fn locate(err: anyhow::Error, span: Span) -> anyhow::Error {
+        if err.is::<Diagnostic>() {
+            return err;
+        }
+        Diagnostic::error(format!("{:#}", err), span).into()
+    }
+
+    /// Convert a pest parse error, keeping its position.
+    pub fn from_pest<R: pest::RuleType>(err: &pest::error::Error<R>) -> Self {
+        let span = match err.location {
+            pest::error::InputLocation::Pos(pos) => Span::new(pos, pos),
+            pest::error::InputLocation::Span((start, end)) => Span::new(start, end),
+        };
+        Diagnostic::error(err.variant.message().to_string(), span)
+    }
+
+    /// Render in the style of rustc, with the offending line and a caret underline.
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}\n", label, self.message);
+
+        if let Some(span) = self.span
+            && let Some(location) = sources.locate(span.start)
+        {
+            let line_text = sources.line(span.start).unwrap_or("");
+            let number = location.line.to_string();
+            let gutter = " ".repeat(number.len());
+
+            // Underline to the end of the span, or at least one column
+            let remaining = line_text.chars().count().saturating_sub(location.column - 1);
+            let width = sources.source_text(span).map_or(1, |t| t.lines().next().unwrap_or("").chars().count());
+            let width = width.clamp(1, remaining.max(1));
+
+            out.push_str(&format!("{}--> {}:{}:{}\n", gutter, location.file, location.line, location.column));
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", number, line_text));
+            out.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(location.column - 1), "^".repeat(width)));
+            if let Some(hint) = &self.hint {
+                out.push_str(&format!("{} = help: {}\n", gutter, hint));
+            }
+        } else if let Some(hint) = &self.hint {
+            out.push_str(&format!("  = help: {}\n", hint));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// A position in one of the original source files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize, // 1-based
+    pub column: usize, // 1-based, in characters
+}
+
+/// One file's contribution to the text that was parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub start: usize, // Byte offset of the file in the combined source
+    pub text: String,
+}
+
+/// Maps byte offsets in the parsed (possibly concatenated) source back to files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn single(name: impl Into<String>, text: impl Into<String>) -> Self {
+        let mut map = SourceMap::default();
+        map.add(name, 0, text);
+        map
+    }
+
+    /// Register a file whose text starts at byte `start` of the combined source.
+    pub fn add(&mut self, name: impl Into<String>, start: usize, text: impl Into<String>) {
+        self.files.push(SourceFile { name: name.into(), start, text: text.into() });
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    fn file_at(&self, offset: usize) -> Option<&SourceFile> {
+        self.files.iter().rev().find(|f| f.start <= offset && offset <= f.start + f.text.len())
+    }
+
+    pub fn locate(&self, offset: usize) -> Option<Location> {
+        let file = self.file_at(offset)?;
+        let local = offset - file.start;
+        let before = file.text.get(..local)?;
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some(Location {
+            file: file.name.clone(),
+            line,
+            column: before[line_start..].chars().count() + 1,
+        })
+    }
+
+    /// The full line of source containing `offset`.
+    fn line(&self, offset: usize) -> Option<&str> {
+        let file = self.file_at(offset)?;
+        let local = offset - file.start;
+        let line_start = file.text.get(..local)?.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = file.text[local..].find('\n').map_or(file.text.len(), |i| local + i);
+        Some(file.text[line_start..line_end].trim_end_matches('\r'))
+    }
+
+    fn source_text(&self, span: Span) -> Option<&str> {
+        let file = self.file_at(span.start)?;
+        file.text.get(span.start - file.start..span.end.checked_sub(file.start)?)
+    }
+}
@@ -0,0 +1,64 @@
+/// Names of the General MIDI percussion keys from 35 up, as played on channel 10.
+/// The first name of each key is the one written back when importing.
+const DRUM_NAMES: [&[&str]; 47] = [
+    &["kick2", "acoustic_bass_drum"], // 35
+    &["kick", "bd", "bass_drum"], // 36
+    &["rim", "sidestick"], // 37
+    &["snare", "sd"], // 38
+    &["clap"], // 39
+    &["snare2", "electric_snare"], // 40
+    &["floortom2"], // 41
+    &["hh", "hihat"], // 42
+    &["floortom"], // 43
+    &["hhp", "hh_pedal"], // 44
+    &["tom4"], // 45
+    &["hho", "hh_open"], // 46
+    &["tom3"], // 47
+    &["tom2"], // 48
+    &["crash"], // 49
+    &["tom1"], // 50
+    &["ride"], // 51
+    &["china"], // 52
+    &["ridebell", "ride_bell"], // 53
+    &["tambourine"], // 54
+    &["splash"], // 55
+    &["cowbell"], // 56
+    &["crash2"], // 57
+    &["vibraslap"], // 58
+    &["ride2"], // 59
+    &["bongo_hi"], // 60
+    &["bongo_lo"], // 61
+    &["conga_mute"], // 62
+    &["conga_hi"], // 63
+    &["conga_lo"], // 64
+    &["timbale_hi"], // 65
+    &["timbale_lo"], // 66
+    &["agogo_hi"], // 67
+    &["agogo_lo"], // 68
+    &["cabasa"], // 69
+    &["maracas"], // 70
+    &["whistle_short"], // 71
+    &["whistle_long"], // 72
+    &["guiro_short"], // 73
+    &["guiro_long"], // 74
+    &["claves"], // 75
+    &["woodblock_hi"], // 76
+    &["woodblock_lo"], // 77
+    &["cuica_mute"], // 78
+    &["cuica_open"], // 79
+    &["triangle_mute"], // 80
+    &["triangle"], // 81
+];
+const FIRST_DRUM: u8 = 35;
+
+/// General MIDI percussion key for a drum name, as played on channel 10.
+pub fn get_drum_note(name: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    let index = DRUM_NAMES.iter().position(|names| names.contains(&name.as_str()))?;
+    Some(FIRST_DRUM + index as u8)
+}
+
+/// The drum name for a General MIDI percussion key, if it has one.
+pub fn get_drum_name(key: u8) -> Option<&'static str> {
+    DRUM_NAMES.get(key.checked_sub(FIRST_DRUM)? as usize).map(|names| names[0])
+}
@@ -0,0 +1,706 @@
+use crate::ast::*;
+use crate::diagnostics::SourceMap;
+use crate::grammar::{MusicParser, Rule};
+use crate::parser::parse;
+use anyhow::Result;
+use pest::iterators::Pair;
+use pest::Parser;
+
+const INDENT: usize = 4;
+/// Opens the last measure of a repeat, which shares the barline before it
+const TAIL: &str = "  ";
+
+/// How formatted source is laid out.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub measures_per_line: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { measures_per_line: 1 }
+    }
+}
+
+/// Print a score as canonical Melos source.
+pub fn format_score(score: &Score, options: &FormatOptions) -> String {
+    let mut printer = Printer::default();
+    printer.score(score, None);
+    layout(&printer.items, &[], "", options).into_iter().map(|(_, line)| line + "\n").collect()
+}
+
+/// Reformat Melos source, keeping its comments and the blank lines that separate sections.
+pub fn format_source(source: &str, options: &FormatOptions) -> Result<String> {
+    Ok(format_files(source, &SourceMap::single("", source), options)?.remove(0))
+}
+
+/// Reformat source concatenated from several files, as read by `loader::load_source`,
+/// returning the new text of each file in `sources`.
+pub fn format_files(source: &str, sources: &SourceMap, options: &FormatOptions) -> Result<Vec<String>> {
+    let score = parse(source)?;
+    let pair = MusicParser::parse(Rule::score, source)?.next();
+    let mut printer = Printer::default();
+    printer.score(&score, pair);
+    let lines = layout(&printer.items, &comments(source), source, options);
+
+    let files = sources.files();
+    Ok(files.iter().enumerate().map(|(i, file)| {
+        let end = files.get(i + 1).map_or(usize::MAX, |next| next.start);
+        let mut text = String::new();
+        for (_, line) in lines.iter().filter(|(offset, _)| file.start <= *offset && *offset < end) {
+            // A file never starts with a blank line
+            if !(text.is_empty() && line.is_empty()) {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text
+    }).collect())
+}
+
+/// A line-level piece of the score, with where it was written.
+struct Item {
+    indent: usize,
+    piece: Piece,
+    span: Span, // Empty when formatting without source
+    comment_indent: usize, // For comments written just before it
+    blank_before: bool, // Always set off by a blank line
+}
+
+enum Piece {
+    Text(String),
+    Measure(Cell),
+}
+
+/// One measure as laid out in a line of measures.
+#[derive(Clone)]
+struct Cell {
+    open: &'static str, // "| ", "|: ", `TAIL`, or nothing after another measure in the line
+    kind: Option<&'static str>, // `Pickup` or `Irregular`
+    content: String,
+    close: String, // "|", or ":|" ending a repeat
+    alone: bool, // Must start its own line
+}
+
+#[derive(Default)]
+struct Printer {
+    items: Vec<Item>,
+}
+
+impl Printer {
+    fn push(&mut self, indent: usize, piece: Piece, span: Span) {
+        self.items.push(Item { indent, piece, span, comment_indent: indent, blank_before: false });
+    }
+
+    fn score(&mut self, score: &Score, pair: Option<Pair<Rule>>) {
+        let Some(pair) = pair else {
+            for header in &score.headers {
+                self.push(0, Piece::Text(header_text(header)), Span::default());
+            }
+            for (name, body) in &score.motifs {
+                self.motif(name, body, Span::default());
+            }
+            for part in &score.parts {
+                self.part(part, None);
+            }
+            return;
+        };
+        let (mut headers, mut parts) = (score.headers.iter(), score.parts.iter());
+        for inner in pair.into_inner() {
+            let span = Span::from(inner.as_span());
+            match inner.as_rule() {
+                Rule::header => {
+                    if let Some(header) = headers.next() {
+                        self.push(0, Piece::Text(header_text(header)), span);
+                    }
+                }
+                Rule::motif => {
+                    let name = inner.into_inner().next().map(|name| name.as_str()).unwrap_or_default();
+                    if let Some(body) = score.motifs.get(name) {
+                        self.motif(name, body, span);
+                    }
+                }
+                Rule::part => {
+                    if let Some(part) = parts.next() {
+                        self.part(part, Some(inner));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Consecutive motifs stay together, set off from what comes before them.
+    fn motif(&mut self, name: &str, body: &[Event], span: Span) {
+        let grouped = self.items.last().is_some_and(|item| matches!(&item.piece, Piece::Text(text) if text.starts_with("Motif:")));
+        self.push(0, Piece::Text(format!("Motif: {} {{ {} }}", name, events(body))), span);
+        self.items.last_mut().unwrap().blank_before = !grouped;
+    }
+
+    fn part(&mut self, part: &Part, pair: Option<Pair<Rule>>) {
+        let header = format!("Part: {} Instrument: {} {{", name(&part.name), name(&part.instrument));
+        self.push(0, Piece::Text(header), part.span);
+        self.items.last_mut().unwrap().blank_before = true;
+        let end = pair.as_ref().map(|pair| pair.as_span().end());
+        let blocks = pair.map(|pair| {
+            pair.into_inner()
+                .filter(|inner| inner.as_rule() == Rule::part_content)
+                .flat_map(|content| content.into_inner())
+                .flat_map(|block| block.into_inner())
+                .collect()
+        });
+        self.blocks(&part.content, blocks, INDENT);
+        self.close(0, end);
+    }
+
+    /// The closing brace of a part or ending whose source ends at `end`.
+    fn close(&mut self, indent: usize, end: Option<usize>) {
+        let span = end.map_or_else(Span::default, |end| Span::new(end - 1, end));
+        self.push(indent, Piece::Text("}".to_string()), span);
+        self.items.last_mut().unwrap().comment_indent = indent + INDENT;
+    }
+
+    fn blocks(&mut self, blocks: &[MeasureBlock], pairs: Option<Vec<Pair<Rule>>>, indent: usize) {
+        let mut pairs = pairs.map(|pairs| pairs.into_iter());
+        for block in blocks {
+            let pair = pairs.as_mut().and_then(|pairs| pairs.next());
+            let span = pair.as_ref().map_or_else(Span::default, |pair| Span::from(pair.as_span()));
+            match block {
+                MeasureBlock::Measure(measure) => {
+                    self.push(indent, Piece::Measure(cell(measure, "| ", "|".to_string())), span);
+                }
+                MeasureBlock::ContextChange(change) => self.push(indent, Piece::Text(context_change(change)), span),
+                MeasureBlock::Navigation(navigation) => {
+                    self.push(indent, Piece::Text(navigation_text(*navigation).to_string()), span);
+                }
+                MeasureBlock::Repeat(repeat) => self.repeat(repeat, pair, indent),
+            }
+        }
+    }
+
+    fn repeat(&mut self, repeat: &Repeat, pair: Option<Pair<Rule>>, indent: usize) {
+        let mut body_pairs = Vec::new();
+        let mut ending_pairs = Vec::new();
+        let start = pair.as_ref().map(|pair| pair.as_span().start());
+        let end = pair.as_ref().map(|pair| pair.as_span().end());
+        if let Some(pair) = pair {
+            for inner in pair.into_inner() {
+                match inner.as_rule() {
+                    Rule::repeat_body => body_pairs.extend(inner.into_inner()),
+                    Rule::repeat_tail => body_pairs.push(inner),
+                    Rule::ending => ending_pairs.push(inner),
+                    _ => {}
+                }
+            }
+        }
+        let has_source = start.is_some();
+        let mut body_pairs = body_pairs.into_iter();
+
+        // A plain measure takes the `|:` and, without endings, the `:|` into its own line
+        let foldable = |block: Option<&MeasureBlock>| match block {
+            Some(MeasureBlock::Measure(measure)) => {
+                measure.kind == MeasureKind::Regular && !(measure.events.is_empty() && measure.voices.is_empty())
+            }
+            _ => false,
+        };
+        let times = match repeat.times {
+            2 => String::new(),
+            n => format!(" x{}", n),
+        };
+        let opened = foldable(repeat.body.first());
+        let tail = repeat.endings.is_empty() && foldable(repeat.body.last());
+        if !opened {
+            let span = start.map_or_else(Span::default, |start| Span::new(start, start + 2));
+            self.push(indent, Piece::Text("|:".to_string()), span);
+        }
+        for (i, block) in repeat.body.iter().enumerate() {
+            let pair = if has_source { body_pairs.next() } else { None };
+            let last = tail && i + 1 == repeat.body.len();
+            match block {
+                MeasureBlock::Measure(measure) if (i == 0 && opened) || last => {
+                    let open = if i == 0 && opened { "|: " } else { TAIL };
+                    let close = if last { format!(":|{}", times) } else { "|".to_string() };
+                    let mut span = pair.as_ref().map_or_else(Span::default, |pair| Span::from(pair.as_span()));
+                    if i == 0 && let Some(start) = start {
+                        span.start = start;
+                    }
+                    self.push(indent, Piece::Measure(cell(measure, open, close)), span);
+                }
+                _ => self.blocks(std::slice::from_ref(block), pair.map(|pair| vec![pair]), indent),
+            }
+        }
+        if repeat.endings.is_empty() && !tail {
+            let span = end.map_or_else(Span::default, |end| Span::new(end, end));
+            self.push(indent, Piece::Text(format!(":|{}", times)), span);
+        }
+
+        let mut ending_pairs = ending_pairs.into_iter();
+        for ending in &repeat.endings {
+            let pair = ending_pairs.next();
+            let span = pair.as_ref().map_or_else(Span::default, |pair| {
+                Span::new(pair.as_span().start(), pair.as_span().start())
+            });
+            let end = pair.as_ref().map(|pair| pair.as_span().end());
+            let numbers: Vec<String> = ending.numbers.iter().map(|n| n.to_string()).collect();
+            self.push(indent, Piece::Text(format!("Ending({}) {{", numbers.join(", "))), span);
+            let blocks = pair.map(|pair| pair.into_inner().filter(|inner| inner.as_rule() != Rule::integer).collect());
+            self.blocks(&ending.body, blocks, indent + INDENT);
+            self.close(indent, end);
+        }
+    }
+}
+
+fn cell(measure: &Measure, open: &'static str, close: String) -> Cell {
+    let mut content = String::new();
+    if !measure.voices.is_empty() && !measure.events.is_empty() {
+        content = format!("V1: {}", events(&measure.events));
+    } else if measure.voices.is_empty() {
+        content = events(&measure.events);
+    }
+    for voice in &measure.voices {
+        if !content.is_empty() {
+            content.push(' ');
+        }
+        content.push_str(format!("V{}: {}", voice.number, events(&voice.events)).trim_end());
+    }
+    let kind = match measure.kind {
+        MeasureKind::Regular => None,
+        MeasureKind::Pickup => Some("Pickup"),
+        MeasureKind::Irregular => Some("Irregular"),
+    };
+    let alone = kind.is_some() || content.is_empty();
+    Cell { open, kind, content, close, alone }
+}
+
+/// A comment as written, `// ...` or `= ...` to the end of its line.
+struct Comment {
+    span: Span,
+    text: String,
+    trailing: bool, // Written after code on the same line
+}
+
+fn comments(source: &str) -> Vec<Comment> {
+    let bytes = source.as_bytes();
+    let mut comments = Vec::new();
+    let mut in_string = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => in_string = !in_string,
+            b'/' | b'=' if !in_string && (bytes[i] == b'=' || bytes.get(i + 1) == Some(&b'/')) => {
+                let end = source[i..].find('\n').map_or(source.len(), |n| i + n);
+                let line_start = source[..i].rfind('\n').map_or(0, |n| n + 1);
+                comments.push(Comment {
+                    span: Span::new(i, end),
+                    text: source[i..end].trim_end().to_string(),
+                    trailing: !source[line_start..i].trim().is_empty(),
+                });
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    comments
+}
+
+/// A line of output before barlines are aligned.
+enum Line {
+    Blank,
+    Text(usize, String),
+    Measures(usize, Vec<Cell>, Option<String>), // Indent, measures, trailing comment
+}
+
+/// Lay items out in lines with the comments woven back in, each line paired with the
+/// source offset it came from.
+fn layout(items: &[Item], comments: &[Comment], source: &str, options: &FormatOptions) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, Line)> = Vec::new();
+    let all_comments = comments;
+    let mut comments = comments.iter().peekable();
+    let mut previous_end = 0;
+    // Whether the source between two offsets has an empty line
+    let gap = |from: usize, to: usize| {
+        let lines: Vec<&str> = source.get(from..to).unwrap_or_default().split('\n').collect();
+        lines.len() > 2 && lines[1..lines.len() - 1].iter().any(|line| line.trim().is_empty())
+    };
+
+    for item in items {
+        let mut first = true;
+        while let Some(comment) = comments.next_if(|comment| comment.span.start < item.span.start) {
+            if comment.trailing {
+                attach(&mut lines, &comment.text);
+            } else {
+                if (first && item.blank_before) || gap(previous_end, comment.span.start) {
+                    blank(&mut lines, comment.span.start);
+                }
+                lines.push((comment.span.start, Line::Text(item.comment_indent, comment.text.clone())));
+                first = false;
+            }
+            previous_end = comment.span.end;
+        }
+        let closing = matches!(&item.piece, Piece::Text(text) if text == "}");
+        if !closing && ((first && item.blank_before) || gap(previous_end, item.span.start)) {
+            blank(&mut lines, item.span.start);
+        }
+        match &item.piece {
+            Piece::Text(text) => lines.push((item.span.start, Line::Text(item.indent, text.clone()))),
+            Piece::Measure(cell) => match lines.last_mut() {
+                Some((_, Line::Measures(indent, cells, None)))
+                    if *indent == item.indent
+                        && cells.len() < options.measures_per_line
+                        && cells.last().is_some_and(|last| last.close == "|")
+                        && (cell.open == "| " || cell.open == TAIL)
+                        && !cell.alone =>
+                {
+                    cells.push(Cell { open: "", ..cell.clone() });
+                }
+                _ => lines.push((item.span.start, Line::Measures(item.indent, vec![cell.clone()], None))),
+            },
+        }
+        previous_end = code_end(item.span.end, source, all_comments);
+    }
+    for comment in comments {
+        if comment.trailing {
+            attach(&mut lines, &comment.text);
+        } else {
+            if gap(previous_end, comment.span.start) {
+                blank(&mut lines, comment.span.start);
+            }
+            lines.push((comment.span.start, Line::Text(0, comment.text.clone())));
+        }
+        previous_end = comment.span.end;
+    }
+    render(lines)
+}
+
+/// Where the code before `end` stops. The spans of rules ending in a lookahead run
+/// on over the whitespace and comments after them.
+fn code_end(mut end: usize, source: &str, comments: &[Comment]) -> usize {
+    loop {
+        end = source.get(..end).map_or(end, |code| code.trim_end().len());
+        match comments.iter().find(|comment| comment.span.start < end && end <= comment.span.end) {
+            Some(comment) => end = comment.span.start,
+            None => return end,
+        }
+    }
+}
+
+/// Set off what follows with a blank line, except at the top of a file or a block.
+fn blank(lines: &mut Vec<(usize, Line)>, offset: usize) {
+    let opening = matches!(lines.last(), Some((_, Line::Text(_, text))) if text.ends_with('{') || text == "|:");
+    if !lines.is_empty() && !opening && !matches!(lines.last(), Some((_, Line::Blank))) {
+        lines.push((offset, Line::Blank));
+    }
+}
+
+/// Put a comment at the end of the last line.
+fn attach(lines: &mut [(usize, Line)], comment: &str) {
+    match lines.iter_mut().rev().find(|(_, line)| !matches!(line, Line::Blank)) {
+        Some((_, Line::Text(_, text))) => {
+            text.push_str("  ");
+            text.push_str(comment);
+        }
+        Some((_, Line::Measures(_, _, trailing))) => {
+            let text = trailing.get_or_insert_with(String::new);
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(comment);
+        }
+        _ => {}
+    }
+}
+
+/// Write out lines, aligning the barlines of consecutive lines of measures.
+fn render(lines: Vec<(usize, Line)>) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Line::Measures(indent, ..) = lines[i].1 else {
+            out.push((lines[i].0, match &lines[i].1 {
+                Line::Text(indent, text) => format!("{}{}", " ".repeat(*indent), text),
+                _ => String::new(),
+            }));
+            i += 1;
+            continue;
+        };
+        // Repeat barlines and measure kinds keep their own width
+        let plain = |line: &Line| matches!(line, Line::Measures(other, cells, _)
+            if *other == indent && cells[0].open == "| " && cells.iter().all(|c| c.kind.is_none() && c.close == "|"));
+        let run: Vec<&(usize, Line)> = if plain(&lines[i].1) {
+            lines[i..].iter().take_while(|(_, line)| plain(line)).collect()
+        } else {
+            vec![&lines[i]]
+        };
+        let mut widths: Vec<usize> = Vec::new();
+        for (_, line) in &run {
+            if let Line::Measures(_, cells, _) = line {
+                for (column, cell) in cells.iter().enumerate() {
+                    let width = cell_text(cell).chars().count();
+                    match widths.get_mut(column) {
+                        Some(max) => *max = (*max).max(width),
+                        None => widths.push(width),
+                    }
+                }
+            }
+        }
+        for (offset, line) in &run {
+            if let Line::Measures(_, cells, trailing) = line {
+                let mut text = " ".repeat(indent);
+                for (column, cell) in cells.iter().enumerate() {
+                    if column > 0 {
+                        text.push(' ');
+                    }
+                    let body = cell_text(cell);
+                    text.push_str(&format!("{:width$} {}", body, cell.close, width = widths[column]));
+                }
+                if let Some(comment) = trailing {
+                    text.push_str("  ");
+                    text.push_str(comment);
+                }
+                out.push((*offset, text));
+            }
+        }
+        i += run.len();
+    }
+    out
+}
+
+fn cell_text(cell: &Cell) -> String {
+    let mut text = String::new();
+    if let Some(kind) = cell.kind {
+        text.push_str(kind);
+        text.push(' ');
+    }
+    text.push_str(cell.open);
+    text.push_str(&cell.content);
+    text.trim_end().to_string()
+}
+
+fn header_text(header: &Header) -> String {
+    match header {
+        Header::Title(title) => format!("Title: \"{}\"", title),
+        Header::Copyright(notice) => format!("Copyright: \"{}\"", notice),
+        Header::Text(text) => format!("Text: \"{}\"", text),
+        Header::Tempo(tempo) => format!("Tempo: {}", tempo_text(tempo)),
+        Header::TimeSignature(num, den) => format!("Time: {}/{}", num, den),
+        Header::KeySignature(key) => format!("Key: {}", key_text(key)),
+        Header::Swing(swing) => format!("Swing: {}", swing_text(swing)),
+        Header::Articulation(setting) => {
+            let mut text = format!("Articulation: {}", setting.name);
+            if let Some(gate) = setting.gate {
+                text.push_str(&format!(" gate {:?}", gate));
+            }
+            if let Some(velocity) = setting.velocity {
+                text.push_str(&format!(" velocity {:+}", velocity));
+            }
+            if let Some(hold) = setting.hold {
+                text.push_str(&format!(" hold {:?}", hold));
+            }
+            text
+        }
+        Header::Ppq(ppq) => format!("PPQ: {}", ppq),
+        Header::BendRange(range) => format!("BendRange: {}", range),
+        Header::Accidentals(AccidentalMode::Key) => "Accidentals: Key".to_string(),
+        Header::Accidentals(AccidentalMode::Explicit) => "Accidentals: Explicit".to_string(),
+    }
+}
+
+fn context_change(change: &ContextChange) -> String {
+    match change {
+        ContextChange::Tempo(tempo) => format!("Tempo: {}", tempo_text(tempo)),
+        ContextChange::TempoRamp(ramp) => {
+            let curve = match ramp.curve {
+                TempoCurve::Linear => "",
+                TempoCurve::Exponential => " exp",
+            };
+            let label = if ramp.accelerando { "Accel" } else { "Rit" };
+            format!("{}: {} over {}{}", label, tempo_text(&ramp.target), ramp.beats, curve)
+        }
+        ContextChange::TimeSignature(num, den) => format!("Time: {}/{}", num, den),
+        ContextChange::KeySignature(key) => format!("Key: {}", key_text(key)),
+        ContextChange::Swing(swing) => format!("Swing: {}", swing_text(swing)),
+    }
+}
+
+fn navigation_text(navigation: Navigation) -> &'static str {
+    match navigation {
+        Navigation::Segno => "Segno",
+        Navigation::Coda => "Coda",
+        Navigation::ToCoda => "To Coda",
+        Navigation::Fine => "Fine",
+        Navigation::DaCapo(JumpEnd::Plain) => "D.C.",
+        Navigation::DaCapo(JumpEnd::AlFine) => "D.C. al Fine",
+        Navigation::DaCapo(JumpEnd::AlCoda) => "D.C. al Coda",
+        Navigation::DalSegno(JumpEnd::Plain) => "D.S.",
+        Navigation::DalSegno(JumpEnd::AlFine) => "D.S. al Fine",
+        Navigation::DalSegno(JumpEnd::AlCoda) => "D.S. al Coda",
+    }
+}
+
+fn tempo_text(tempo: &Tempo) -> String {
+    match &tempo.beat {
+        Some(beat) => format!("{} {}", tempo.bpm, duration(beat)),
+        None => tempo.bpm.to_string(),
+    }
+}
+
+fn key_text(key: &Key) -> String {
+    let mode = match key.mode {
+        Mode::Major => "Major",
+        Mode::Minor => "Minor",
+        Mode::Dorian => "Dorian",
+        Mode::Phrygian => "Phrygian",
+        Mode::Lydian => "Lydian",
+        Mode::Mixolydian => "Mixolydian",
+        Mode::Locrian => "Locrian",
+        Mode::Octatonic => "Octatonic",
+        Mode::WholeTone => "Whole Tone",
+        Mode::Chromatic => "Chromatic",
+    };
+    format!("{}{} \"{}\"", key.step, key.accidental.map_or("", accidental), mode)
+}
+
+fn swing_text(swing: &Option<(BaseDuration, f64)>) -> String {
+    match swing {
+        Some((base, ratio)) => format!("{} {:?}", base_duration(*base), ratio),
+        None => "off".to_string(),
+    }
+}
+
+/// A part or instrument name, quoted only when it could not be read bare.
+fn name(name: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || " _-().,'#+&".contains(c);
+    if !name.is_empty() && name.chars().all(plain) {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+fn events(events: &[Event]) -> String {
+    events.iter().map(event).collect::<Vec<_>>().join(" ")
+}
+
+fn event(event: &Event) -> String {
+    let mut words: Vec<String> = Vec::new();
+    match event {
+        Event::Note(note) => {
+            if let Some(grace) = &note.grace {
+                let slash = if grace.acciaccatura { "/" } else { "" };
+                let pitches: Vec<String> = grace.pitches.iter().map(pitch).collect();
+                words.push(format!("{{{}{}}}", slash, pitches.join(" ")));
+            }
+            words.push(pitch(&note.pitch));
+            words.extend(note.duration.as_ref().map(duration));
+            words.extend(note.dynamic.clone());
+            words.extend(note.articulation.clone());
+            words.extend(note.ornament.map(|ornament| match ornament {
+                Ornament::Trill => "tr".to_string(),
+                Ornament::Mordent => "mord".to_string(),
+                Ornament::InvertedMordent => "prall".to_string(),
+                Ornament::Turn => "turn".to_string(),
+            }));
+        }
+        Event::Chord(pitches, length, dynamic, articulation) => {
+            let pitches: Vec<String> = pitches.iter().map(pitch).collect();
+            words.push(format!("[{}]", pitches.join(" ")));
+            words.extend(length.as_ref().map(duration));
+            words.extend(dynamic.clone());
+            words.extend(articulation.clone());
+        }
+        Event::Rest(length, held) => {
+            words.push("r".to_string());
+            words.extend(length.as_ref().map(duration));
+            if *held {
+                words.push("U".to_string());
+            }
+        }
+        Event::Drum(hit) => {
+            match hit.drums.as_slice() {
+                [drum] => words.push(drum.clone()),
+                drums => words.push(format!("[{}]", drums.join(" "))),
+            }
+            words.extend(hit.duration.as_ref().map(duration));
+            words.extend(hit.dynamic.clone());
+            words.extend(hit.articulation.clone());
+        }
+        Event::Tie => words.push("~".to_string()),
+        Event::Tuplet(tuplet) => {
+            let inner = events(&tuplet.events);
+            let gap = if inner.is_empty() { "" } else { " " };
+            words.push(format!("Tuplet({}:{}) {{{}{}{}}}", tuplet.p, tuplet.q, gap, inner, gap));
+        }
+        Event::Dynamic(dynamic) => words.push(dynamic.clone()),
+        Event::Crescendo => words.push("<".to_string()),
+        Event::Diminuendo => words.push("dim".to_string()),
+        Event::MotifCall(call) => {
+            let transforms: Vec<String> = call.transforms.iter().map(|transform| match transform {
+                MotifTransform::Transpose(semitones) => format!("{:+}", semitones),
+                MotifTransform::TransposeDiatonic(steps) => format!("{:+}d", steps),
+                MotifTransform::Inversion => "inv".to_string(),
+                MotifTransform::Retrograde => "retro".to_string(),
+                MotifTransform::Augmentation(2) => "aug".to_string(),
+                MotifTransform::Augmentation(factor) => format!("aug{}", factor),
+                MotifTransform::Diminution(2) => "dim".to_string(),
+                MotifTransform::Diminution(factor) => format!("dim{}", factor),
+            }).collect();
+            match transforms.is_empty() {
+                true => words.push(format!("@{}", call.name)),
+                false => words.push(format!("@{}({})", call.name, transforms.join(", "))),
+            }
+        }
+        Event::Pedal(true) => words.push("Ped".to_string()),
+        Event::Pedal(false) => words.push("*".to_string()),
+        Event::Controller(controller) => words.push(match &controller.ramp {
+            Some((target, over)) => {
+                format!("CC({}, {} -> {} over {})", controller.number, controller.value, target, duration(over))
+            }
+            None => format!("CC({}, {})", controller.number, controller.value),
+        }),
+        Event::Slide(Slide::Glissando) => words.push("gliss".to_string()),
+        Event::Slide(Slide::Portamento) => words.push("port".to_string()),
+        Event::Bend(bend) => words.push(match &bend.ramp {
+            Some((target, over)) => format!("Bend({} -> {} over {})", bend.semitones, target, duration(over)),
+            None => format!("Bend({})", bend.semitones),
+        }),
+    }
+    words.join(" ")
+}
+
+pub(crate) fn pitch(pitch: &Pitch) -> String {
+    format!("{}{}{}", pitch.step, pitch.accidental.map_or("", accidental), pitch.octave)
+}
+
+fn accidental(accidental: Accidental) -> &'static str {
+    match accidental {
+        Accidental::Sharp => "#",
+        Accidental::Flat => "b",
+        Accidental::Natural => "n",
+        Accidental::QuarterSharp => "+",
+        Accidental::QuarterFlat => "d",
+        Accidental::ThreeQuarterSharp => "#+",
+        Accidental::ThreeQuarterFlat => "db",
+    }
+}
+
+fn duration(duration: &Duration) -> String {
+    match duration {
+        Duration::Base(base, dots) => format!("{}{}", base_duration(*base), ".".repeat(*dots as usize)),
+        Duration::Subdivision(base, divisions) => format!("{}/{}", base_duration(*base), divisions),
+        Duration::Fraction(num, den) => format!("{}/{}", num, den),
+    }
+}
+
+fn base_duration(base: BaseDuration) -> &'static str {
+    match base {
+        BaseDuration::Whole => "w",
+        BaseDuration::Half => "h",
+        BaseDuration::Quarter => "q",
+        BaseDuration::Eighth => "e",
+        BaseDuration::Sixteenth => "s",
+        BaseDuration::ThirtySecond => "t",
+        BaseDuration::SixtyFourth => "x",
+    }
+}
@@ -0,0 +1,667 @@
+use crate::drums::get_drum_name;
+use crate::instruments::{get_instrument_program, instrument_name, is_percussion};
+use crate::keys::key_name;
+use crate::musicxml::values;
+use crate::walker::{dynamic_to_velocity, Position, DRUM_CHANNEL};
+use anyhow::{anyhow, Context, Result};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_VELOCITY: u8 = 100;
+const DYNAMICS: [&str; 8] = ["ppp", "pp", "p", "mp", "mf", "f", "ff", "fff"];
+
+/// How a MIDI file is read back as written music.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Onsets and releases snap to this many steps per quarter note (4 = sixteenths)
+    pub grid: u32,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions { grid: 4 }
+    }
+}
+
+pub fn import_file(path: &Path, options: &ImportOptions) -> Result<String> {
+    let data = fs::read(path).with_context(|| format!("Failed to read MIDI file: {:?}", path))?;
+    let smf = Smf::parse(&data).context("Failed to parse MIDI file")?;
+    import(&smf, options)
+}
+
+/// Write a MIDI file as Melos source.
+///
+/// Notes snap to the grid, or to triplets or quintuplets where a beat's onsets fit
+/// those better. Each track and instrument becomes a part; notes that start and end
+/// together form chords, and overlapping ones are spread across voices. Notes bent by
+/// a quarter tone are written as quarter tones; other pitch bends are left out.
+pub fn import(smf: &Smf, options: &ImportOptions) -> Result<String> {
+    if !options.grid.is_power_of_two() || options.grid > 16 {
+        return Err(anyhow!("Grid must be 1, 2, 4, 8 or 16 steps per quarter note, got {}", options.grid));
+    }
+    let ppq = match smf.header.timing {
+        Timing::Metrical(ppq) => ppq.as_int() as u64,
+        Timing::Timecode(..) => return Err(anyhow!("MIDI files timed in SMPTE frames cannot be imported")),
+    };
+    let grid = options.grid as u64;
+    let midi = read(smf);
+    if midi.sources.is_empty() {
+        return Err(anyhow!("The MIDI file has no notes"));
+    }
+    let at = |tick: u64| Position::new(tick, 4 * ppq);
+
+    // Barlines long enough for every note on the straight grid, to decide tuplets
+    let step = (ppq / grid).max(1);
+    let changes: Vec<(Position, (u32, u32))> = midi.time_signatures.iter()
+        .map(|&(tick, num, den)| (at((tick + step / 2) / step * step), (num, den)))
+        .collect();
+    let last_tick = midi.sources.iter().flat_map(|source| &source.notes).map(|note| note.end).max().unwrap_or(0);
+    let straight_end = at(last_tick.div_ceil(step) * step);
+    let measures = measures(&changes, straight_end);
+
+    let mut parts = Vec::new();
+    let mut names: HashMap<String, usize> = HashMap::new();
+    let mut raised = 0;
+    let mut skipped = 0;
+    for source in &midi.sources {
+        let tuplets = tuplet_beats(&source.notes, ppq, grid, &measures);
+        let quantize = |tick: u64| {
+            let beat = tick / ppq;
+            let steps = tuplets.get(&beat).copied().unwrap_or(grid);
+            let offset = ((tick % ppq) * steps * 2 + ppq) / (2 * ppq);
+            (Position::new(beat * steps + offset, 4 * steps), Position::new(1, 4 * steps))
+        };
+        let mut grouped: BTreeMap<(Position, Position), Chord> = BTreeMap::new();
+        for note in &source.notes {
+            let (start, step) = quantize(note.start);
+            let end = quantize(note.end).0.max(start + step);
+            let key = if source.drums {
+                if get_drum_name(note.key).is_none() {
+                    skipped += 1;
+                    continue;
+                }
+                note.key
+            } else if note.key < 12 {
+                raised += 1;
+                note.key + 12
+            } else {
+                note.key
+            };
+            let pitch = (key, note.quarter && !source.drums);
+            let chord = grouped.entry((start, end)).or_insert_with(|| Chord {
+                start,
+                end,
+                keys: Vec::new(),
+                velocity: note.velocity,
+            });
+            if !chord.keys.contains(&pitch) {
+                chord.keys.push(pitch);
+            }
+            chord.velocity = chord.velocity.max(note.velocity);
+        }
+        if grouped.is_empty() {
+            continue;
+        }
+        let name = unique_name(&mut names, &source.name);
+        parts.push(ImportedPart {
+            name,
+            instrument: source.instrument.clone(),
+            drums: source.drums,
+            voices: voices(grouped.into_values().collect()),
+            tuplets,
+        });
+    }
+    if parts.is_empty() {
+        return Err(anyhow!("The MIDI file has no notes Melos can write"));
+    }
+
+    // Quantized notes can end a little later than the straight grid allowed for
+    let end = parts.iter().flat_map(|part| &part.voices).flatten().map(|chord| chord.end).max().unwrap_or_default();
+    let measures = self::measures(&changes, end.max(straight_end));
+
+    let mut out = String::new();
+    if raised > 0 {
+        out.push_str(&format!("// {} notes below C0 were raised an octave\n", raised));
+    }
+    if skipped > 0 {
+        out.push_str(&format!("// {} drum hits on keys without a name were left out\n", skipped));
+    }
+    if midi.ignored_bends > 0 {
+        out.push_str(&format!("// {} pitch bends were ignored\n", midi.ignored_bends));
+    }
+    if let Some(title) = &midi.title {
+        out.push_str(&format!("Title: \"{}\"\n", literal(title)));
+    }
+    if let Some(copyright) = &midi.copyright {
+        out.push_str(&format!("Copyright: \"{}\"\n", literal(copyright)));
+    }
+    for text in &midi.texts {
+        out.push_str(&format!("Text: \"{}\"\n", literal(text)));
+    }
+    if let Some(&(_, bpm)) = midi.tempos.iter().rev().find(|&&(tick, _)| tick == 0) {
+        out.push_str(&format!("Tempo: {}\n", tempo(bpm)));
+    }
+    let (num, den) = measures[0].time_signature;
+    out.push_str(&format!("Time: {}/{}\n", num, den));
+    let first_key = midi.keys.iter().rev().find(|&&(tick, ..)| tick == 0).map(|&(_, fifths, minor)| (fifths, minor));
+    if let Some((fifths, minor)) = first_key {
+        out.push_str(&format!("Key: {}\n", key(fifths, minor)));
+    }
+
+    for (index, part) in parts.iter().enumerate() {
+        out.push('\n');
+        out.push_str(&format!("Part: {} Instrument: {} {{\n", name(&part.name), name(&part.instrument)));
+        let mut velocity = DEFAULT_VELOCITY;
+        let mut fifths = first_key.map_or(0, |(fifths, _)| fifths);
+        for (number, measure) in measures.iter().enumerate() {
+            let within = |tick: u64| tick > 0 && measure.contains(at(tick));
+            if number > 0 && measure.time_signature != measures[number - 1].time_signature {
+                let (num, den) = measure.time_signature;
+                out.push_str(&format!("    Time: {}/{}\n", num, den));
+            }
+            if let Some(&(_, new_fifths, minor)) = midi.keys.iter().rev().find(|&&(tick, ..)| within(tick)) {
+                fifths = new_fifths;
+                out.push_str(&format!("    Key: {}\n", key(new_fifths, minor)));
+            }
+            // The tempo is shared, so only the first part carries its changes
+            if index == 0
+                && let Some(&(_, bpm)) = midi.tempos.iter().rev().find(|&&(tick, _)| within(tick))
+            {
+                out.push_str(&format!("    Tempo: {}\n", tempo(bpm)));
+            }
+            let spelling = Spelling { drums: part.drums, flats: fifths < 0 };
+            // Later voices that are silent in this measure are left out
+            let sounding: Vec<(usize, &Vec<Chord>)> = part.voices.iter().enumerate()
+                .filter(|(number, voice)| {
+                    *number == 0 || voice.iter().any(|chord| chord.end > measure.start && chord.start < measure.end)
+                })
+                .collect();
+            let mut voices = Vec::new();
+            for &(number, voice) in &sounding {
+                let mut words = voice_words(voice, measure, &part.tuplets, spelling, &mut velocity)?;
+                if sounding.len() > 1 {
+                    words.insert(0, format!("V{}:", number + 1));
+                }
+                voices.push(words.join(" "));
+            }
+            let kind = if measure.end - measure.start != measure.length() { "Irregular " } else { "" };
+            out.push_str(&format!("    {}| {} |\n", kind, voices.join(" ")));
+        }
+        out.push_str("}\n");
+    }
+    Ok(out)
+}
+
+/// Notes and meta events read from a MIDI file, in ticks.
+#[derive(Default)]
+struct Midi {
+    tempos: Vec<(u64, f64)>,
+    time_signatures: Vec<(u64, u32, u32)>,
+    keys: Vec<(u64, i8, bool)>,
+    title: Option<String>,
+    copyright: Option<String>,
+    texts: Vec<String>,
+    sources: Vec<Source>,
+    ignored_bends: usize, // Bends that are not quarter tones
+}
+
+/// The notes one instrument of one track plays.
+struct Source {
+    name: String,
+    instrument: String,
+    drums: bool,
+    notes: Vec<RawNote>,
+}
+
+struct RawNote {
+    start: u64,
+    end: u64,
+    key: u8,
+    quarter: bool, // Bent up a quarter tone from `key`
+    velocity: u8,
+}
+
+fn read(smf: &Smf) -> Midi {
+    let mut midi = Midi::default();
+    for track in &smf.tracks {
+        let mut time = 0u64;
+        let mut track_name = None;
+        let mut instrument = None;
+        let mut programs: HashMap<u8, u8> = HashMap::new();
+        let mut notes: BTreeMap<u8, Vec<RawNote>> = BTreeMap::new();
+        let mut sounding: HashMap<(u8, u8), VecDeque<RawNote>> = HashMap::new(); // Ending when released
+        let mut bends: HashMap<u8, i16> = HashMap::new(); // Quarter tones each channel is bent by
+        let mut ranges: HashMap<u8, u8> = HashMap::new(); // Pitch bend range, from RPN 0
+        let mut parameters: HashMap<u8, (u8, u8)> = HashMap::new(); // RPN selected on each channel
+        for event in track {
+            time += event.delta.as_int() as u64;
+            let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim().to_string();
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(mpq)) => {
+                    midi.tempos.push((time, 60_000_000.0 / mpq.as_int() as f64));
+                }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(num, den, _, _)) => {
+                    midi.time_signatures.push((time, num as u32, 2u32.pow(den as u32)));
+                }
+                TrackEventKind::Meta(MetaMessage::KeySignature(fifths, minor)) => {
+                    midi.keys.push((time, fifths, minor));
+                }
+                TrackEventKind::Meta(MetaMessage::TrackName(bytes)) => {
+                    track_name.get_or_insert_with(|| text(bytes));
+                }
+                TrackEventKind::Meta(MetaMessage::InstrumentName(bytes)) => {
+                    instrument.get_or_insert_with(|| text(bytes));
+                }
+                TrackEventKind::Meta(MetaMessage::Copyright(bytes)) => midi.copyright = Some(text(bytes)),
+                TrackEventKind::Meta(MetaMessage::Text(bytes)) => midi.texts.push(text(bytes)),
+                TrackEventKind::Midi { channel, message } => {
+                    let channel = channel.as_int();
+                    match message {
+                        MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            let bend = bends.get(&channel).copied().unwrap_or(0);
+                            let note = raw_note(time, key.as_int(), vel.as_int(), bend);
+                            sounding.entry((channel, key.as_int())).or_default().push_back(note);
+                        }
+                        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                            if let Some(note) = sounding.get_mut(&(channel, key.as_int())).and_then(|notes| notes.pop_front()) {
+                                notes.entry(channel).or_default().push(RawNote { end: time, ..note });
+                            }
+                        }
+                        MidiMessage::PitchBend { bend } => {
+                            let range = ranges.get(&channel).copied().unwrap_or(2);
+                            let cents = bend.as_int() as f64 * range as f64 * 100.0 / 8192.0;
+                            let quarters = (cents / 50.0).round();
+                            if quarters.abs() > 1.0 || (cents - quarters * 50.0).abs() > 5.0 {
+                                midi.ignored_bends += 1;
+                                bends.remove(&channel);
+                            } else {
+                                bends.insert(channel, quarters as i16);
+                            }
+                        }
+                        MidiMessage::Controller { controller, value } => {
+                            let selected = parameters.entry(channel).or_insert((127, 127));
+                            match controller.as_int() {
+                                101 => selected.0 = value.as_int(),
+                                100 => selected.1 = value.as_int(),
+                                6 if *selected == (0, 0) => {
+                                    ranges.insert(channel, value.as_int());
+                                }
+                                _ => {}
+                            }
+                        }
+                        MidiMessage::ProgramChange { program } => {
+                            programs.entry(channel).or_insert(program.as_int());
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Notes still held when the track ends stop there
+        for ((channel, _), held) in sounding {
+            for note in held {
+                notes.entry(channel).or_default().push(RawNote { end: time, ..note });
+            }
+        }
+
+        // A track without notes names the piece
+        if notes.is_empty() {
+            if midi.title.is_none() {
+                midi.title = track_name.filter(|name| !name.is_empty());
+            }
+            continue;
+        }
+        // Melos plays quarter tones on further channels with the part's program, so the
+        // channels of a track that play the same instrument are read as one part
+        let mut instruments: Vec<(bool, u8, Vec<RawNote>)> = Vec::new();
+        for (channel, channel_notes) in notes {
+            let drums = channel == DRUM_CHANNEL;
+            let program = programs.get(&channel).copied().unwrap_or(0);
+            match instruments.iter_mut().find(|(d, p, _)| *d == drums && (drums || *p == program)) {
+                Some((_, _, group)) => group.extend(channel_notes),
+                None => instruments.push((drums, program, channel_notes)),
+            }
+        }
+        let shared = instruments.len() > 1;
+        for (drums, program, mut channel_notes) in instruments {
+            channel_notes.sort_by_key(|note| (note.start, note.key, note.quarter));
+            // Keep the instrument name the file gives when it means the same sound
+            let instrument = match &instrument {
+                Some(name) if drums && is_percussion(name) => name.clone(),
+                Some(name) if !drums && get_instrument_program(name) == Some(program) => name.clone(),
+                _ if drums => "Drums".to_string(),
+                _ => instrument_name(program).to_string(),
+            };
+            let name = match &track_name {
+                Some(name) if !name.is_empty() && shared => format!("{} {}", name, instrument),
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => instrument.clone(),
+            };
+            midi.sources.push(Source { name, instrument, drums, notes: channel_notes });
+        }
+    }
+    // Every track may repeat the conductor's meta events; keep one per tick
+    midi.tempos.sort_by_key(|&(tick, _)| tick);
+    midi.tempos.dedup_by(|later, earlier| later.0 == earlier.0 && later.1 == earlier.1);
+    midi.time_signatures.sort_by_key(|&(tick, ..)| tick);
+    midi.time_signatures.dedup();
+    midi.keys.sort_by_key(|&(tick, ..)| tick);
+    midi.keys.dedup();
+    midi
+}
+
+/// A note starting at `start` that sounds `quarters` quarter tones away from `key`,
+/// stored as a key and whether it lies a quarter tone above it.
+fn raw_note(start: u64, key: u8, velocity: u8, quarters: i16) -> RawNote {
+    match quarters {
+        1 => RawNote { start, end: start, key, quarter: true, velocity },
+        -1 if key > 0 => RawNote { start, end: start, key: key - 1, quarter: true, velocity },
+        _ => RawNote { start, end: start, key, quarter: false, velocity },
+    }
+}
+
+/// Quarter-note beats whose onsets fit triplet eighths or quintuplet sixteenths
+/// better than the straight grid, with the number of notes in each beat's tuplet.
+fn tuplet_beats(notes: &[RawNote], ppq: u64, grid: u64, measures: &[Measure]) -> BTreeMap<u64, u64> {
+    let mut onsets: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for note in notes {
+        onsets.entry(note.start / ppq).or_default().push(note.start % ppq);
+    }
+    // Distance from each onset to its nearest step, in quarter notes
+    let error = |offsets: &[u64], steps: u64| -> f64 {
+        offsets.iter().map(|&offset| {
+            let position = offset as f64 * steps as f64 / ppq as f64;
+            (position - position.round()).abs() / steps as f64
+        }).sum()
+    };
+    onsets.into_iter().filter_map(|(beat, offsets)| {
+        let straight = error(&offsets, grid);
+        let (steps, tuplet) = [3, 5].into_iter()
+            .map(|steps| (steps, error(&offsets, steps)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        // A tuplet cannot straddle a barline
+        let (start, end) = (Position::new(beat, 4), Position::new(beat + 1, 4));
+        let inside = measures.iter().any(|measure| measure.start <= start && end <= measure.end);
+        // A finer grid always fits a little better, so a tuplet must fit clearly better
+        (inside && tuplet * 2.0 < straight).then_some((beat, steps))
+    }).collect()
+}
+
+struct ImportedPart {
+    name: String,
+    instrument: String,
+    drums: bool,
+    voices: Vec<Vec<Chord>>,
+    tuplets: BTreeMap<u64, u64>,
+}
+
+/// Notes that start and end together.
+struct Chord {
+    start: Position,
+    end: Position,
+    keys: Vec<(u8, bool)>, // MIDI key, and whether a quarter tone above it
+    velocity: u8,
+}
+
+/// Spread chords across as few voices as possible, each voice playing one chord at a time.
+fn voices(mut chords: Vec<Chord>) -> Vec<Vec<Chord>> {
+    chords.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+    let mut voices: Vec<Vec<Chord>> = Vec::new();
+    for chord in chords {
+        match voices.iter_mut().find(|voice| voice.last().is_none_or(|last| last.end <= chord.start)) {
+            Some(voice) => voice.push(chord),
+            None => voices.push(vec![chord]),
+        }
+    }
+    voices
+}
+
+struct Measure {
+    start: Position,
+    end: Position,
+    time_signature: (u32, u32),
+}
+
+impl Measure {
+    fn length(&self) -> Position {
+        Position::new(self.time_signature.0 as u64, self.time_signature.1 as u64)
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.start <= position && position < self.end
+    }
+}
+
+/// Measures from the start of the piece until `end`. A time signature that
+/// changes mid-measure cuts that measure short.
+fn measures(changes: &[(Position, (u32, u32))], end: Position) -> Vec<Measure> {
+    let mut measures = Vec::new();
+    let mut start = Position::from_integer(0);
+    let mut time_signature = (4, 4);
+    let mut next = 0;
+    loop {
+        while let Some(&(at, signature)) = changes.get(next)
+            && at <= start
+        {
+            time_signature = signature;
+            next += 1;
+        }
+        let mut measure = Measure { start, end: start, time_signature };
+        measure.end = start + measure.length();
+        if let Some(&(at, _)) = changes.get(next)
+            && at < measure.end
+        {
+            measure.end = at;
+        }
+        start = measure.end;
+        measures.push(measure);
+        if start >= end {
+            return measures;
+        }
+    }
+}
+
+/// How notes are spelled in the current measure.
+#[derive(Clone, Copy)]
+struct Spelling {
+    drums: bool,
+    flats: bool,
+}
+
+/// A stretch of one voice within a measure: a chord, or a rest when `chord` is `None`.
+struct Span<'a> {
+    start: Position,
+    end: Position,
+    chord: Option<&'a Chord>,
+    tied_in: bool,
+    tied_out: bool,
+}
+
+/// The events of one voice in one measure.
+fn voice_words(
+    voice: &[Chord],
+    measure: &Measure,
+    tuplets: &BTreeMap<u64, u64>,
+    spelling: Spelling,
+    velocity: &mut u8,
+) -> Result<Vec<String>> {
+    let mut spans = Vec::new();
+    let mut cursor = measure.start;
+    for chord in voice.iter().filter(|chord| chord.end > measure.start && chord.start < measure.end) {
+        let start = chord.start.max(measure.start);
+        if start > cursor {
+            spans.push(Span { start: cursor, end: start, chord: None, tied_in: false, tied_out: false });
+        }
+        let end = chord.end.min(measure.end);
+        spans.push(Span {
+            start,
+            end,
+            chord: Some(chord),
+            tied_in: chord.start < measure.start,
+            tied_out: chord.end > measure.end,
+        });
+        cursor = end;
+    }
+    if cursor < measure.end {
+        spans.push(Span { start: cursor, end: measure.end, chord: None, tied_in: false, tied_out: false });
+    }
+
+    // Split at the edges of tuplet beats, so each tuplet gets a bracket of its own
+    let cuts: Vec<Position> = tuplets.keys()
+        .flat_map(|&beat| [Position::new(beat, 4), Position::new(beat + 1, 4)])
+        .filter(|&cut| measure.start < cut && cut < measure.end)
+        .collect();
+    let mut pieces = Vec::new();
+    for span in spans {
+        let mut start = span.start;
+        for &cut in cuts.iter().filter(|&&cut| span.start < cut && cut < span.end) {
+            pieces.push(Span { start, end: cut, chord: span.chord, tied_in: span.tied_in || start > span.start, tied_out: true });
+            start = cut;
+        }
+        pieces.push(Span { start, tied_in: span.tied_in || start > span.start, ..span });
+    }
+
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < pieces.len() {
+        let beat = (pieces[i].start * 4).to_integer();
+        let (start, end) = (Position::new(beat, 4), Position::new(beat + 1, 4));
+        // A note or rest filling the whole beat is a plain quarter
+        let whole_beat = pieces[i].start == start && pieces[i].end == end;
+        match tuplets.get(&beat) {
+            Some(&steps) if pieces[i].end <= end && !whole_beat => {
+                let normal = 1u64 << (63 - steps.leading_zeros());
+                let mut inner = Vec::new();
+                while i < pieces.len() && (pieces[i].start * 4).to_integer() == beat {
+                    piece_words(&mut inner, &pieces[i], Position::new(steps, normal), spelling, velocity)?;
+                    i += 1;
+                }
+                words.push(format!("Tuplet({}:{}) {{ {} }}", steps, normal, inner.join(" ")));
+            }
+            _ => {
+                piece_words(&mut words, &pieces[i], Position::from_integer(1), spelling, velocity)?;
+                i += 1;
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// Write a span as notes tied together, or rests. `scale` turns its length into
+/// the written length inside a tuplet.
+fn piece_words(words: &mut Vec<String>, piece: &Span, scale: Position, spelling: Spelling, velocity: &mut u8) -> Result<()> {
+    let values = values((piece.end - piece.start) * scale)?;
+    let count = values.len();
+    for (i, (value, dots)) in values.into_iter().enumerate() {
+        let duration = format!("{}{}", duration_letter(value)?, ".".repeat(dots as usize));
+        let onset = i == 0 && !piece.tied_in;
+        let word = match piece.chord {
+            // Drums are not held, so what a long hit leaves behind is silence
+            Some(chord) if !spelling.drums || onset => {
+                let mut word = format!("{} {}", head(chord, spelling), duration);
+                if onset && velocity.abs_diff(chord.velocity) > 8 {
+                    let (mark, level) = DYNAMICS.into_iter()
+                        .map(|mark| (mark, dynamic_to_velocity(mark)))
+                        .min_by_key(|(_, level)| level.abs_diff(chord.velocity))
+                        .unwrap();
+                    word.push_str(&format!(" {}", mark));
+                    *velocity = level;
+                }
+                if !spelling.drums && (i + 1 < count || piece.tied_out) {
+                    word.push_str(" ~");
+                }
+                word
+            }
+            _ => format!("r {}", duration),
+        };
+        words.push(word);
+    }
+    Ok(())
+}
+
+fn head(chord: &Chord, spelling: Spelling) -> String {
+    let names: Vec<String> = chord.keys.iter().map(|&(key, quarter)| match spelling.drums {
+        true => get_drum_name(key).unwrap_or("kick").to_string(),
+        false if quarter => quarter_tone_name(key),
+        false => pitch_name(key, spelling.flats),
+    }).collect();
+    match names.as_slice() {
+        [name] => name.clone(),
+        _ => format!("[{}]", names.join(" ")),
+    }
+}
+
+fn pitch_name(key: u8, flats: bool) -> String {
+    const SHARPS: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    const FLATS: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"];
+    let names = if flats { FLATS } else { SHARPS };
+    format!("{}{}", names[(key % 12) as usize], key / 12 - 1)
+}
+
+/// The quarter tone above `key`, written from whichever neighbour is a natural:
+/// `C+4` above C4, `Dd4` above C#4.
+fn quarter_tone_name(key: u8) -> String {
+    match pitch_name(key, false) {
+        name if !name.contains('#') => format!("{}+{}", &name[..1], key / 12 - 1),
+        _ => {
+            let above = key + 1;
+            format!("{}d{}", &pitch_name(above, false)[..1], above / 12 - 1)
+        }
+    }
+}
+
+fn duration_letter(value: u64) -> Result<char> {
+    match value {
+        1 => Ok('w'),
+        2 => Ok('h'),
+        4 => Ok('q'),
+        8 => Ok('e'),
+        16 => Ok('s'),
+        32 => Ok('t'),
+        64 => Ok('x'),
+        _ => Err(anyhow!("Cannot write a 1/{} note", value)),
+    }
+}
+
+/// e.g. (-3, false) -> `Eb "Major"`
+fn key(fifths: i8, minor: bool) -> String {
+    let name = key_name(fifths, minor);
+    let (root, mode) = name.split_once(' ').unwrap_or((&name, "major"));
+    format!("{} \"{}{}\"", root, mode[..1].to_uppercase(), &mode[1..])
+}
+
+fn tempo(bpm: f64) -> String {
+    let rounded = (bpm * 100.0).round() / 100.0;
+    if rounded.fract() == 0.0 {
+        format!("{}", rounded as u64)
+    } else {
+        format!("{:.2}", rounded).trim_end_matches('0').to_string()
+    }
+}
+
+/// A part or instrument name, quoted unless it is plain words.
+fn name(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ') && !name.contains("Instrument") {
+        name.to_string()
+    } else {
+        format!("\"{}\"", literal(name))
+    }
+}
+
+/// Text that fits in a string literal.
+fn literal(text: &str) -> String {
+    text.replace('"', "'")
+}
+
+/// `name`, or `name 2`, `name 3`... when an earlier part already has it.
+fn unique_name(names: &mut HashMap<String, usize>, name: &str) -> String {
+    let count = names.entry(name.to_string()).or_insert(0);
+    *count += 1;
+    match *count {
+        1 => name.to_string(),
+        n => format!("{} {}", name, n),
+    }
+}
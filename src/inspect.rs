@@ -1,3 +1,4 @@
+use crate::keys::key_name;
 use anyhow::{Context, Result};
 use midly::{Smf, TrackEventKind, MetaMessage, MidiMessage};
 use std::path::Path;
@@ -30,13 +31,25 @@ pub fn inspect(path: &Path) -> Result<()> {
                      println!("  [@{}] Time Signature: {}/{}", absolute_time, num, den_val);
                 }
                 TrackEventKind::Meta(MetaMessage::KeySignature(key, scale)) => {
-                    println!("  [@{}] Key Signature: {:?} {:?}", absolute_time, key, scale);
+                    println!("  [@{}] Key Signature: {}", absolute_time, key_name(key, scale));
+                }
+                TrackEventKind::Meta(MetaMessage::MidiPort(port)) => {
+                    println!("  [@{}] MIDI Port: {}", absolute_time, port.as_int());
                 }
                 TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
                      if let Ok(s) = std::str::from_utf8(name) {
                          println!("  [@{}] Track Name: {}", absolute_time, s);
                      }
                 }
+                TrackEventKind::Meta(MetaMessage::InstrumentName(name)) => {
+                    println!("  [@{}] Instrument Name: {}", absolute_time, String::from_utf8_lossy(name));
+                }
+                TrackEventKind::Meta(MetaMessage::Copyright(notice)) => {
+                    println!("  [@{}] Copyright: {}", absolute_time, String::from_utf8_lossy(notice));
+                }
+                TrackEventKind::Meta(MetaMessage::Text(text)) => {
+                    println!("  [@{}] Text: {}", absolute_time, String::from_utf8_lossy(text));
+                }
                 TrackEventKind::Midi { message: MidiMessage::NoteOn { .. }, .. } => {
                     note_count += 1;
                 }
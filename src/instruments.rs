@@ -1,133 +1,197 @@
+/// Percussion parts play drum names on the General MIDI drum channel (10).
+pub fn is_percussion(name: &str) -> bool {
+    matches!(
+        name.to_lowercase().as_str(),
+        "drums" | "drum kit" | "drum set" | "drumset" | "percussion"
+    )
+}
+
+/// General MIDI program names, indexed by program number.
+const PROGRAM_NAMES: [&str; 128] = [
+    "Acoustic Grand Piano", // 0
+    "Bright Acoustic Piano",
+    "Electric Grand Piano",
+    "Honky-tonk Piano",
+    "Electric Piano 1",
+    "Electric Piano 2",
+    "Harpsichord",
+    "Clavinet",
+    "Celesta", // 8
+    "Glockenspiel",
+    "Music Box",
+    "Vibraphone",
+    "Marimba",
+    "Xylophone",
+    "Tubular Bells",
+    "Dulcimer",
+    "Drawbar Organ", // 16
+    "Percussive Organ",
+    "Rock Organ",
+    "Church Organ",
+    "Reed Organ",
+    "Accordion",
+    "Harmonica",
+    "Tango Accordion",
+    "Acoustic Guitar (Nylon)", // 24
+    "Acoustic Guitar (Steel)",
+    "Electric Guitar (Jazz)",
+    "Electric Guitar (Clean)",
+    "Electric Guitar (Muted)",
+    "Overdriven Guitar",
+    "Distortion Guitar",
+    "Guitar Harmonics",
+    "Acoustic Bass", // 32
+    "Electric Bass (Finger)",
+    "Electric Bass (Pick)",
+    "Fretless Bass",
+    "Slap Bass 1",
+    "Slap Bass 2",
+    "Synth Bass 1",
+    "Synth Bass 2",
+    "Violin", // 40
+    "Viola",
+    "Cello",
+    "Contrabass",
+    "Tremolo Strings",
+    "Pizzicato Strings",
+    "Orchestral Harp",
+    "Timpani",
+    "String Ensemble 1", // 48
+    "String Ensemble 2",
+    "Synthstrings 1",
+    "Synthstrings 2",
+    "Choir Aahs",
+    "Voice Oohs",
+    "Synth Voice",
+    "Orchestra Hit",
+    "Trumpet", // 56
+    "Trombone",
+    "Tuba",
+    "Muted Trumpet",
+    "French Horn",
+    "Brass Section",
+    "Synthbrass 1",
+    "Synthbrass 2",
+    "Soprano Sax", // 64
+    "Alto Sax",
+    "Tenor Sax",
+    "Baritone Sax",
+    "Oboe",
+    "English Horn",
+    "Bassoon",
+    "Clarinet",
+    "Piccolo", // 72
+    "Flute",
+    "Recorder",
+    "Pan Flute",
+    "Blown Bottle",
+    "Shakuhachi",
+    "Whistle",
+    "Ocarina",
+    "Lead 1 (Square)", // 80
+    "Lead 2 (Sawtooth)",
+    "Lead 3 (Calliope)",
+    "Lead 4 (Chiff)",
+    "Lead 5 (Charang)",
+    "Lead 6 (Voice)",
+    "Lead 7 (Fifths)",
+    "Lead 8 (Bass + Lead)",
+    "Pad 1 (New Age)", // 88
+    "Pad 2 (Warm)",
+    "Pad 3 (Polysynth)",
+    "Pad 4 (Choir)",
+    "Pad 5 (Bowed)",
+    "Pad 6 (Metallic)",
+    "Pad 7 (Halo)",
+    "Pad 8 (Sweep)",
+    "Fx 1 (Rain)", // 96
+    "Fx 2 (Soundtrack)",
+    "Fx 3 (Crystal)",
+    "Fx 4 (Atmosphere)",
+    "Fx 5 (Brightness)",
+    "Fx 6 (Goblins)",
+    "Fx 7 (Echoes)",
+    "Fx 8 (Sci-fi)",
+    "Sitar", // 104
+    "Banjo",
+    "Shamisen",
+    "Koto",
+    "Kalimba",
+    "Bag Pipe",
+    "Fiddle",
+    "Shanai",
+    "Tinkle Bell", // 112
+    "Agogo",
+    "Steel Drums",
+    "Woodblock",
+    "Taiko Drum",
+    "Melodic Tom",
+    "Synth Drum",
+    "Reverse Cymbal",
+    "Guitar Fret Noise", // 120
+    "Breath Noise",
+    "Seashore",
+    "Bird Tweet",
+    "Telephone Ring",
+    "Helicopter",
+    "Applause",
+    "Gunshot",
+];
+
 pub fn get_instrument_program(name: &str) -> Option<u8> {
-    match name.to_lowercase().as_str() {
-        "piano" | "acoustic grand piano" => Some(0),
-        "bright acoustic piano" => Some(1),
-        "electric grand piano" => Some(2),
-        "honky-tonk piano" => Some(3),
-        "electric piano 1" => Some(4),
-        "electric piano 2" => Some(5),
-        "harpsichord" => Some(6),
-        "clavinet" => Some(7),
-        "celesta" => Some(8),
-        "glockenspiel" => Some(9),
-        "music box" => Some(10),
-        "vibraphone" => Some(11),
-        "marimba" => Some(12),
-        "xylophone" => Some(13),
-        "tubular bells" => Some(14),
-        "dulcimer" => Some(15),
-        "drawbar organ" => Some(16),
-        "percussive organ" => Some(17),
-        "rock organ" => Some(18),
-        "church organ" => Some(19),
-        "reed organ" => Some(20),
-        "accordion" => Some(21),
-        "harmonica" => Some(22),
-        "tango accordion" => Some(23),
-        "acoustic guitar (nylon)" | "guitar" => Some(24),
-        "acoustic guitar (steel)" => Some(25),
-        "electric guitar (jazz)" => Some(26),
-        "electric guitar (clean)" => Some(27),
-        "electric guitar (muted)" => Some(28),
-        "overdriven guitar" => Some(29),
-        "distortion guitar" => Some(30),
-        "guitar harmonics" => Some(31),
-        "acoustic bass" => Some(32),
-        "electric bass (finger)" | "bass" => Some(33),
-        "electric bass (pick)" => Some(34),
-        "fretless bass" => Some(35),
-        "slap bass 1" => Some(36),
-        "slap bass 2" => Some(37),
-        "synth bass 1" => Some(38),
-        "synth bass 2" => Some(39),
-        "violin" => Some(40),
-        "viola" => Some(41),
-        "cello" => Some(42),
-        "contrabass" => Some(43),
-        "tremolo strings" => Some(44),
-        "pizzicato strings" => Some(45),
-        "orchestral harp" => Some(46),
-        "timpani" => Some(47),
-        "string ensemble 1" | "strings" => Some(48),
-        "string ensemble 2" => Some(49),
-        "synthstrings 1" => Some(50),
-        "synthstrings 2" => Some(51),
-        "choir aahs" | "choir" => Some(52),
-        "voice oohs" => Some(53),
-        "synth voice" => Some(54),
-        "orchestra hit" => Some(55),
-        "trumpet" => Some(56),
-        "trombone" => Some(57),
-        "tuba" => Some(58),
-        "muted trumpet" => Some(59),
-        "french horn" => Some(60),
-        "brass section" | "brass" => Some(61),
-        "synthbrass 1" => Some(62),
-        "synthbrass 2" => Some(63),
-        "soprano sax" => Some(64),
-        "alto sax" => Some(65),
-        "tenor sax" => Some(66),
-        "baritone sax" => Some(67),
-        "oboe" => Some(68),
-        "english horn" => Some(69),
-        "bassoon" => Some(70),
-        "clarinet" => Some(71),
-        "piccolo" => Some(72),
-        "flute" => Some(73),
-        "recorder" => Some(74),
-        "pan flute" => Some(75),
-        "blown bottle" => Some(76),
-        "shakuhachi" => Some(77),
-        "whistle" => Some(78),
-        "ocarina" => Some(79),
-        "lead 1 (square)" => Some(80),
-        "lead 2 (sawtooth)" => Some(81),
-        "lead 3 (calliope)" => Some(82),
-        "lead 4 (chiff)" => Some(83),
-        "lead 5 (charang)" => Some(84),
-        "lead 6 (voice)" => Some(85),
-        "lead 7 (fifths)" => Some(86),
-        "lead 8 (bass + lead)" => Some(87),
-        "pad 1 (new age)" => Some(88),
-        "pad 2 (warm)" => Some(89),
-        "pad 3 (polysynth)" => Some(90),
-        "pad 4 (choir)" => Some(91),
-        "pad 5 (bowed)" => Some(92),
-        "pad 6 (metallic)" => Some(93),
-        "pad 7 (halo)" => Some(94),
-        "pad 8 (sweep)" => Some(95),
-        "fx 1 (rain)" => Some(96),
-        "fx 2 (soundtrack)" => Some(97),
-        "fx 3 (crystal)" => Some(98),
-        "fx 4 (atmosphere)" => Some(99),
-        "fx 5 (brightness)" => Some(100),
-        "fx 6 (goblins)" => Some(101),
-        "fx 7 (echoes)" => Some(102),
-        "fx 8 (sci-fi)" => Some(103),
-        "sitar" => Some(104),
-        "banjo" => Some(105),
-        "shamisen" => Some(106),
-        "koto" => Some(107),
-        "kalimba" => Some(108),
-        "bag pipe" => Some(109),
-        "fiddle" => Some(110),
-        "shanai" => Some(111),
-        "tinkle bell" => Some(112),
-        "agogo" => Some(113),
-        "steel drums" => Some(114),
-        "woodblock" => Some(115),
-        "taiko drum" => Some(116),
-        "melodic tom" => Some(117),
-        "synth drum" => Some(118),
-        "reverse cymbal" => Some(119),
-        "guitar fret noise" => Some(120),
-        "breath noise" => Some(121),
-        "seashore" => Some(122),
-        "bird tweet" => Some(123),
-        "telephone ring" => Some(124),
-        "helicopter" => Some(125),
-        "applause" => Some(126),
-        "gunshot" => Some(127),
-        _ => None,
+    let name = name.to_lowercase();
+    // Short names for the most common instruments
+    let program = match name.as_str() {
+        "piano" => 0,
+        "guitar" => 24,
+        "bass" => 33,
+        "strings" => 48,
+        "choir" => 52,
+        "brass" => 61,
+        _ => PROGRAM_NAMES.iter().position(|program| program.to_lowercase() == name)? as u8,
+    };
+    Some(program)
+}
+
+/// Every name `get_instrument_program` accepts, short names first.
+pub fn instrument_names() -> impl Iterator<Item = &'static str> {
+    ["Piano", "Guitar", "Bass", "Strings", "Choir", "Brass"].into_iter().chain(PROGRAM_NAMES)
+}
+
+/// The name a score uses for a General MIDI program: its short name if it has one,
+/// otherwise its full name.
+pub fn instrument_name(program: u8) -> &'static str {
+    match program {
+        0 => "Piano",
+        24 => "Guitar",
+        33 => "Bass",
+        48 => "Strings",
+        52 => "Choir",
+        61 => "Brass",
+        _ => PROGRAM_NAMES[program as usize & 0x7f],
+    }
+}
+
+/// Instruments that cannot bend a note, so glissandi are played as runs.
+pub fn is_keyboard(program: u8) -> bool {
+    match program {
+        0..=21 => true, // Pianos, chromatic percussion, organs, accordion
+        23 => true,     // Tango accordion
+        46 => true,     // Harp
+        _ => false,
+    }
+}
+
+/// Whether a General MIDI program holds its notes at a steady level (organs,
+/// strings, winds, pads) rather than decaying after the attack. Only sustaining
+/// instruments can swell within a held note.
+pub fn is_sustaining(program: u8) -> bool {
+    match program {
+        16..=23 => true,  // Organs, accordion, harmonica
+        40..=44 => true,  // Bowed strings and tremolo
+        48..=54 => true,  // Ensembles and choirs (not orchestra hit)
+        56..=95 => true,  // Brass, reeds, pipes, synth leads and pads
+        109..=111 => true, // Bag pipe, fiddle, shanai
+        _ => false,
     }
 }
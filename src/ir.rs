@@ -1,3 +1,5 @@
+use crate::ast::{Key, Navigation};
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct IrScore {
     pub tracks: Vec<IrTrack>,
@@ -7,7 +9,10 @@ pub struct IrScore {
 #[derive(Debug, PartialEq, Clone)]
 pub struct IrTrack {
     pub name: String,
+    pub instrument: Option<String>, // As written in the score; None for the conductor track
+    pub port: u8, // MIDI port, for scores that need more than 16 channels
     pub channel: u8, // MIDI channel 0-15
+    pub member_channels: Vec<u8>, // Spare channels for quarter tones, each with its own pitch bend (MPE-style)
     pub events: Vec<IrEvent>,
 }
 
@@ -23,12 +28,32 @@ pub enum IrEventKind {
         pitch: u8, // MIDI note number 0-127
         velocity: u8, // 0-127
         duration: u32, // Ticks
+        voice: u8, // Voice within the part, from 1
+        member: u8, // 0 plays on the track's channel, n on member_channels[n - 1]
     },
-    Tempo(u32), // BPM
+    Tempo(f64), // Quarter notes per minute
     TimeSignature(u32, u32),
-    KeySignature {
-        root: String,
-        scale: String,
-    },
+    KeySignature(Key),
     ProgramChange(u8),
+    TrackName(String), // Score title, on the conductor track
+    Copyright(String),
+    Text(String),
+    ControlChange {
+        controller: u8, // 0-127
+        value: u8, // 0-127
+    },
+    PitchBend {
+        value: i16, // -8192 to 8191, 0 is no bend
+        member: u8, // Channel as for notes
+    },
+    Mark(Mark), // Notation-only structure, emitted when repeats are not unrolled
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Mark {
+    RepeatStart,
+    RepeatEnd { times: u32 },
+    EndingStart(Vec<u32>),
+    EndingEnd { numbers: Vec<u32>, discontinue: bool },
+    Navigation(Navigation),
 }
@@ -0,0 +1,121 @@
+use crate::ast::{Accidental, Key, Mode};
+use anyhow::{anyhow, Result};
+
+/// Steps in scale order, used to index per-step alterations.
+pub const STEPS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+/// Position of a step letter within `STEPS`.
+pub fn step_index(step: char) -> Result<usize> {
+    STEPS.iter().position(|&s| s == step).ok_or_else(|| anyhow!("Invalid pitch step"))
+}
+
+/// Read a key from its tonic and mode as written, e.g. ("Eb", "Major").
+pub fn parse_key(root: &str, mode: &str) -> Result<Key> {
+    let (step, accidental) = parse_tonic(root)?;
+    Ok(Key { step, accidental, mode: parse_mode(mode)? })
+}
+
+/// Read the tonic of a key: a step with an optional sharp or flat.
+pub fn parse_tonic(root: &str) -> Result<(char, Option<Accidental>)> {
+    let mut chars = root.chars();
+    let step = chars.next().filter(|step| STEPS.contains(step))
+        .ok_or_else(|| anyhow!("Invalid key root: {}", root))?;
+    let accidental = match chars.as_str() {
+        "" => None,
+        "#" => Some(Accidental::Sharp),
+        "b" => Some(Accidental::Flat),
+        _ => return Err(anyhow!("Invalid key root: {}", root)),
+    };
+    Ok((step, accidental))
+}
+
+/// Read a mode name, ignoring case; "Ionian" and "Aeolian" are major and minor.
+pub fn parse_mode(mode: &str) -> Result<Mode> {
+    Ok(match mode.to_lowercase().as_str() {
+        "major" | "ionian" => Mode::Major,
+        "minor" | "aeolian" => Mode::Minor,
+        "dorian" => Mode::Dorian,
+        "phrygian" => Mode::Phrygian,
+        "lydian" => Mode::Lydian,
+        "mixolydian" => Mode::Mixolydian,
+        "locrian" => Mode::Locrian,
+        "octatonic" => Mode::Octatonic,
+        "whole tone" | "whole-tone" | "wholetone" => Mode::WholeTone,
+        "chromatic" => Mode::Chromatic,
+        _ => return Err(anyhow!("Unknown mode \"{}\"", mode)),
+    })
+}
+
+/// Number of sharps (positive) or flats (negative) in the signature of a key,
+/// e.g. Eb major -> -3, D Dorian -> 0. Scales without a signature are an error.
+pub fn key_fifths(key: &Key) -> Result<i32> {
+    let root_fifths = match key.step {
+        'F' => -1,
+        'C' => 0,
+        'G' => 1,
+        'D' => 2,
+        'A' => 3,
+        'E' => 4,
+        'B' => 5,
+        _ => return Err(anyhow!("Invalid key root: {}", key.step)),
+    };
+    let accidental = match key.accidental {
+        Some(Accidental::Sharp) => 7,
+        Some(Accidental::Flat) => -7,
+        _ => 0,
+    };
+
+    // Offset of each mode from the major key on the same root
+    let mode_offset = match key.mode {
+        Mode::Major => 0,
+        Mode::Minor => -3,
+        Mode::Dorian => -2,
+        Mode::Phrygian => -4,
+        Mode::Lydian => 1,
+        Mode::Mixolydian => -1,
+        Mode::Locrian => -5,
+        Mode::Octatonic | Mode::WholeTone | Mode::Chromatic => {
+            return Err(anyhow!("{:?} scales have no key signature (expected a major, minor or church mode)", key.mode));
+        }
+    };
+
+    Ok(root_fifths + accidental + mode_offset)
+}
+
+/// Alteration in semitones that each step (C..B) takes in a key with the given fifths.
+pub fn step_alterations(fifths: i32) -> [i32; 7] {
+    const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6]; // F C G D A E B
+    let mut alterations = [0; 7];
+    for i in 0..fifths.unsigned_abs() as usize {
+        let step = if fifths > 0 { SHARP_ORDER[i % 7] } else { SHARP_ORDER[6 - i % 7] };
+        alterations[step] += fifths.signum();
+    }
+    alterations
+}
+
+/// MIDI key signature (sharps/flats, minor flag) for a key, respelled into the
+/// -7..=7 range MIDI allows. Modes other than major and minor are written with
+/// their signature and the major flag, since MIDI cannot express them. Returns
+/// `None` for scales without a key signature.
+pub fn midi_key_signature(key: &Key) -> Option<(i8, bool)> {
+    let mut fifths = key_fifths(key).ok()?;
+    if fifths > 7 {
+        fifths -= 12;
+    } else if fifths < -7 {
+        fifths += 12;
+    }
+    let minor = key.mode == Mode::Minor;
+    Some((fifths as i8, minor))
+}
+
+/// Name of the key with a MIDI key signature, e.g. (-3, false) -> "Eb major".
+pub fn key_name(fifths: i8, minor: bool) -> String {
+    const MAJOR: [&str; 15] = ["Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#"];
+    const MINOR: [&str; 15] = ["Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#"];
+    let index = (fifths.clamp(-7, 7) + 7) as usize;
+    if minor {
+        format!("{} minor", MINOR[index])
+    } else {
+        format!("{} major", MAJOR[index])
+    }
+}
@@ -4,7 +4,17 @@ pub mod walker;
 pub mod ir;
 pub mod codegen;
 pub mod instruments;
+pub mod drums;
 pub mod inspect;
 pub mod grammar;
 pub mod loader;
+pub mod keys;
+pub mod motifs;
+pub mod accidentals;
+pub mod diagnostics;
+pub mod musicxml;
+pub mod import;
+pub mod format;
+pub mod lsp;
+pub mod render;
 pub mod wasm;
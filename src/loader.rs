@@ -1,3 +1,4 @@
+use crate::diagnostics::SourceMap;
 use anyhow::{Context, Result, bail};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -9,6 +10,8 @@ pub struct LoadedSource {
     pub source: String,
     /// The base path (file path for single file, directory path for multi-file)
     pub base_path: PathBuf,
+    /// Where each file's text sits in `source`, for reporting diagnostics
+    pub sources: SourceMap,
 }
 
 /// Load Melos source from a file or directory.
@@ -31,19 +34,32 @@ fn load_single_file(path: &Path) -> Result<LoadedSource> {
         .with_context(|| format!("Failed to read file: {:?}", path))?;
 
     Ok(LoadedSource {
+        sources: SourceMap::single(path.display().to_string(), source.clone()),
         source,
         base_path: path.to_path_buf(),
     })
 }
 
 fn load_directory(dir: &Path) -> Result<LoadedSource> {
+    let mut files = Vec::new();
+    for file_path in score_files(dir)? {
+        let content = fs::read_to_string(&file_path)
+            .with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        files.push((file_path.display().to_string(), content));
+    }
+    Ok(combine(dir, files))
+}
+
+/// The .mel files of a directory in the order they are combined: score.mel first,
+/// then alphabetically by filename.
+pub fn score_files(dir: &Path) -> Result<Vec<PathBuf>> {
     // Find all .mel files in the directory
     let mut mel_files: Vec<PathBuf> = fs::read_dir(dir)
         .with_context(|| format!("Failed to read directory: {:?}", dir))?
         .filter_map(|entry| entry.ok())
         .map(|entry| entry.path())
         .filter(|path| {
-            path.is_file() && path.extension().map_or(false, |ext| ext == "mel")
+            path.is_file() && path.extension().is_some_and(|ext| ext == "mel")
         })
         .collect();
 
@@ -62,23 +78,27 @@ fn load_directory(dir: &Path) -> Result<LoadedSource> {
             _ => a_name.cmp(b_name),
         }
     });
+    Ok(mel_files)
+}
 
-    // Read and concatenate all files
+/// Concatenate the (name, text) of each file of a directory score, in order.
+pub fn combine(dir: &Path, files: Vec<(String, String)>) -> LoadedSource {
     let mut combined = String::new();
-    for (i, file_path) in mel_files.iter().enumerate() {
-        let content = fs::read_to_string(file_path)
-            .with_context(|| format!("Failed to read file: {:?}", file_path))?;
-
+    let mut sources = SourceMap::default();
+    let count = files.len();
+    for (i, (name, content)) in files.into_iter().enumerate() {
+        sources.add(name, combined.len(), content.as_str());
         combined.push_str(&content);
 
         // Add newline between files if the content doesn't end with one
-        if i < mel_files.len() - 1 && !content.ends_with('\n') {
+        if i < count - 1 && !content.ends_with('\n') {
             combined.push('\n');
         }
     }
 
-    Ok(LoadedSource {
+    LoadedSource {
         source: combined,
         base_path: dir.to_path_buf(),
-    })
+        sources,
+    }
 }
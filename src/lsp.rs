@@ -0,0 +1,655 @@
+//! Language server for `.mel` files, run by the `melos-lsp` binary over stdio.
+//!
+//! A document that sits in a directory with a `score.mel` is checked as part of
+//! that directory's score, the way `melos compile <dir>` reads it.
+
+use crate::ast::*;
+use crate::accidentals::apply_key;
+use crate::diagnostics::{Diagnostic, Severity, SourceMap};
+use crate::format;
+use crate::grammar::{MusicParser, Rule};
+use crate::instruments::{get_instrument_program, instrument_names};
+use crate::loader::{combine, score_files};
+use crate::motifs::expand_motifs;
+use crate::parser::{parse, parse_pitch};
+use crate::walker::{calculate_pitch, dynamic_to_velocity, events_length, pitch_cents, voices_of, walk_with_diagnostics, WalkOptions};
+use anyhow::Result;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionResponse, DiagnosticSeverity, DocumentSymbol,
+    DocumentSymbolResponse, GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use pest::Parser;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+const DYNAMICS: [&str; 8] = ["ppp", "pp", "p", "mp", "mf", "f", "ff", "fff"];
+
+/// Keywords that start a line, with an example of each.
+const KEYWORDS: [(&str, &str); 15] = [
+    ("Title", "Title: \"Suite in G\""),
+    ("Copyright", "Copyright: \"2025 Name\""),
+    ("Text", "Text: \"Performance note\""),
+    ("Tempo", "Tempo: 120"),
+    ("Time", "Time: 4/4"),
+    ("Key", "Key: G \"Major\""),
+    ("Swing", "Swing: e 0.67"),
+    ("Articulation", "Articulation: staccato gate 0.5"),
+    ("PPQ", "PPQ: 960"),
+    ("BendRange", "BendRange: 2"),
+    ("Accidentals", "Accidentals: Key"),
+    ("Motif", "Motif: name { C4 q D4 q }"),
+    ("Part", "Part: \"Piano\" Instrument: Piano { ... }"),
+    ("Accel", "Accel: 140 over 8"),
+    ("Rit", "Rit: 60 over 4"),
+];
+
+/// One document as the compiler sees it, alone or within its directory's score.
+pub struct Analysis {
+    source: String, // Text that was parsed
+    sources: SourceMap, // Files of `source`, named by URI
+    current: usize, // Index in `sources` of the document
+    score: Option<Score>,
+    problems: Vec<Diagnostic>,
+    parts: Vec<(Span, Span)>, // Whole part and its name, for each part of `score`
+    motifs: Vec<(String, Span)>, // Motif definitions
+    pitches: Vec<(Span, Pitch)>, // Every written pitch, in source order
+}
+
+impl Analysis {
+    /// Check a document on its own.
+    pub fn single(uri: &Url, text: &str) -> Self {
+        Analysis::new(text.to_string(), SourceMap::single(uri.as_str(), text), 0)
+    }
+
+    /// Check the files of `sources`, combined into `source`, reporting on file `current`.
+    pub fn new(source: String, sources: SourceMap, current: usize) -> Self {
+        let mut analysis = Analysis {
+            source,
+            sources,
+            current,
+            score: None,
+            problems: Vec::new(),
+            parts: Vec::new(),
+            motifs: Vec::new(),
+            pitches: Vec::new(),
+        };
+        let score = match parse(&analysis.source) {
+            Ok(score) => score,
+            Err(err) => {
+                analysis.problems.push(diagnostic(err));
+                return analysis;
+            }
+        };
+        match walk_with_diagnostics(&score, &WalkOptions::default()) {
+            Ok((_, warnings)) => analysis.problems = warnings,
+            Err(err) => analysis.problems.push(diagnostic(err)),
+        }
+        if let Ok(pairs) = MusicParser::parse(Rule::score, &analysis.source) {
+            for pair in pairs.flatten() {
+                let span = Span::from(pair.as_span());
+                match pair.as_rule() {
+                    Rule::part => {
+                        let name = pair.into_inner().find(|p| p.as_rule() == Rule::part_name);
+                        analysis.parts.push((span, name.map_or(span, |name| name.as_span().into())));
+                    }
+                    Rule::motif => {
+                        let name = pair.into_inner().find(|p| p.as_rule() == Rule::identifier);
+                        analysis.motifs.push((name.map_or_else(String::new, |name| name.as_str().to_string()), span));
+                    }
+                    Rule::pitch => {
+                        if let Ok(pitch) = parse_pitch(pair) {
+                            analysis.pitches.push((span, pitch));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        analysis.score = Some(score);
+        analysis
+    }
+
+    /// Problems in the document: parse errors, the first compile error and any warnings.
+    pub fn diagnostics(&self) -> Vec<lsp_types::Diagnostic> {
+        self.problems.iter().filter_map(|problem| {
+            let range = match problem.span {
+                Some(span) => self.range(span)?,
+                None => Range::default(),
+            };
+            let mut message = problem.message.clone();
+            if let Some(hint) = &problem.hint {
+                message.push_str(&format!("\nhelp: {}", hint));
+            }
+            Some(lsp_types::Diagnostic {
+                range,
+                severity: Some(match problem.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("melos".to_string()),
+                message,
+                ..Default::default()
+            })
+        }).collect()
+    }
+
+    /// Instrument names after `Instrument:`, keywords at the start of a line, dynamics elsewhere.
+    pub fn completions(&self, position: Position) -> Vec<CompletionItem> {
+        let text = self.text();
+        let offset = offset(text, position);
+        let line = &text[text[..offset].rfind('\n').map_or(0, |i| i + 1)..offset];
+        let item = |label: &str, kind, detail: String| CompletionItem {
+            label: label.to_string(),
+            kind: Some(kind),
+            detail: Some(detail),
+            ..Default::default()
+        };
+
+        if let Some(at) = line.rfind("Instrument:")
+            && !line[at..].contains('{')
+        {
+            let mut items: Vec<_> = instrument_names()
+                .map(|name| {
+                    let program = get_instrument_program(name).unwrap_or_default();
+                    item(name, CompletionItemKind::VALUE, format!("General MIDI program {}", program))
+                })
+                .collect();
+            items.push(item("Drums", CompletionItemKind::VALUE, "General MIDI drum channel".to_string()));
+            return items;
+        }
+
+        let before_word = line.trim_end_matches(|c: char| c.is_alphanumeric() || c == '#');
+        if before_word.trim().is_empty() {
+            KEYWORDS.iter().map(|(keyword, example)| CompletionItem {
+                insert_text: Some(format!("{}: ", keyword)),
+                ..item(keyword, CompletionItemKind::KEYWORD, example.to_string())
+            }).collect()
+        } else {
+            DYNAMICS.iter().map(|dynamic| {
+                item(dynamic, CompletionItemKind::VALUE, format!("Velocity {}", dynamic_to_velocity(dynamic)))
+            }).collect()
+        }
+    }
+
+    /// The MIDI number of the pitch under the cursor and the length of its measure in beats.
+    pub fn hover(&self, position: Position) -> Option<Hover> {
+        let score = self.score.as_ref()?;
+        let at = self.start() + offset(self.text(), position);
+        let found = find_measure(score, at);
+        let mut lines = Vec::new();
+
+        if let Some(index) = self.pitches.iter().position(|(span, _)| span.start <= at && at <= span.end) {
+            let (span, written) = self.pitches[index];
+            let pitch = self.spelled(score, found.as_ref(), span).unwrap_or(written);
+            let mut line = format!("`{}`", format::pitch(&written));
+            if pitch != written {
+                line.push_str(&format!(" sounds as `{}`", format::pitch(&pitch)));
+            }
+            match calculate_pitch(&pitch) {
+                Ok(midi) => {
+                    line.push_str(&format!(": MIDI {}", midi));
+                    match pitch_cents(&pitch) {
+                        0 => {}
+                        cents => line.push_str(&format!(" {:+} cents", cents)),
+                    }
+                }
+                Err(_) => line.push_str(": outside the MIDI range"),
+            }
+            lines.push(line);
+        }
+
+        if let Some(found) = &found {
+            lines.push(beats_text(score, found));
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: lines.join("\n\n") }),
+            range: None,
+        })
+    }
+
+    /// The first header of the part whose name is under the cursor.
+    pub fn definition(&self, position: Position) -> Option<Location> {
+        let score = self.score.as_ref()?;
+        let at = self.start() + offset(self.text(), position);
+        let index = self.parts.iter().position(|(_, name)| name.start <= at && at <= name.end)?;
+        let first = score.parts.iter().position(|part| part.name == score.parts[index].name)?;
+        let name = self.parts[first].1;
+        let file = self.sources.files().iter().rposition(|file| file.start <= name.start)?;
+        Some(Location {
+            uri: Url::parse(&self.sources.files()[file].name).ok()?,
+            range: self.range_in(file, name)?,
+        })
+    }
+
+    /// The parts of the document, each with its measures.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        let Some(score) = &self.score else {
+            return Vec::new();
+        };
+        let mut numbers: HashMap<&str, usize> = HashMap::new(); // Measures so far in each part
+        score.parts.iter().zip(&self.parts).filter_map(|(part, &(whole, name))| {
+            let mut measures = Vec::new();
+            for_each_measure(&part.content, &mut |measure| measures.push(measure));
+            let count = numbers.entry(&part.name).or_default();
+            let first = *count;
+            *count += measures.len();
+            let children = measures.iter().enumerate().filter_map(|(i, measure)| {
+                let range = self.range(measure.span)?;
+                let detail = match measure.kind {
+                    MeasureKind::Regular => None,
+                    MeasureKind::Pickup => Some("Pickup".to_string()),
+                    MeasureKind::Irregular => Some("Irregular".to_string()),
+                };
+                Some(symbol(format!("Measure {}", first + i + 1), detail, SymbolKind::FIELD, range, range, None))
+            }).collect();
+            let detail = Some(part.instrument.clone());
+            Some(symbol(part.name.clone(), detail, SymbolKind::CLASS, self.range(whole)?, self.range(name)?, Some(children)))
+        }).collect()
+    }
+
+    /// The pitch at `span` as it sounds once `Accidentals: Key` has spelled it.
+    fn spelled(&self, score: &Score, found: Option<&Found>, span: Span) -> Option<Pitch> {
+        if !score.headers.contains(&Header::Accidentals(AccidentalMode::Key)) {
+            return None;
+        }
+        // Pitches are spelled per voice of a measure, or per motif in the score's key
+        let contains = |outer: Span, inner: Span| outer.start <= inner.start && inner.end <= outer.end;
+        let (events, key, region, mut tied): (&[Event], _, Box<dyn Fn(Span) -> bool>, _) = match found {
+            Some(found) => {
+                let measure = found.measure;
+                match measure.voices.iter().find(|voice| contains(voice.span, span)) {
+                    Some(voice) => (&voice.events, found.key, Box::new(move |s| contains(voice.span, s)), tied_into(found, voice.number)),
+                    None => (&measure.events, found.key, Box::new(move |s| {
+                        contains(measure.span, s) && !measure.voices.iter().any(|voice| contains(voice.span, s))
+                    }), tied_into(found, 1)),
+                }
+            }
+            None => {
+                let (name, motif) = self.motifs.iter().find(|(_, motif)| contains(*motif, span))?;
+                (score.motifs.get(name)?, header_key(score), Box::new(move |s| contains(*motif, s)), Vec::new())
+            }
+        };
+        let index = self.pitches.iter().filter(|(s, _)| region(*s)).position(|(s, _)| s.start == span.start)?;
+        let mut events = events.to_vec();
+        apply_key(&mut events, key, &mut tied).ok()?;
+        let mut pitches = Vec::new();
+        written_pitches(&events, &mut pitches);
+        pitches.get(index).copied()
+    }
+
+    fn text(&self) -> &str {
+        &self.sources.files()[self.current].text
+    }
+
+    fn start(&self) -> usize {
+        self.sources.files()[self.current].start
+    }
+
+    /// Where `span` of the parsed source lies in the document, if it is in the document.
+    fn range(&self, span: Span) -> Option<Range> {
+        self.range_in(self.current, span)
+    }
+
+    fn range_in(&self, file: usize, span: Span) -> Option<Range> {
+        let file = &self.sources.files()[file];
+        let end = file.start + file.text.len();
+        if span.start < file.start || span.start > end {
+            return None;
+        }
+        let local = |offset: usize| position(&file.text, offset.clamp(file.start, end) - file.start);
+        Some(Range::new(local(span.start), local(span.end)))
+    }
+}
+
+/// A measure with the context it is read in.
+struct Found<'a> {
+    measure: &'a Measure,
+    number: usize, // Counted from 1 through the blocks of a part, as the walker does
+    time: (u32, u32),
+    key: Option<Key>,
+    previous: Option<(&'a Measure, Option<Key>)>, // The measure written before it, and its key
+}
+
+/// Spelled pitches that voice `number` of the previous measure ties into the found one.
+fn tied_into(found: &Found, number: u8) -> Vec<Pitch> {
+    let mut tied = Vec::new();
+    if let Some((previous, key)) = found.previous
+        && let Some((_, events, _)) = voices_of(previous).into_iter().find(|(voice, _, _)| *voice == number)
+    {
+        let mut events = events.to_vec();
+        if apply_key(&mut events, key, &mut tied).is_err() {
+            tied.clear();
+        }
+    }
+    tied
+}
+
+fn find_measure(score: &Score, at: usize) -> Option<Found<'_>> {
+    let mut header_time = (4, 4);
+    for header in &score.headers {
+        if let Header::TimeSignature(num, den) = header {
+            header_time = (*num, *den);
+        }
+    }
+    // Each block starts from the headers; blocks with the same name continue the numbering
+    let mut numbers: HashMap<&str, usize> = HashMap::new();
+    for part in &score.parts {
+        let number = numbers.entry(&part.name).or_default();
+        let (mut time, mut key) = (header_time, header_key(score));
+        let mut found = None;
+        find_in(&part.content, at, number, &mut time, &mut key, &mut None, &mut found);
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+fn find_in<'a>(
+    blocks: &'a [MeasureBlock],
+    at: usize,
+    number: &mut usize,
+    time: &mut (u32, u32),
+    key: &mut Option<Key>,
+    previous: &mut Option<(&'a Measure, Option<Key>)>,
+    found: &mut Option<Found<'a>>,
+) {
+    for block in blocks {
+        if found.is_some() {
+            return;
+        }
+        match block {
+            MeasureBlock::Measure(measure) => {
+                *number += 1;
+                if measure.span.start <= at && at <= measure.span.end {
+                    *found = Some(Found { measure, number: *number, time: *time, key: *key, previous: *previous });
+                }
+                *previous = Some((measure, *key));
+            }
+            MeasureBlock::ContextChange(ContextChange::TimeSignature(num, den)) => *time = (*num, *den),
+            MeasureBlock::ContextChange(ContextChange::KeySignature(new)) => *key = Some(*new),
+            MeasureBlock::ContextChange(_) | MeasureBlock::Navigation(_) => {}
+            MeasureBlock::Repeat(repeat) => {
+                find_in(&repeat.body, at, number, time, key, previous, found);
+                for ending in &repeat.endings {
+                    find_in(&ending.body, at, number, time, key, previous, found);
+                }
+            }
+        }
+    }
+}
+
+fn for_each_measure<'a>(blocks: &'a [MeasureBlock], visit: &mut impl FnMut(&'a Measure)) {
+    for block in blocks {
+        match block {
+            MeasureBlock::Measure(measure) => visit(measure),
+            MeasureBlock::Repeat(repeat) => {
+                for_each_measure(&repeat.body, visit);
+                for ending in &repeat.endings {
+                    for_each_measure(&ending.body, visit);
+                }
+            }
+            MeasureBlock::ContextChange(_) | MeasureBlock::Navigation(_) => {}
+        }
+    }
+}
+
+/// E.g. "Measure 3 in 3/4: 2 of 3 beats", with the length of each voice.
+fn beats_text(score: &Score, found: &Found) -> String {
+    let (num, den) = found.time;
+    let label = match found.measure.kind {
+        MeasureKind::Regular => "Measure",
+        MeasureKind::Pickup => "Pickup measure",
+        MeasureKind::Irregular => "Irregular measure",
+    };
+    let voices = voices_of(found.measure);
+    let lengths: Vec<String> = voices.iter().map(|&(number, events, _)| {
+        let length = expand_motifs(events, &score.motifs, found.key)
+            .and_then(|events| events_length(&events))
+            .map_or_else(|_| "?".to_string(), |length| (length * den as u64).to_string());
+        let beats = format!("{} of {} beats", length, num);
+        if voices.len() > 1 { format!("V{} {}", number, beats) } else { beats }
+    }).collect();
+    format!("**{} {}** in {}/{}: {}", label, found.number, num, den, lengths.join(", "))
+}
+
+fn header_key(score: &Score) -> Option<Key> {
+    score.headers.iter().rev().find_map(|header| match header {
+        Header::KeySignature(key) => Some(*key),
+        _ => None,
+    })
+}
+
+/// Pitches of `events` in the order they are written.
+fn written_pitches(events: &[Event], out: &mut Vec<Pitch>) {
+    for event in events {
+        match event {
+            Event::Note(note) => {
+                if let Some(grace) = &note.grace {
+                    out.extend(&grace.pitches);
+                }
+                out.push(note.pitch);
+            }
+            Event::Chord(pitches, ..) => out.extend(pitches),
+            Event::Tuplet(tuplet) => written_pitches(&tuplet.events, out),
+            _ => {}
+        }
+    }
+}
+
+#[allow(deprecated)] // `DocumentSymbol::deprecated` must still be given
+fn symbol(
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    range: Range,
+    selection_range: Range,
+    children: Option<Vec<DocumentSymbol>>,
+) -> DocumentSymbol {
+    DocumentSymbol { name, detail, kind, tags: None, deprecated: None, range, selection_range, children }
+}
+
+fn diagnostic(err: anyhow::Error) -> Diagnostic {
+    match err.downcast::<Diagnostic>() {
+        Ok(diagnostic) => diagnostic,
+        Err(err) => Diagnostic { severity: Severity::Error, message: format!("{:#}", err), span: None, hint: None },
+    }
+}
+
+/// The LSP position of a byte offset in `text`, with columns in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(before.matches('\n').count() as u32, before[line_start..].encode_utf16().count() as u32)
+}
+
+/// The byte offset of an LSP position in `text`, clamped to its line.
+fn offset(text: &str, position: Position) -> usize {
+    let mut start = 0;
+    for _ in 0..position.line {
+        match text[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= position.character {
+            return start + i;
+        }
+        units += c.len_utf16() as u32;
+    }
+    start + line.len()
+}
+
+/// Serve the language server protocol over stdin and stdout until the client exits.
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".to_string()]),
+            ..Default::default()
+        }),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    Server { connection: &connection, documents: HashMap::new() }.serve()?;
+    // The writer thread finishes once the connection is closed
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: HashMap<Url, String>, // Text of each open document
+}
+
+impl Server<'_> {
+    fn serve(&mut self) -> Result<()> {
+        for message in &self.connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.respond(request);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.notify(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            HoverRequest::METHOD => self.answer::<HoverRequest>(request, |server, params| {
+                let position = params.text_document_position_params;
+                server.analysis(&position.text_document.uri)?.hover(position.position)
+            }),
+            Completion::METHOD => self.answer::<Completion>(request, |server, params| {
+                let position = params.text_document_position;
+                let items = server.analysis(&position.text_document.uri)?.completions(position.position);
+                Some(CompletionResponse::Array(items))
+            }),
+            GotoDefinition::METHOD => self.answer::<GotoDefinition>(request, |server, params| {
+                let position = params.text_document_position_params;
+                let location = server.analysis(&position.text_document.uri)?.definition(position.position)?;
+                Some(GotoDefinitionResponse::Scalar(location))
+            }),
+            DocumentSymbolRequest::METHOD => self.answer::<DocumentSymbolRequest>(request, |server, params| {
+                let symbols = server.analysis(&params.text_document.uri)?.symbols();
+                Some(DocumentSymbolResponse::Nested(symbols))
+            }),
+            _ => Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("Unsupported request {}", request.method)),
+        }
+    }
+
+    fn answer<R: lsp_types::request::Request>(&self, request: Request, answer: impl FnOnce(&Self, R::Params) -> R::Result) -> Response {
+        match serde_json::from_value(request.params) {
+            Ok(params) => Response::new_ok(request.id, answer(self, params)),
+            Err(err) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string()),
+        }
+    }
+
+    fn notify(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.insert(params.text_document.uri.clone(), params.text_document.text);
+                self.publish(&params.text_document.uri)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                // Changes carry the whole text, as requested by `TextDocumentSyncKind::FULL`
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(params.text_document.uri.clone(), change.text);
+                }
+                self.publish(&params.text_document.uri)?;
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: lsp_types::DidSaveTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.publish(&params.text_document.uri)?;
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.send_diagnostics(params.text_document.uri.clone(), Vec::new())?;
+                self.publish(&params.text_document.uri)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Publish diagnostics for `uri` and the other open files of its score.
+    fn publish(&self, uri: &Url) -> Result<()> {
+        let directory = score_directory(uri);
+        for open in self.documents.keys() {
+            if open == uri || (directory.is_some() && score_directory(open) == directory) {
+                let diagnostics = self.analysis(open).map_or_else(Vec::new, |analysis| analysis.diagnostics());
+                self.send_diagnostics(open.clone(), diagnostics)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_diagnostics(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams { uri, diagnostics, version: None };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(Message::Notification(notification))?;
+        Ok(())
+    }
+
+    /// Check an open document, reading the rest of its score from disk unless open too.
+    fn analysis(&self, uri: &Url) -> Option<Analysis> {
+        let text = self.documents.get(uri)?;
+        if let Some(directory) = score_directory(uri) {
+            let mut files = Vec::new();
+            let mut current = None;
+            for path in score_files(&directory).unwrap_or_default() {
+                let Ok(file) = Url::from_file_path(&path) else { continue };
+                let text = match self.documents.get(&file) {
+                    Some(text) => text.clone(),
+                    None => fs::read_to_string(&path).unwrap_or_default(),
+                };
+                if file == *uri {
+                    current = Some(files.len());
+                }
+                files.push((file.to_string(), text));
+            }
+            if let Some(current) = current {
+                let loaded = combine(&directory, files);
+                return Some(Analysis::new(loaded.source, loaded.sources, current));
+            }
+        }
+        Some(Analysis::single(uri, text))
+    }
+}
+
+/// The directory of a file that belongs to a multi-file score, one with a `score.mel`.
+fn score_directory(uri: &Url) -> Option<PathBuf> {
+    let path = uri.to_file_path().ok()?;
+    let directory = path.parent()?;
+    directory.join("score.mel").is_file().then(|| directory.to_path_buf())
+}
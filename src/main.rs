@@ -2,11 +2,18 @@ use clap::{Parser, Subcommand};
 use anyhow::{Context, Result};
 use std::path::PathBuf;
 use melos::parser::parse;
-use melos::walker::walk;
+use melos::walker::{walk_with_diagnostics, WalkOptions};
 use melos::codegen::generate;
+use melos::diagnostics::{Diagnostic, SourceMap};
 use melos::loader::load_source;
-
-pub mod inspect;
+use melos::inspect;
+use melos::musicxml;
+use melos::import::{import_file, ImportOptions};
+use melos::format::{format_files, FormatOptions};
+use melos::ir::IrScore;
+use melos::render::{render, wav, RenderOptions};
+use melos::ast::Score;
+use melos::loader::LoadedSource;
 
 #[derive(Parser)]
 #[command(author, version, about = "Melos - A music composition language", long_about = None)]
@@ -18,9 +25,17 @@ struct Cli {
     #[arg(value_name = "FILE")]
     input: Option<PathBuf>,
 
-    /// Output MIDI file
+    /// Output MIDI file, or MusicXML when it ends in .musicxml or .xml
     #[arg(short, long, value_name = "FILE")]
     output: Option<PathBuf>,
+
+    /// Fail on measures whose length does not match the time signature
+    #[arg(long)]
+    strict: bool,
+
+    /// MIDI resolution in ticks per quarter note (chosen from the score by default)
+    #[arg(long)]
+    ppq: Option<u32>,
 }
 
 #[derive(Subcommand)]
@@ -34,6 +49,28 @@ enum Commands {
         /// Output MIDI file
         #[arg(short, long, value_name = "FILE")]
         output: Option<PathBuf>,
+
+        /// Fail on measures whose length does not match the time signature
+        #[arg(long)]
+        strict: bool,
+
+        /// MIDI resolution in ticks per quarter note (chosen from the score by default)
+        #[arg(long)]
+        ppq: Option<u32>,
+    },
+    /// Render a Melos file or directory to a WAV file with the built-in synthesizer
+    Render {
+        /// Input Melos file or directory containing .mel files
+        #[arg(value_name = "PATH")]
+        input: PathBuf,
+
+        /// Output WAV file
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Samples per second
+        #[arg(long, default_value_t = 44100, value_parser = clap::value_parser!(u32).range(8000..=192000))]
+        sample_rate: u32,
     },
     /// Inspect a MIDI file
     Inspect {
@@ -41,6 +78,34 @@ enum Commands {
         #[arg(value_name = "FILE")]
         input: PathBuf,
     },
+    /// Rewrite Melos files in canonical layout
+    Fmt {
+        /// Melos file or directory containing .mel files
+        #[arg(value_name = "PATH")]
+        input: PathBuf,
+
+        /// List files that are not formatted instead of rewriting them, failing if there are any
+        #[arg(long)]
+        check: bool,
+
+        /// Number of measures written on each line
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        measures_per_line: u16,
+    },
+    /// Write a MIDI file as Melos source
+    Import {
+        /// Input MIDI file
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+
+        /// Output Melos file (printed to stdout by default)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Steps per quarter note that notes snap to: 1, 2, 4, 8 or 16
+        #[arg(long, default_value_t = 4)]
+        grid: u32,
+    },
 }
 
 fn main() -> Result<()> {
@@ -48,21 +113,51 @@ fn main() -> Result<()> {
 
     // Handle direct file argument (default to compile)
     if let Some(input) = cli.input {
-        return compile(&input, cli.output.as_ref());
+        let options = WalkOptions { strict: cli.strict, ppq: cli.ppq, ..Default::default() };
+        return compile(&input, cli.output.as_ref(), &options);
     }
 
     // Handle subcommands
     match cli.command {
-        Some(Commands::Compile { input, output }) => {
-            compile(&input, output.as_ref())
+        Some(Commands::Compile { input, output, strict, ppq }) => {
+            let options = WalkOptions { strict, ppq, ..Default::default() };
+            compile(&input, output.as_ref(), &options)
+        }
+        Some(Commands::Render { input, output, sample_rate }) => {
+            let (loaded, _, ir) = build(&input, &WalkOptions::default())?;
+            let samples = render(&ir, &RenderOptions { sample_rate })?;
+            let output_path = output.unwrap_or_else(|| default_output(&loaded, "wav"));
+            std::fs::write(&output_path, wav(&samples, sample_rate))
+                .with_context(|| format!("Failed to write WAV file: {:?}", output_path))?;
+            println!("Rendered {:?} → {:?}", loaded.base_path, output_path);
+            Ok(())
         }
         Some(Commands::Inspect { input }) => {
             inspect::inspect(&input)
         }
+        Some(Commands::Fmt { input, check, measures_per_line }) => {
+            let options = FormatOptions { measures_per_line: measures_per_line as usize };
+            fmt(&input, check, &options)
+        }
+        Some(Commands::Import { input, output, grid }) => {
+            let source = import_file(&input, &ImportOptions { grid })?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, source)
+                        .with_context(|| format!("Failed to write Melos file: {:?}", path))?;
+                    println!("Imported {:?} → {:?}", input, path);
+                }
+                None => print!("{}", source),
+            }
+            Ok(())
+        }
         None => {
             // No input and no subcommand - show help
             eprintln!("Usage: melos <FILE> or melos compile <FILE>");
+            eprintln!("       melos render <FILE> -o <FILE.wav>");
             eprintln!("       melos inspect <FILE.mid>");
+            eprintln!("       melos import <FILE.mid>");
+            eprintln!("       melos fmt <PATH>");
             eprintln!();
             eprintln!("Run 'melos --help' for more information.");
             std::process::exit(1);
@@ -70,43 +165,113 @@ fn main() -> Result<()> {
     }
 }
 
-fn compile(input: &PathBuf, output: Option<&PathBuf>) -> Result<()> {
+fn compile(input: &PathBuf, output: Option<&PathBuf>, options: &WalkOptions) -> Result<()> {
+    let (loaded, ast, ir) = build(input, options)?;
+
+    // An output ending in .musicxml or .xml asks for notation instead of MIDI
+    if let Some(path) = output.filter(|path| is_musicxml(path)) {
+        let xml = musicxml::export(&ast, &ir)
+            .map_err(|e| report(e, &loaded.sources))
+            .context("Failed to export MusicXML")?;
+        std::fs::write(path, xml)
+            .with_context(|| format!("Failed to write MusicXML file: {:?}", path))?;
+        println!("Exported {:?} → {:?}", loaded.base_path, path);
+        return Ok(());
+    }
+
+    // 4. Codegen (IR -> MIDI)
+    let smf = generate(&ir)
+        .context("Failed to generate MIDI")?;
+
+    // 5. Write Output
+    let output_path = output.cloned().unwrap_or_else(|| default_output(&loaded, "mid"));
+
+    smf.save(&output_path)
+        .with_context(|| format!("Failed to write MIDI file: {:?}", output_path))?;
+
+    println!("Compiled {:?} → {:?}", loaded.base_path, output_path);
+
+    Ok(())
+}
+
+/// Load, parse and walk a score, printing its warnings.
+fn build(input: &PathBuf, options: &WalkOptions) -> Result<(LoadedSource, Score, IrScore)> {
     // 1. Load source (handles both files and directories)
     let loaded = load_source(input)
         .with_context(|| format!("Failed to load source from: {:?}", input))?;
 
     // 2. Parse
     let ast = parse(&loaded.source)
+        .map_err(|e| report(e, &loaded.sources))
         .context("Failed to parse Melos")?;
 
     // 3. Walk (AST -> IR)
-    let ir = walk(&ast)
+    let (ir, warnings) = walk_with_diagnostics(&ast, options)
+        .map_err(|e| report(e, &loaded.sources))
         .context("Failed to generate IR")?;
+    for warning in &warnings {
+        eprintln!("{}", warning.render(&loaded.sources));
+    }
 
-    // 4. Codegen (IR -> MIDI)
-    let smf = generate(&ir)
-        .context("Failed to generate MIDI")?;
+    Ok((loaded, ast, ir))
+}
 
-    // 5. Write Output
-    let output_path = output.cloned().unwrap_or_else(|| {
-        if loaded.base_path.is_dir() {
-            // For directories, create .mid file with directory name
-            let dir_name = loaded.base_path.file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("output");
-            loaded.base_path.join(format!("{}.mid", dir_name))
+/// The file next to the source, or inside a score directory named after it.
+fn default_output(loaded: &LoadedSource, extension: &str) -> PathBuf {
+    if loaded.base_path.is_dir() {
+        // For directories, create the file with the directory name
+        let dir_name = loaded.base_path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("output");
+        loaded.base_path.join(format!("{}.{}", dir_name, extension))
+    } else {
+        // For files, replace extension
+        let mut p = loaded.base_path.clone();
+        p.set_extension(extension);
+        p
+    }
+}
+
+fn fmt(input: &PathBuf, check: bool, options: &FormatOptions) -> Result<()> {
+    let loaded = load_source(input)
+        .with_context(|| format!("Failed to load source from: {:?}", input))?;
+    let formatted = format_files(&loaded.source, &loaded.sources, options)
+        .map_err(|e| report(e, &loaded.sources))
+        .context("Failed to parse Melos")?;
+
+    let mut unformatted = 0;
+    for (file, text) in loaded.sources.files().iter().zip(formatted) {
+        if file.text == text {
+            continue;
+        }
+        if check {
+            println!("Not formatted: {}", file.name);
+            unformatted += 1;
         } else {
-            // For files, replace extension
-            let mut p = loaded.base_path.clone();
-            p.set_extension("mid");
-            p
+            std::fs::write(&file.name, text)
+                .with_context(|| format!("Failed to write file: {:?}", file.name))?;
+            println!("Formatted {}", file.name);
         }
-    });
-
-    smf.save(&output_path)
-        .with_context(|| format!("Failed to write MIDI file: {:?}", output_path))?;
+    }
+    if unformatted > 0 {
+        anyhow::bail!("{} file(s) need formatting; run `melos fmt` to fix them", unformatted);
+    }
+    Ok(())
+}
 
-    println!("Compiled {:?} → {:?}", loaded.base_path, output_path);
+fn is_musicxml(path: &std::path::Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("musicxml") || ext.eq_ignore_ascii_case("xml"))
+}
 
-    Ok(())
+/// Print a located error with its source snippet; other errors pass through unchanged.
+fn report(err: anyhow::Error, sources: &SourceMap) -> anyhow::Error {
+    match err.downcast_ref::<Diagnostic>() {
+        Some(diagnostic) => {
+            eprintln!("{}", diagnostic.render(sources));
+            anyhow::anyhow!("aborting due to the error above")
+        }
+        None => err,
+    }
 }
@@ -0,0 +1,287 @@
+use crate::ast::*;
+use crate::diagnostics::Diagnostic;
+use crate::keys::{key_fifths, step_alterations, step_index, STEPS};
+use crate::walker::{calculate_pitch, pitch_cents};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+/// Replace every motif invocation in `events` with the motif's (transformed) events.
+///
+/// `key` is the key signature in effect, used for diatonic transposition and for
+/// spelling transposed pitches. Without one, C major is assumed.
+pub fn expand_motifs(
+    events: &[Event],
+    motifs: &BTreeMap<String, Vec<Event>>,
+    key: Option<Key>,
+) -> Result<Vec<Event>> {
+    expand_with_stack(events, motifs, key, &mut Vec::new())
+}
+
+fn expand_with_stack<'a>(
+    events: &[Event],
+    motifs: &'a BTreeMap<String, Vec<Event>>,
+    key: Option<Key>,
+    stack: &mut Vec<&'a str>,
+) -> Result<Vec<Event>> {
+    let mut expanded = Vec::with_capacity(events.len());
+    for event in events {
+        match event {
+            Event::MotifCall(call) => {
+                let (name, body) = motifs
+                    .get_key_value(&call.name)
+                    .ok_or_else(|| Diagnostic::error(format!("Unknown motif '@{}'", call.name), call.span)
+                        .with_hint("define it with `Motif: name { ... }`"))?;
+                if stack.contains(&name.as_str()) {
+                    return Err(Diagnostic::error(format!("Motif '@{}' invokes itself", call.name), call.span).into());
+                }
+
+                stack.push(name);
+                let mut body = expand_with_stack(body, motifs, key, stack)?;
+                stack.pop();
+
+                for transform in &call.transforms {
+                    body = apply_transform(body, transform, key)
+                        .map_err(|e| Diagnostic::locate(anyhow!("{} in motif '@{}'", e, call.name), call.span))?;
+                }
+                expanded.extend(body);
+            }
+            Event::Tuplet(tuplet) => {
+                expanded.push(Event::Tuplet(Tuplet {
+                    p: tuplet.p,
+                    q: tuplet.q,
+                    events: expand_with_stack(&tuplet.events, motifs, key, stack)?,
+                }));
+            }
+            other => expanded.push(other.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+fn apply_transform(mut events: Vec<Event>, transform: &MotifTransform, key: Option<Key>) -> Result<Vec<Event>> {
+    let fifths = || match key {
+        Some(key) => key_fifths(&key),
+        None => Ok(0),
+    };
+
+    match *transform {
+        MotifTransform::Transpose(semitones) => {
+            let prefer_flats = fifths().unwrap_or(0) < 0;
+            map_pitches(&mut events, &mut |pitch| {
+                let (pitch, cents) = temper(pitch);
+                Ok(detune(pitch_from_midi(calculate_pitch(&pitch)? as i32 + semitones, prefer_flats), cents))
+            })?;
+        }
+        MotifTransform::TransposeDiatonic(steps) => {
+            let fifths = fifths()?;
+            let alterations = step_alterations(fifths);
+            map_pitches(&mut events, &mut |pitch| {
+                let (pitch, cents) = temper(pitch);
+                let index = step_index(pitch.step)?;
+                // Keep any deviation from the key signature (e.g. a raised leading tone)
+                let deviation = accidental_value(pitch.accidental) - alterations[index];
+                let target = index as i32 + steps;
+                let new_index = target.rem_euclid(7) as usize;
+                let octave = pitch.octave + target.div_euclid(7);
+                let moved = match accidental_from_value(alterations[new_index] + deviation) {
+                    Some(accidental) => Pitch { step: STEPS[new_index], accidental, octave },
+                    None => {
+                        // Would need a double accidental; respell enharmonically
+                        let natural = Pitch { step: STEPS[new_index], accidental: None, octave };
+                        let midi = calculate_pitch(&natural)? as i32 + alterations[new_index] + deviation;
+                        pitch_from_midi(midi, fifths < 0)
+                    }
+                };
+                Ok(detune(moved, cents))
+            })?;
+        }
+        MotifTransform::Inversion => {
+            let prefer_flats = fifths().unwrap_or(0) < 0;
+            if let Some(first) = first_pitch(&events) {
+                // Mirror in cents, so that quarter tones invert too
+                let cents = |pitch: &Pitch| -> Result<i32> { Ok(calculate_pitch(pitch)? as i32 * 100 + pitch_cents(pitch) as i32) };
+                let axis = cents(&first)?;
+                map_pitches(&mut events, &mut |pitch| {
+                    let mirrored = 2 * axis - cents(&pitch)?;
+                    let tempered = pitch_from_midi(mirrored.div_euclid(100), prefer_flats);
+                    Ok(detune(tempered, mirrored.rem_euclid(100) as i16))
+                })?;
+            }
+        }
+        MotifTransform::Retrograde => reverse(&mut events),
+        MotifTransform::Augmentation(factor) => return Ok(scale_durations(events, factor, 1)),
+        MotifTransform::Diminution(factor) => return Ok(scale_durations(events, 1, factor)),
+    }
+    Ok(events)
+}
+
+fn map_pitches(events: &mut [Event], f: &mut impl FnMut(Pitch) -> Result<Pitch>) -> Result<()> {
+    for event in events {
+        match event {
+            Event::Note(note) => {
+                note.pitch = f(note.pitch)?;
+                if let Some(grace) = &mut note.grace {
+                    for pitch in &mut grace.pitches {
+                        *pitch = f(*pitch)?;
+                    }
+                }
+            }
+            Event::Chord(pitches, _, _, _) => {
+                for pitch in pitches {
+                    *pitch = f(*pitch)?;
+                }
+            }
+            Event::Tuplet(tuplet) => map_pitches(&mut tuplet.events, f)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn first_pitch(events: &[Event]) -> Option<Pitch> {
+    events.iter().find_map(|event| match event {
+        Event::Note(note) => Some(note.pitch),
+        Event::Chord(pitches, _, _, _) => pitches.first().copied(),
+        Event::Tuplet(tuplet) => first_pitch(&tuplet.events),
+        _ => None,
+    })
+}
+
+fn reverse(events: &mut [Event]) {
+    events.reverse();
+    for event in events {
+        if let Event::Tuplet(tuplet) = event {
+            reverse(&mut tuplet.events);
+        }
+    }
+}
+
+/// Multiply every duration by `num / den`. Power-of-two factors change the written
+/// note values; anything else (or values that would leave the w..s range) is
+/// expressed as a tuplet around the whole motif.
+fn scale_durations(events: Vec<Event>, num: u32, den: u32) -> Vec<Event> {
+    let shift = match (num.is_power_of_two(), den.is_power_of_two()) {
+        (true, true) => Some(den.trailing_zeros() as i32 - num.trailing_zeros() as i32),
+        _ => None,
+    };
+
+    if let Some(shift) = shift {
+        let mut rescaled = events.clone();
+        if shift_durations(&mut rescaled, shift) {
+            return rescaled;
+        }
+    }
+
+    vec![Event::Tuplet(Tuplet { p: den, q: num, events })]
+}
+
+fn shift_durations(events: &mut [Event], shift: i32) -> bool {
+    for event in events {
+        let ok = match event {
+            Event::Note(note) => shift_duration(&mut note.duration, shift),
+            Event::Chord(_, duration, _, _) => shift_duration(duration, shift),
+            Event::Rest(duration, _) => shift_duration(duration, shift),
+            Event::Drum(hit) => shift_duration(&mut hit.duration, shift),
+            Event::Tuplet(tuplet) => shift_durations(&mut tuplet.events, shift),
+            _ => true,
+        };
+        if !ok {
+            return false;
+        }
+    }
+    true
+}
+
+fn shift_duration(duration: &mut Option<Duration>, shift: i32) -> bool {
+    const BASES: [BaseDuration; 7] = [
+        BaseDuration::Whole,
+        BaseDuration::Half,
+        BaseDuration::Quarter,
+        BaseDuration::Eighth,
+        BaseDuration::Sixteenth,
+        BaseDuration::ThirtySecond,
+        BaseDuration::SixtyFourth,
+    ];
+    let shift_base = |base: BaseDuration| {
+        let index = BASES.iter().position(|&b| b == base).unwrap() as i32 + shift;
+        (0..BASES.len() as i32).contains(&index).then(|| BASES[index as usize])
+    };
+
+    let shifted = match duration {
+        None => shift_base(BaseDuration::Quarter).map(|base| Duration::Base(base, 0)),
+        Some(Duration::Base(base, dots)) => shift_base(*base).map(|base| Duration::Base(base, *dots)),
+        Some(Duration::Subdivision(base, n)) => shift_base(*base).map(|base| Duration::Subdivision(base, *n)),
+        Some(Duration::Fraction(num, den)) if shift >= 0 => den.checked_mul(1 << shift).map(|den| Duration::Fraction(*num, den)),
+        Some(Duration::Fraction(num, den)) => num.checked_mul(1 << -shift).map(|num| Duration::Fraction(num, *den)),
+    };
+    match shifted {
+        Some(shifted) => {
+            *duration = Some(shifted);
+            true
+        }
+        None => false,
+    }
+}
+
+fn accidental_value(accidental: Option<Accidental>) -> i32 {
+    match accidental {
+        Some(Accidental::Sharp) => 1,
+        Some(Accidental::Flat) => -1,
+        _ => 0, // Quarter tones are tempered before they are moved
+    }
+}
+
+/// Split a quarter tone into the tempered pitch it is bent from and the bend in cents.
+fn temper(pitch: Pitch) -> (Pitch, i16) {
+    let accidental = match pitch.accidental {
+        Some(Accidental::QuarterSharp) | Some(Accidental::QuarterFlat) => None,
+        Some(Accidental::ThreeQuarterSharp) => Some(Accidental::Sharp),
+        Some(Accidental::ThreeQuarterFlat) => Some(Accidental::Flat),
+        accidental => accidental,
+    };
+    (Pitch { accidental, ..pitch }, pitch_cents(&pitch))
+}
+
+/// Bend a tempered pitch by a quarter tone (`cents` of ±50), keeping its step.
+fn detune(pitch: Pitch, cents: i16) -> Pitch {
+    let accidental = match (pitch.accidental, cents.signum()) {
+        (accidental, 0) => accidental,
+        (None | Some(Accidental::Natural), 1) | (Some(Accidental::Sharp), -1) => Some(Accidental::QuarterSharp),
+        (None | Some(Accidental::Natural), _) | (Some(Accidental::Flat), 1) => Some(Accidental::QuarterFlat),
+        (Some(Accidental::Sharp), _) => Some(Accidental::ThreeQuarterSharp),
+        (Some(Accidental::Flat), _) => Some(Accidental::ThreeQuarterFlat),
+        (accidental, _) => accidental,
+    };
+    Pitch { accidental, ..pitch }
+}
+
+fn accidental_from_value(value: i32) -> Option<Option<Accidental>> {
+    match value {
+        -1 => Some(Some(Accidental::Flat)),
+        0 => Some(None),
+        1 => Some(Some(Accidental::Sharp)),
+        _ => None,
+    }
+}
+
+/// Spell a MIDI note number as a pitch, using sharps or flats for black keys.
+pub fn pitch_from_midi(midi: i32, prefer_flats: bool) -> Pitch {
+    const SHARPS: [(char, Option<Accidental>); 12] = [
+        ('C', None), ('C', Some(Accidental::Sharp)), ('D', None), ('D', Some(Accidental::Sharp)),
+        ('E', None), ('F', None), ('F', Some(Accidental::Sharp)), ('G', None),
+        ('G', Some(Accidental::Sharp)), ('A', None), ('A', Some(Accidental::Sharp)), ('B', None),
+    ];
+    const FLATS: [(char, Option<Accidental>); 12] = [
+        ('C', None), ('D', Some(Accidental::Flat)), ('D', None), ('E', Some(Accidental::Flat)),
+        ('E', None), ('F', None), ('G', Some(Accidental::Flat)), ('G', None),
+        ('A', Some(Accidental::Flat)), ('A', None), ('B', Some(Accidental::Flat)), ('B', None),
+    ];
+
+    let table = if prefer_flats { &FLATS } else { &SHARPS };
+    let (step, accidental) = table[midi.rem_euclid(12) as usize];
+    Pitch {
+        step,
+        accidental,
+        octave: midi.div_euclid(12) - 1,
+    }
+}
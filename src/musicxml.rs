@@ -0,0 +1,1031 @@
+use crate::accidentals::{apply_key, apply_key_to_motifs};
+use crate::ast::*;
+use crate::diagnostics::Diagnostic;
+use crate::drums::get_drum_note;
+use crate::instruments::{get_instrument_program, is_keyboard};
+use crate::ir::{IrScore, Mark};
+use crate::keys::key_fifths;
+use crate::motifs::expand_motifs;
+use crate::walker::{
+    base_denominator, calculate_pitch, duration_length, dynamic_to_velocity, lcm, pitch_cents, quarter_bpm,
+    voices_of, written, Position, Step, DEFAULT_PPQ, DRUM_CHANNEL,
+};
+use anyhow::{anyhow, Result};
+use num_rational::Ratio;
+use std::collections::BTreeMap;
+
+/// A written note value: the denominator of its fraction of a whole note, and its dots.
+type Value = (u64, u8);
+
+/// Write a score as a MusicXML 4.0 partwise document.
+///
+/// The export follows the score as written rather than as performed: pitches keep
+/// their spelling, durations their written values and repeats their barlines, so
+/// swing, articulation gates and realized ornaments never reach the page. `midi` is
+/// the walked score, whose channels the parts' MIDI instruments name.
+pub fn export(score: &Score, midi: &IrScore) -> Result<String> {
+    let mut defaults = Defaults {
+        time_signature: (4, 4),
+        key: None,
+        accidentals: AccidentalMode::Explicit,
+        motifs: &score.motifs,
+    };
+    let mut title = None;
+    let mut rights = None;
+    let mut tempo = None;
+    for header in &score.headers {
+        match header {
+            Header::Title(text) => title = Some(text),
+            Header::Copyright(text) => rights = Some(text),
+            Header::Tempo(mark) => tempo = Some(tempo_xml(mark)?),
+            Header::TimeSignature(num, den) => defaults.time_signature = (*num, *den),
+            Header::KeySignature(key) => defaults.key = Some(*key),
+            Header::Accidentals(mode) => defaults.accidentals = *mode,
+            _ => {}
+        }
+    }
+
+    // Parts with the same name continue one another, as they do in MIDI, and play on
+    // the channels the MIDI export gives them
+    let mut parts: Vec<XmlPart> = Vec::new();
+    for part in &score.parts {
+        let index = match parts.iter().position(|p| p.name == part.name) {
+            Some(index) => index,
+            None => {
+                let channel = midi.tracks.iter().find(|t| t.name == part.name).map_or(0, |t| t.channel);
+                parts.push(XmlPart::new(part, channel));
+                parts.len() - 1
+            }
+        };
+        // The score's tempo is marked once, above the first part
+        let directions = if index == 0 { tempo.take().into_iter().collect() } else { Vec::new() };
+        lay_out(part, &mut parts[index], &defaults, directions)
+            .map_err(|e| Diagnostic::locate(anyhow!("{} in part '{}'", e, part.name), part.span))?;
+    }
+    for part in &mut parts {
+        link(part);
+    }
+
+    // Enough divisions per quarter note to give every duration a whole number of them
+    let mut divisions = DEFAULT_PPQ as u64;
+    for part in &parts {
+        for written in part.notes() {
+            divisions = lcm(divisions, *(written.length * 4).denom());
+        }
+    }
+
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<score-partwise version="4.0">
+"#);
+    if let Some(title) = title {
+        xml.push_str(&format!("  <work>\n    <work-title>{}</work-title>\n  </work>\n", escape(title)));
+    }
+    xml.push_str("  <identification>\n");
+    if let Some(rights) = rights {
+        xml.push_str(&format!("    <rights>{}</rights>\n", escape(rights)));
+    }
+    xml.push_str("    <encoding>\n      <software>Melos</software>\n    </encoding>\n  </identification>\n");
+
+    xml.push_str("  <part-list>\n");
+    for (i, part) in parts.iter().enumerate() {
+        xml.push_str(&score_part_xml(part, &format!("P{}", i + 1)));
+    }
+    xml.push_str("  </part-list>\n");
+
+    for (i, part) in parts.iter().enumerate() {
+        let id = format!("P{}", i + 1);
+        xml.push_str(&format!("  <part id=\"{}\">\n", id));
+        let mut number = 0;
+        for (index, measure) in part.measures.iter().enumerate() {
+            if !measure.pickup {
+                number += 1;
+            }
+            let first = (index == 0).then(|| clef_xml(part));
+            xml.push_str(&measure_xml(measure, number, first.as_deref(), &id, divisions)?);
+        }
+        xml.push_str("  </part>\n");
+    }
+
+    xml.push_str("</score-partwise>\n");
+    Ok(xml)
+}
+
+/// Score-level settings that every part starts from.
+struct Defaults<'a> {
+    time_signature: (u32, u32),
+    key: Option<Key>,
+    accidentals: AccidentalMode,
+    motifs: &'a BTreeMap<String, Vec<Event>>,
+}
+
+/// A part of the exported score, gathered from every `Part:` block with its name.
+struct XmlPart {
+    name: String,
+    instrument: String,
+    program: u8,
+    channel: u8,
+    drums: BTreeMap<u8, String>, // Drum keys played, with the name first written for each
+    measures: Vec<XmlMeasure>,
+    shown: Option<(Option<Key>, (u32, u32))>, // Key and time signature last written
+    hairpin: bool, // A wedge is open
+    pedal: bool,
+}
+
+impl XmlPart {
+    fn new(part: &Part, channel: u8) -> Self {
+        XmlPart {
+            name: part.name.clone(),
+            instrument: part.instrument.clone(),
+            program: get_instrument_program(&part.instrument).unwrap_or(0),
+            channel,
+            drums: BTreeMap::new(),
+            measures: Vec::new(),
+            shown: None,
+            hairpin: false,
+            pedal: false,
+        }
+    }
+
+    fn percussion(&self) -> bool {
+        self.channel == DRUM_CHANNEL
+    }
+
+    fn notes(&self) -> impl Iterator<Item = &Written> {
+        self.measures.iter()
+            .flat_map(|measure| &measure.voices)
+            .flat_map(|(_, items)| items)
+            .filter_map(|item| match item {
+                Item::Note(written) => Some(written),
+                Item::Direction(_) => None,
+            })
+    }
+}
+
+struct XmlMeasure {
+    pickup: bool,
+    key: Option<String>, // Key signature markup, where it changes
+    time: Option<(u32, u32)>, // Time signature, where it changes
+    left: Vec<Mark>, // Barlines and signs at the start
+    right: Vec<Mark>, // Barlines and jumps at the end
+    directions: Vec<String>, // Tempo marks at the start
+    voices: Vec<(u8, Vec<Item>)>,
+}
+
+enum Item {
+    Note(Written),
+    Direction(String), // Markup placed before the next note of the voice
+}
+
+/// A note, chord, rest or drum hit with one written value. Durations no single
+/// value can show are written as several, tied.
+struct Written {
+    heads: Vec<Head>, // One per pitch or drum, or a single rest
+    length: Position, // Sounding length in whole notes; zero for grace notes
+    value: u64, // Note type, as the denominator of its fraction of a whole note
+    dots: u8,
+    modification: Option<(u64, u64)>, // Tuplet ratio as (actual, normal) notes
+    grace: Option<bool>, // Grace note, slashed when an acciaccatura
+    tie_to_next: bool, // `~` follows; linked to the next note of the voice
+    slides: Vec<(Slide, bool)>, // Slides starting (true) or ending here
+    slide_to_next: Option<Slide>,
+    tuplets: Vec<(u8, bool)>, // Tuplet brackets (number, starting) beginning or ending here
+    articulation: Option<String>,
+    ornament: Option<Ornament>,
+    fermata: bool,
+}
+
+struct Head {
+    kind: HeadKind,
+    tie_stop: bool,
+    tie_start: bool,
+}
+
+#[derive(Clone, Copy)]
+enum HeadKind {
+    Pitch(Pitch),
+    Drum(u8),
+    Rest,
+}
+
+/// Lay out one `Part:` block measure by measure, in written order.
+fn lay_out(part: &Part, xml: &mut XmlPart, defaults: &Defaults, mut directions: Vec<String>) -> Result<()> {
+    let mut time_signature = defaults.time_signature;
+    let mut key = defaults.key;
+    let mut left = Vec::new(); // Marks waiting for the next measure
+    let mut keyed_motifs = None;
+    let mut tied_over: BTreeMap<u8, Vec<Pitch>> = BTreeMap::new(); // Spelled pitches each voice ties into the next measure
+
+    let mut steps = Vec::new();
+    written(&part.content, 1, &mut steps);
+    for step in steps {
+        match step {
+            Step::Measure(measure, _) => {
+                // Spell key-relative pitches and expand motifs as the walker does
+                let key_relative = defaults.accidentals == AccidentalMode::Key;
+                if key_relative && keyed_motifs.as_ref().is_none_or(|(motif_key, _)| *motif_key != key) {
+                    keyed_motifs = Some((key, apply_key_to_motifs(defaults.motifs, key)?));
+                }
+                let motifs = keyed_motifs.as_ref().map_or(defaults.motifs, |(_, motifs)| motifs);
+
+                // Signatures are written at the start and wherever they change
+                let key_changed = xml.shown.is_none_or(|(shown, _)| shown != key);
+                let time_changed = xml.shown.is_none_or(|(_, shown)| shown != time_signature);
+                let mut layout = XmlMeasure {
+                    pickup: measure.kind == MeasureKind::Pickup && xml.measures.is_empty(),
+                    key: (key_changed && !xml.percussion()).then(|| key_xml(key)), // Drums read without a key
+                    time: time_changed.then_some(time_signature),
+                    left: std::mem::take(&mut left),
+                    right: Vec::new(),
+                    directions: std::mem::take(&mut directions),
+                    voices: Vec::new(),
+                };
+                xml.shown = Some((key, time_signature));
+
+                for (number, events, span) in voices_of(measure) {
+                    let mut events = events.to_vec();
+                    if key_relative {
+                        let tied = tied_over.entry(number).or_default();
+                        apply_key(&mut events, key, tied).map_err(|e| Diagnostic::locate(e, span))?;
+                    }
+                    let events = expand_motifs(&events, motifs, key).map_err(|e| Diagnostic::locate(e, span))?;
+                    let mut items = Vec::new();
+                    place(&events, xml, &mut items, (1, 1), 0).map_err(|e| Diagnostic::locate(e, span))?;
+                    layout.voices.push((number, items));
+                }
+                xml.measures.push(layout);
+            }
+            Step::Mark(mark) => match mark {
+                Mark::RepeatStart | Mark::EndingStart(_) | Mark::Navigation(Navigation::Segno | Navigation::Coda) => {
+                    left.push(mark);
+                }
+                _ => {
+                    if let Some(measure) = xml.measures.last_mut() {
+                        measure.right.push(mark);
+                    }
+                }
+            },
+            Step::ContextChange(change) => match change {
+                ContextChange::TimeSignature(num, den) => time_signature = (*num, *den),
+                ContextChange::KeySignature(new_key) => key = Some(*new_key),
+                ContextChange::Tempo(tempo) => directions.push(tempo_xml(tempo)?),
+                ContextChange::TempoRamp(ramp) => {
+                    let words = if ramp.accelerando { "accel." } else { "rit." };
+                    directions.push(direction_type_xml("above", &format!("<words>{}</words>", words)));
+                }
+                ContextChange::Swing(_) => {}
+            },
+        }
+    }
+
+    // A hairpin still open at the end of the part stops at the last barline
+    if xml.hairpin {
+        xml.hairpin = false;
+        if let Some((_, items)) = xml.measures.last_mut().and_then(|m| m.voices.first_mut()) {
+            items.push(Item::Direction(direction_type_xml("below", "<wedge type=\"stop\"/>")));
+        }
+    }
+    Ok(())
+}
+
+/// Lay out the events of one voice. `tuplet` is the (actual, normal) ratio of the
+/// enclosing tuplets and `depth` how many there are.
+fn place(events: &[Event], part: &mut XmlPart, items: &mut Vec<Item>, tuplet: (u64, u64), depth: u8) -> Result<()> {
+    for event in events {
+        match event {
+            Event::Note(note) => {
+                if let Some(dynamic) = &note.dynamic {
+                    dynamic_mark(part, items, dynamic);
+                }
+                if let Some(grace) = &note.grace {
+                    // A single grace note is written as an eighth, a group as sixteenths
+                    let value = if grace.pitches.len() == 1 { 8 } else { 16 };
+                    for pitch in &grace.pitches {
+                        let mut written = Written::new(vec![HeadKind::Pitch(*pitch)], Position::from_integer(0), value, 0, None);
+                        written.grace = Some(grace.acciaccatura);
+                        items.push(Item::Note(written));
+                    }
+                }
+                let first = sound(items, vec![HeadKind::Pitch(note.pitch)], &note.duration, tuplet)?;
+                first.articulation = note.articulation.clone();
+                first.ornament = note.ornament;
+            }
+            Event::Chord(pitches, duration, dynamic, articulation) => {
+                if let Some(dynamic) = dynamic {
+                    dynamic_mark(part, items, dynamic);
+                }
+                let heads = pitches.iter().map(|pitch| HeadKind::Pitch(*pitch)).collect();
+                sound(items, heads, duration, tuplet)?.articulation = articulation.clone();
+            }
+            Event::Rest(duration, fermata) => {
+                sound(items, vec![HeadKind::Rest], duration, tuplet)?.fermata = *fermata;
+            }
+            Event::Drum(hit) => {
+                if let Some(dynamic) = &hit.dynamic {
+                    dynamic_mark(part, items, dynamic);
+                }
+                let mut heads = Vec::new();
+                for name in &hit.drums {
+                    let key = get_drum_note(name).ok_or_else(|| anyhow!("Unknown drum: {}", name))?;
+                    part.drums.entry(key).or_insert_with(|| name.clone());
+                    heads.push(HeadKind::Drum(key));
+                }
+                sound(items, heads, &hit.duration, tuplet)
+                    .map_err(|e| Diagnostic::locate(e, hit.span))?
+                    .articulation = hit.articulation.clone();
+            }
+            Event::Tie => {
+                if let Some(last) = last_note(items) {
+                    last.tie_to_next = true;
+                }
+            }
+            Event::Slide(slide) => {
+                if let Some(last) = last_note(items) {
+                    last.slide_to_next = Some(*slide);
+                }
+            }
+            Event::Tuplet(group) => {
+                let start = items.len();
+                let ratio = Ratio::new(tuplet.0 * group.p as u64, tuplet.1 * group.q as u64);
+                place(&group.events, part, items, (*ratio.numer(), *ratio.denom()), depth + 1)?;
+                let mut notes = items[start..].iter_mut().filter_map(|item| match item {
+                    Item::Note(written) if written.grace.is_none() => Some(written),
+                    _ => None,
+                });
+                if let Some(first) = notes.next() {
+                    first.tuplets.push((depth + 1, true));
+                    match notes.last() {
+                        Some(last) => last.tuplets.push((depth + 1, false)),
+                        None => first.tuplets.push((depth + 1, false)),
+                    }
+                }
+            }
+            Event::Dynamic(dynamic) => dynamic_mark(part, items, dynamic),
+            Event::Crescendo | Event::Diminuendo => {
+                if part.hairpin {
+                    items.push(Item::Direction(direction_type_xml("below", "<wedge type=\"stop\"/>")));
+                }
+                let kind = if *event == Event::Crescendo { "crescendo" } else { "diminuendo" };
+                items.push(Item::Direction(direction_type_xml("below", &format!("<wedge type=\"{}\"/>", kind))));
+                part.hairpin = true;
+            }
+            Event::Pedal(down) => {
+                let kind = match (*down, part.pedal) {
+                    (true, true) => "change",
+                    (true, false) => "start",
+                    (false, _) => "stop",
+                };
+                items.push(Item::Direction(direction_type_xml("below", &format!("<pedal type=\"{}\" line=\"yes\"/>", kind))));
+                part.pedal = *down;
+            }
+            Event::MotifCall(call) => {
+                return Err(anyhow!("Motif '@{}' was not expanded", call.name));
+            }
+            // Performance only: controllers and bends have no notation
+            Event::Controller(_) | Event::Bend(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Write a sounding event as one or more tied values, returning the first.
+fn sound<'a>(items: &'a mut Vec<Item>, heads: Vec<HeadKind>, duration: &Option<Duration>, tuplet: (u64, u64)) -> Result<&'a mut Written> {
+    let (values, (actual, normal)) = notated(duration)?;
+    let ratio = Ratio::new(tuplet.0 * actual, tuplet.1 * normal);
+    let modification = (ratio != Ratio::from_integer(1)).then(|| (*ratio.numer(), *ratio.denom()));
+    let rest = matches!(heads.first(), Some(HeadKind::Rest));
+
+    let first = items.len();
+    let count = values.len();
+    for (i, (value, dots)) in values.into_iter().enumerate() {
+        let length = dotted(value, dots) / ratio;
+        let mut written = Written::new(heads.clone(), length, value, dots, modification);
+        if !rest {
+            for head in &mut written.heads {
+                head.tie_stop = i > 0;
+                head.tie_start = i + 1 < count;
+            }
+        }
+        items.push(Item::Note(written));
+    }
+    match &mut items[first] {
+        Item::Note(written) => Ok(written),
+        Item::Direction(_) => unreachable!("a sound writes at least one value"),
+    }
+}
+
+/// Written values for a duration, with the tuplet ratio they need. Plain and dotted
+/// durations keep their value; fractions are split into tied values, inside a
+/// tuplet when their length is not a power-of-two fraction of a whole note.
+fn notated(duration: &Option<Duration>) -> Result<(Vec<Value>, (u64, u64))> {
+    let length = duration_length(duration)?;
+    match duration {
+        None => Ok((vec![(4, 0)], (1, 1))),
+        Some(Duration::Base(base, dots)) => Ok((vec![(base_denominator(*base), *dots)], (1, 1))),
+        Some(_) => {
+            let odd = *length.denom() >> length.denom().trailing_zeros();
+            if odd == 1 {
+                return Ok((values(length)?, (1, 1)));
+            }
+            // e.g. `q/3` is an eighth under 3:2, `1/5` a quarter under 5:4
+            let normal = 1u64 << (63 - odd.leading_zeros());
+            Ok((values(length * odd / normal)?, (odd, normal)))
+        }
+    }
+}
+
+/// Plain or dotted values adding up to `length`, longest first.
+pub(crate) fn values(length: Position) -> Result<Vec<Value>> {
+    let mut values = Vec::new();
+    let mut rest = length;
+    while rest > Position::from_integer(0) {
+        let mut value = 1;
+        while Position::new(1, value) > rest {
+            value *= 2;
+            if value > 1024 {
+                return Err(anyhow!("Cannot notate a duration of {} of a whole note", length));
+            }
+        }
+        let mut dots = 0;
+        while dots < 2 && dotted(value, dots + 1) <= rest {
+            dots += 1;
+        }
+        values.push((value, dots));
+        rest -= dotted(value, dots);
+    }
+    Ok(values)
+}
+
+/// Length of a note value with dots, in whole notes.
+fn dotted(value: u64, dots: u8) -> Position {
+    Position::new(1, value) * (Position::from_integer(2) - Position::new(1, 1 << dots))
+}
+
+impl Written {
+    fn new(heads: Vec<HeadKind>, length: Position, value: u64, dots: u8, modification: Option<(u64, u64)>) -> Self {
+        Written {
+            heads: heads.into_iter().map(|kind| Head { kind, tie_stop: false, tie_start: false }).collect(),
+            length,
+            value,
+            dots,
+            modification,
+            grace: None,
+            tie_to_next: false,
+            slides: Vec::new(),
+            slide_to_next: None,
+            tuplets: Vec::new(),
+            articulation: None,
+            ornament: None,
+            fermata: false,
+        }
+    }
+}
+
+fn last_note(items: &mut [Item]) -> Option<&mut Written> {
+    items.iter_mut().rev().find_map(|item| match item {
+        Item::Note(written) if written.grace.is_none() => Some(written),
+        _ => None,
+    })
+}
+
+fn dynamic_mark(part: &mut XmlPart, items: &mut Vec<Item>, dynamic: &str) {
+    // A dynamic ends the hairpin leading to it
+    if part.hairpin {
+        part.hairpin = false;
+        items.push(Item::Direction(direction_type_xml("below", "<wedge type=\"stop\"/>")));
+    }
+    let percent = (dynamic_to_velocity(dynamic) as f64 / 90.0 * 100.0).round();
+    items.push(Item::Direction(format!(r#"      <direction placement="below">
+        <direction-type>
+          <dynamics>
+            <{}/>
+          </dynamics>
+        </direction-type>
+        <sound dynamics="{}"/>
+      </direction>
+"#, dynamic, percent)));
+}
+
+/// Join `~` ties and slides to the next note of the same voice, across barlines.
+fn link(part: &mut XmlPart) {
+    let mut previous: BTreeMap<u8, (usize, usize, usize)> = BTreeMap::new(); // Voice -> last note
+    for m in 0..part.measures.len() {
+        for v in 0..part.measures[m].voices.len() {
+            let voice = part.measures[m].voices[v].0;
+            for i in 0..part.measures[m].voices[v].1.len() {
+                let Item::Note(written) = &part.measures[m].voices[v].1[i] else { continue };
+                if written.grace.is_some() {
+                    continue;
+                }
+                if let Some(&(pm, pv, pi)) = previous.get(&voice)
+                    && let Item::Note(before) = &part.measures[pm].voices[pv].1[pi]
+                {
+                    let tied: Vec<Option<(u8, i16)>> = if before.tie_to_next {
+                        before.heads.iter().map(|head| sounding(head.kind)).collect()
+                    } else {
+                        Vec::new()
+                    };
+                    let slide = before.slide_to_next;
+
+                    let mut matched = Vec::new();
+                    if let Item::Note(after) = &mut part.measures[m].voices[v].1[i] {
+                        for head in &mut after.heads {
+                            if let Some(j) = tied.iter().position(|t| t.is_some() && *t == sounding(head.kind)) {
+                                head.tie_stop = true;
+                                matched.push(j);
+                            }
+                        }
+                        if let Some(slide) = slide {
+                            after.slides.push((slide, false));
+                        }
+                    }
+                    if let Item::Note(before) = &mut part.measures[pm].voices[pv].1[pi] {
+                        for j in matched {
+                            before.heads[j].tie_start = true;
+                        }
+                        if let Some(slide) = slide {
+                            before.slides.push((slide, true));
+                        }
+                    }
+                }
+                previous.insert(voice, (m, v, i));
+            }
+        }
+    }
+}
+
+/// Sounding key and detuning of a pitched head, for matching tied notes.
+fn sounding(kind: HeadKind) -> Option<(u8, i16)> {
+    match kind {
+        HeadKind::Pitch(pitch) => calculate_pitch(&pitch).ok().map(|key| (key, pitch_cents(&pitch))),
+        HeadKind::Drum(_) | HeadKind::Rest => None,
+    }
+}
+
+fn score_part_xml(part: &XmlPart, id: &str) -> String {
+    let mut xml = format!("    <score-part id=\"{}\">\n      <part-name>{}</part-name>\n", id, escape(&part.name));
+    // Percussion lists each drum it plays, so that every note can name its sound
+    let instruments: Vec<(String, String, String)> = if part.percussion() && !part.drums.is_empty() {
+        part.drums.iter().map(|(key, name)| {
+            (format!("{}-I{}", id, key), name.clone(), format!("<midi-unpitched>{}</midi-unpitched>", key + 1))
+        }).collect()
+    } else if part.percussion() {
+        vec![(format!("{}-I1", id), part.instrument.clone(), String::new())]
+    } else {
+        vec![(format!("{}-I1", id), part.instrument.clone(), format!("<midi-program>{}</midi-program>", part.program + 1))]
+    };
+    for (instrument_id, name, _) in &instruments {
+        xml.push_str(&format!("      <score-instrument id=\"{}\">\n        <instrument-name>{}</instrument-name>\n      </score-instrument>\n",
+            instrument_id, escape(name)));
+    }
+    for (instrument_id, _, sound) in &instruments {
+        xml.push_str(&format!("      <midi-instrument id=\"{}\">\n        <midi-channel>{}</midi-channel>\n", instrument_id, part.channel + 1));
+        if !sound.is_empty() {
+            xml.push_str(&format!("        {}\n", sound));
+        }
+        xml.push_str("      </midi-instrument>\n");
+    }
+    xml.push_str("    </score-part>\n");
+    xml
+}
+
+/// Clef for a part: the instrument's usual clef, or for instruments that read from
+/// either staff (keyboards, harp, ensembles, synths) whichever suits the notes.
+fn clef_xml(part: &XmlPart) -> String {
+    let (sign, line) = match part.program {
+        _ if part.percussion() => return "        <clef>\n          <sign>percussion</sign>\n        </clef>\n".to_string(),
+        41 => ("C", 3), // Viola
+        32..=39 | 42 | 43 | 47 | 57 | 58 | 70 => ("F", 4), // Basses, cello, timpani, trombone, tuba, bassoon
+        program if is_keyboard(program) || (48..=55).contains(&program) || (80..=103).contains(&program) => {
+            let pitches: Vec<u32> = part.notes()
+                .flat_map(|written| &written.heads)
+                .filter_map(|head| sounding(head.kind))
+                .map(|(key, _)| key as u32)
+                .collect();
+            let low = !pitches.is_empty() && pitches.iter().sum::<u32>() < 60 * pitches.len() as u32;
+            if low { ("F", 4) } else { ("G", 2) }
+        }
+        _ => ("G", 2),
+    };
+    format!("        <clef>\n          <sign>{}</sign>\n          <line>{}</line>\n        </clef>\n", sign, line)
+}
+
+fn key_xml(key: Option<Key>) -> String {
+    let (fifths, mode) = match key {
+        None => (0, None),
+        Some(key) => match key_fifths(&key) {
+            Ok(fifths) => (fifths, Some(mode_name(key.mode))),
+            Err(_) => (0, Some("none")), // Octatonic, whole-tone and chromatic scales
+        },
+    };
+    let mut xml = format!("        <key>\n          <fifths>{}</fifths>\n", fifths);
+    if let Some(mode) = mode {
+        xml.push_str(&format!("          <mode>{}</mode>\n", mode));
+    }
+    xml.push_str("        </key>\n");
+    xml
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Major => "major",
+        Mode::Minor => "minor",
+        Mode::Dorian => "dorian",
+        Mode::Phrygian => "phrygian",
+        Mode::Lydian => "lydian",
+        Mode::Mixolydian => "mixolydian",
+        Mode::Locrian => "locrian",
+        Mode::Octatonic | Mode::WholeTone | Mode::Chromatic => "none",
+    }
+}
+
+/// A metronome mark in the tempo's own beat, sounding in quarter notes per minute.
+fn tempo_xml(tempo: &Tempo) -> Result<String> {
+    let (unit, dots, per_minute) = match &tempo.beat {
+        None => (4, 0, tempo.bpm),
+        Some(Duration::Base(base, dots)) => (base_denominator(*base), *dots, tempo.bpm),
+        Some(_) => (4, 0, quarter_bpm(tempo)?), // Beats without a note value are shown in quarters
+    };
+    let mut metronome = format!("<metronome>\n            <beat-unit>{}</beat-unit>\n", type_name(unit)?);
+    for _ in 0..dots {
+        metronome.push_str("            <beat-unit-dot/>\n");
+    }
+    metronome.push_str(&format!("            <per-minute>{}</per-minute>\n          </metronome>", per_minute));
+    Ok(format!(r#"      <direction placement="above">
+        <direction-type>
+          {}
+        </direction-type>
+        <sound tempo="{}"/>
+      </direction>
+"#, metronome, quarter_bpm(tempo)?))
+}
+
+fn direction_type_xml(placement: &str, direction_type: &str) -> String {
+    format!(r#"      <direction placement="{}">
+        <direction-type>
+          {}
+        </direction-type>
+      </direction>
+"#, placement, direction_type)
+}
+
+fn measure_xml(measure: &XmlMeasure, number: u32, clef: Option<&str>, part_id: &str, divisions: u64) -> Result<String> {
+    let mut xml = if measure.pickup {
+        "    <measure number=\"0\" implicit=\"yes\">\n".to_string()
+    } else {
+        format!("    <measure number=\"{}\">\n", number)
+    };
+
+    if clef.is_some() || measure.key.is_some() || measure.time.is_some() {
+        xml.push_str("      <attributes>\n");
+        if clef.is_some() {
+            xml.push_str(&format!("        <divisions>{}</divisions>\n", divisions));
+        }
+        if let Some(key) = &measure.key {
+            xml.push_str(key);
+        }
+        if let Some((beats, beat_type)) = measure.time {
+            xml.push_str(&format!("        <time>\n          <beats>{}</beats>\n          <beat-type>{}</beat-type>\n        </time>\n",
+                beats, beat_type));
+        }
+        if let Some(clef) = clef {
+            xml.push_str(clef);
+        }
+        xml.push_str("      </attributes>\n");
+    }
+    xml.push_str(&left_marks_xml(measure.left.iter()));
+    for direction in &measure.directions {
+        xml.push_str(direction);
+    }
+
+    let ticks = |length: Position| (length * 4 * divisions).to_integer();
+    let mut rewind = None;
+    for (voice, items) in &measure.voices {
+        if let Some(duration) = rewind {
+            // Rewind to the barline so the next voice starts with the measure
+            xml.push_str("      <backup>\n");
+            xml.push_str(&format!("        <duration>{}</duration>\n", duration));
+            xml.push_str("      </backup>\n");
+        }
+        let mut length = Position::from_integer(0);
+        for item in items {
+            match item {
+                Item::Direction(direction) => xml.push_str(direction),
+                Item::Note(written) => {
+                    xml.push_str(&note_xml(written, *voice, part_id, ticks(written.length))?);
+                    length += written.length;
+                }
+            }
+        }
+        rewind = Some(ticks(length));
+    }
+
+    xml.push_str(&right_marks_xml(measure.right.iter()));
+    xml.push_str("    </measure>\n");
+    Ok(xml)
+}
+
+/// Barline and direction markup for marks that sit at the start of a measure.
+fn left_marks_xml<'a>(marks: impl Iterator<Item = &'a Mark>) -> String {
+    let mut ending = String::new();
+    let mut repeat = String::new();
+    let mut directions = String::new();
+
+    for mark in marks {
+        match mark {
+            Mark::RepeatStart => {
+                repeat = "        <repeat direction=\"forward\"/>\n".to_string();
+            }
+            Mark::EndingStart(numbers) => {
+                ending = format!("        <ending number=\"{}\" type=\"start\"/>\n", ending_numbers(numbers));
+            }
+            Mark::Navigation(Navigation::Segno) => {
+                directions.push_str(&direction_xml("<segno/>", "segno=\"segno\""));
+            }
+            Mark::Navigation(Navigation::Coda) => {
+                directions.push_str(&direction_xml("<coda/>", "coda=\"coda\""));
+            }
+            _ => {}
+        }
+    }
+
+    if ending.is_empty() && repeat.is_empty() {
+        return directions;
+    }
+
+    let mut xml = String::from("      <barline location=\"left\">\n");
+    if !repeat.is_empty() {
+        xml.push_str("        <bar-style>heavy-light</bar-style>\n");
+    }
+    xml.push_str(&ending);
+    xml.push_str(&repeat);
+    xml.push_str("      </barline>\n");
+    xml + &directions
+}
+
+/// Direction and barline markup for marks that sit at the end of a measure.
+fn right_marks_xml<'a>(marks: impl Iterator<Item = &'a Mark>) -> String {
+    let mut style = "";
+    let mut ending = String::new();
+    let mut repeat = String::new();
+    let mut directions = String::new();
+
+    for mark in marks {
+        match mark {
+            Mark::RepeatEnd { times } => {
+                style = "light-heavy";
+                if *times == 2 {
+                    repeat = "        <repeat direction=\"backward\"/>\n".to_string();
+                } else {
+                    repeat = format!("        <repeat direction=\"backward\" times=\"{}\"/>\n", times);
+                }
+            }
+            Mark::EndingEnd { numbers, discontinue } => {
+                let kind = if *discontinue { "discontinue" } else { "stop" };
+                ending = format!("        <ending number=\"{}\" type=\"{}\"/>\n", ending_numbers(numbers), kind);
+            }
+            Mark::Navigation(Navigation::Fine) => {
+                style = "light-heavy";
+                directions.push_str(&direction_xml("<words>Fine</words>", "fine=\"yes\""));
+            }
+            Mark::Navigation(Navigation::ToCoda) => {
+                directions.push_str(&direction_xml("<words>To Coda</words>", "tocoda=\"coda\""));
+            }
+            Mark::Navigation(Navigation::DaCapo(end)) => {
+                let words = format!("<words>D.C.{}</words>", jump_end_text(*end));
+                directions.push_str(&direction_xml(&words, "dacapo=\"yes\""));
+            }
+            Mark::Navigation(Navigation::DalSegno(end)) => {
+                let words = format!("<words>D.S.{}</words>", jump_end_text(*end));
+                directions.push_str(&direction_xml(&words, "dalsegno=\"segno\""));
+            }
+            _ => {}
+        }
+    }
+
+    if style.is_empty() && ending.is_empty() && repeat.is_empty() {
+        return directions;
+    }
+
+    let mut xml = directions;
+    xml.push_str("      <barline location=\"right\">\n");
+    if !style.is_empty() {
+        xml.push_str(&format!("        <bar-style>{}</bar-style>\n", style));
+    }
+    xml.push_str(&ending);
+    xml.push_str(&repeat);
+    xml.push_str("      </barline>\n");
+    xml
+}
+
+fn direction_xml(direction_type: &str, sound: &str) -> String {
+    format!(r#"      <direction placement="above">
+        <direction-type>
+          {}
+        </direction-type>
+        <sound {}/>
+      </direction>
+"#, direction_type, sound)
+}
+
+fn ending_numbers(numbers: &[u32]) -> String {
+    numbers.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn jump_end_text(end: JumpEnd) -> &'static str {
+    match end {
+        JumpEnd::Plain => "",
+        JumpEnd::AlFine => " al Fine",
+        JumpEnd::AlCoda => " al Coda",
+    }
+}
+
+/// One `<note>` per head, the later heads of a chord marked `<chord/>`.
+fn note_xml(written: &Written, voice: u8, part_id: &str, duration: u64) -> Result<String> {
+    let mut xml = String::new();
+    for (i, head) in written.heads.iter().enumerate() {
+        xml.push_str("      <note>\n");
+        match written.grace {
+            Some(true) => xml.push_str("        <grace slash=\"yes\"/>\n"),
+            Some(false) => xml.push_str("        <grace/>\n"),
+            None => {}
+        }
+        if i > 0 {
+            xml.push_str("        <chord/>\n");
+        }
+        match head.kind {
+            HeadKind::Pitch(pitch) => {
+                xml.push_str("        <pitch>\n");
+                xml.push_str(&format!("          <step>{}</step>\n", pitch.step));
+                if let Some(alter) = alter(pitch.accidental) {
+                    xml.push_str(&format!("          <alter>{}</alter>\n", alter));
+                }
+                xml.push_str(&format!("          <octave>{}</octave>\n", pitch.octave));
+                xml.push_str("        </pitch>\n");
+            }
+            HeadKind::Drum(key) => {
+                let (step, octave, _) = drum_display(key);
+                xml.push_str("        <unpitched>\n");
+                xml.push_str(&format!("          <display-step>{}</display-step>\n", step));
+                xml.push_str(&format!("          <display-octave>{}</display-octave>\n", octave));
+                xml.push_str("        </unpitched>\n");
+            }
+            HeadKind::Rest => xml.push_str("        <rest/>\n"),
+        }
+        if written.grace.is_none() {
+            xml.push_str(&format!("        <duration>{}</duration>\n", duration));
+        }
+        if head.tie_stop {
+            xml.push_str("        <tie type=\"stop\"/>\n");
+        }
+        if head.tie_start {
+            xml.push_str("        <tie type=\"start\"/>\n");
+        }
+        if let HeadKind::Drum(key) = head.kind {
+            xml.push_str(&format!("        <instrument id=\"{}-I{}\"/>\n", part_id, key));
+        }
+        xml.push_str(&format!("        <voice>{}</voice>\n", voice));
+        xml.push_str(&format!("        <type>{}</type>\n", type_name(written.value)?));
+        for _ in 0..written.dots {
+            xml.push_str("        <dot/>\n");
+        }
+        if let HeadKind::Pitch(pitch) = head.kind
+            && let Some(accidental) = accidental_name(pitch.accidental)
+        {
+            xml.push_str(&format!("        <accidental>{}</accidental>\n", accidental));
+        }
+        if let Some((actual, normal)) = written.modification {
+            xml.push_str("        <time-modification>\n");
+            xml.push_str(&format!("          <actual-notes>{}</actual-notes>\n", actual));
+            xml.push_str(&format!("          <normal-notes>{}</normal-notes>\n", normal));
+            xml.push_str("        </time-modification>\n");
+        }
+        if let HeadKind::Drum(key) = head.kind
+            && drum_display(key).2
+        {
+            xml.push_str("        <notehead>x</notehead>\n");
+        }
+
+        let mut notations = Vec::new();
+        if head.tie_stop {
+            notations.push("<tied type=\"stop\"/>".to_string());
+        }
+        if head.tie_start {
+            notations.push("<tied type=\"start\"/>".to_string());
+        }
+        // Everything else belongs to the event rather than each of its heads
+        if i == 0 {
+            for &(number, start) in &written.tuplets {
+                notations.push(if start {
+                    format!("<tuplet type=\"start\" number=\"{}\" bracket=\"yes\"/>", number)
+                } else {
+                    format!("<tuplet type=\"stop\" number=\"{}\"/>", number)
+                });
+            }
+            for &(slide, start) in &written.slides {
+                let kind = if start { "start" } else { "stop" };
+                notations.push(match slide {
+                    Slide::Glissando => format!("<glissando type=\"{}\" line-type=\"wavy\"/>", kind),
+                    Slide::Portamento => format!("<slide type=\"{}\" line-type=\"solid\"/>", kind),
+                });
+            }
+            if let Some(ornament) = written.ornament {
+                let mark = match ornament {
+                    Ornament::Trill => "trill-mark",
+                    Ornament::Mordent => "mordent",
+                    Ornament::InvertedMordent => "inverted-mordent",
+                    Ornament::Turn => "turn",
+                };
+                notations.push(format!("<ornaments>\n            <{}/>\n          </ornaments>", mark));
+            }
+            let symbols = written.articulation.as_deref().unwrap_or("");
+            let articulations: Vec<&str> = symbols.chars().filter_map(|symbol| match symbol {
+                '.' => Some("staccato"),
+                '\'' => Some("staccatissimo"),
+                '-' => Some("tenuto"),
+                '>' => Some("accent"),
+                '^' => Some("strong-accent"),
+                _ => None,
+            }).collect();
+            if !articulations.is_empty() {
+                let marks: String = articulations.iter().map(|a| format!("            <{}/>\n", a)).collect();
+                notations.push(format!("<articulations>\n{}          </articulations>", marks));
+            }
+            if written.fermata || symbols.contains('U') {
+                notations.push("<fermata/>".to_string());
+            }
+        }
+        if !notations.is_empty() {
+            xml.push_str("        <notations>\n");
+            for notation in notations {
+                xml.push_str(&format!("          {}\n", notation));
+            }
+            xml.push_str("        </notations>\n");
+        }
+        xml.push_str("      </note>\n");
+    }
+    Ok(xml)
+}
+
+fn alter(accidental: Option<Accidental>) -> Option<&'static str> {
+    match accidental? {
+        Accidental::Sharp => Some("1"),
+        Accidental::Flat => Some("-1"),
+        Accidental::Natural => None,
+        Accidental::QuarterSharp => Some("0.5"),
+        Accidental::QuarterFlat => Some("-0.5"),
+        Accidental::ThreeQuarterSharp => Some("1.5"),
+        Accidental::ThreeQuarterFlat => Some("-1.5"),
+    }
+}
+
+/// Accidentals that notation programs cannot work out from the key and `<alter>`.
+fn accidental_name(accidental: Option<Accidental>) -> Option<&'static str> {
+    match accidental? {
+        Accidental::Natural => Some("natural"),
+        Accidental::QuarterSharp => Some("quarter-sharp"),
+        Accidental::QuarterFlat => Some("quarter-flat"),
+        Accidental::ThreeQuarterSharp => Some("three-quarters-sharp"),
+        Accidental::ThreeQuarterFlat => Some("three-quarters-flat"),
+        Accidental::Sharp | Accidental::Flat => None,
+    }
+}
+
+/// Where a drum sits on the percussion staff, and whether it takes a cross notehead.
+fn drum_display(key: u8) -> (char, i32, bool) {
+    match key {
+        35 | 36 => ('F', 4, false), // Kicks
+        37 => ('C', 5, true), // Side stick
+        38..=40 => ('C', 5, false), // Snares and clap
+        41 | 43 => ('A', 4, false), // Floor toms
+        45 | 47 => ('D', 5, false),
+        48 | 50 => ('E', 5, false),
+        42 | 46 => ('G', 5, true), // Hi-hat
+        44 => ('D', 4, true), // Hi-hat pedal
+        51 | 53 | 59 => ('F', 5, true), // Rides
+        49 | 52 | 55 | 57 => ('A', 5, true), // Crashes, china, splash
+        _ => ('C', 5, false),
+    }
+}
+
+/// MusicXML note type for a value given as the denominator of a whole note.
+fn type_name(value: u64) -> Result<&'static str> {
+    Ok(match value {
+        1 => "whole",
+        2 => "half",
+        4 => "quarter",
+        8 => "eighth",
+        16 => "16th",
+        32 => "32nd",
+        64 => "64th",
+        128 => "128th",
+        256 => "256th",
+        512 => "512th",
+        1024 => "1024th",
+        _ => return Err(anyhow!("No note type for 1/{} of a whole note", value)),
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -1,18 +1,24 @@
 use crate::ast::*;
+use crate::diagnostics::Diagnostic;
+use crate::drums::get_drum_note;
+use crate::keys::{parse_mode, parse_tonic};
 use crate::grammar::{MusicParser, Rule};
 use anyhow::{anyhow, Result};
 use pest::Parser;
+use std::collections::BTreeMap;
 
 pub fn parse(input: &str) -> Result<Score> {
-    let mut pairs = MusicParser::parse(Rule::score, input)?;
+    let mut pairs = MusicParser::parse(Rule::score, input).map_err(|e| Diagnostic::from_pest(&e))?;
     let score_pair = pairs.next().ok_or_else(|| anyhow!("No score found"))?;
 
     let mut headers = Vec::new();
+    let mut motifs = BTreeMap::new();
     let mut parts = Vec::new();
 
     for pair in score_pair.into_inner() {
         match pair.as_rule() {
             Rule::header => {
+                let span = Span::from(pair.as_span());
                 let inner = pair.into_inner().next().unwrap();
                 match inner.as_rule() {
                     Rule::string_literal => {
@@ -22,24 +28,46 @@ pub fn parse(input: &str) -> Result<Score> {
                         let content = s.trim_matches('"').to_string();
                         headers.push(Header::Title(content));
                     }
-                    Rule::integer => {
-                        let bpm = inner.as_str().parse()?;
-                        headers.push(Header::Tempo(bpm));
+                    Rule::copyright => headers.push(Header::Copyright(string_content(inner))),
+                    Rule::text => headers.push(Header::Text(string_content(inner))),
+                    Rule::ppq => headers.push(Header::Ppq(inner.as_str().trim().parse()?)),
+                    Rule::bend_range => {
+                        let range = inner.as_str().trim().parse().ok().filter(|r| (1..=24).contains(r)).ok_or_else(|| {
+                            let message = format!("Pitch bend range must be 1 to 24 semitones, got {}", inner.as_str().trim());
+                            Diagnostic::error(message, span)
+                        })?;
+                        headers.push(Header::BendRange(range));
                     }
+                    Rule::accidental_mode => headers.push(Header::Accidentals(match inner.as_str() {
+                        "Key" => AccidentalMode::Key,
+                        _ => AccidentalMode::Explicit,
+                    })),
+                    Rule::tempo => headers.push(Header::Tempo(parse_tempo(inner)?)),
                     Rule::time_signature => {
                         let (num, den) = parse_time_signature(inner)?;
                         headers.push(Header::TimeSignature(num, den));
                     }
                     Rule::key_signature => {
-                        let (root, scale) = parse_key_signature(inner)?;
-                        headers.push(Header::KeySignature(root, scale));
+                        headers.push(Header::KeySignature(parse_key_signature(inner)?));
                     }
                     Rule::swing_setting => {
                         headers.push(Header::Swing(parse_swing_setting(inner)?));
                     }
+                    Rule::articulation_setting => {
+                        let setting = parse_articulation_setting(inner).map_err(|e| Diagnostic::locate(e, span))?;
+                        headers.push(Header::Articulation(setting));
+                    }
                     _ => {}
                 }
             }
+            Rule::motif => {
+                let span = Span::from(pair.as_span());
+                let (name, events) = parse_motif(pair)?;
+                if motifs.insert(name.clone(), events).is_some() {
+                    let message = format!("Motif '{}' is defined more than once", name);
+                    return Err(Diagnostic::error(message, span).with_hint("rename one of the motifs").into());
+                }
+            }
             Rule::part => {
                 parts.push(parse_part(pair)?);
             }
@@ -48,10 +76,62 @@ pub fn parse(input: &str) -> Result<Score> {
         }
     }
 
-    Ok(Score { headers, parts })
+    Ok(Score { headers, motifs, parts })
+}
+
+/// The unquoted contents of a rule wrapping a single string literal.
+fn string_content(pair: pest::iterators::Pair<Rule>) -> String {
+    pair.as_str().trim().trim_matches('"').to_string()
+}
+
+fn parse_motif(pair: pest::iterators::Pair<Rule>) -> Result<(String, Vec<Event>)> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().ok_or_else(|| anyhow!("Motif name missing"))?.as_str().to_string();
+
+    let mut events = Vec::new();
+    for event_pair in inner {
+        events.push(parse_music_event(event_pair)?);
+    }
+
+    Ok((name, events))
+}
+
+fn parse_motif_call(pair: pest::iterators::Pair<Rule>) -> Result<MotifCall> {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+
+    let mut transforms = Vec::new();
+    for transform_pair in inner {
+        let transform = transform_pair.into_inner().next().unwrap();
+        let factor = |p: pest::iterators::Pair<Rule>| -> Result<u32> {
+            match p.into_inner().next() {
+                Some(n) => {
+                    let factor: u32 = n.as_str().parse()?;
+                    if factor == 0 {
+                        return Err(anyhow!("Augmentation/diminution factor must be at least 1"));
+                    }
+                    Ok(factor)
+                }
+                None => Ok(2),
+            }
+        };
+        transforms.push(match transform.as_rule() {
+            Rule::transpose_semitones => MotifTransform::Transpose(transform.as_str().parse()?),
+            Rule::transpose_steps => MotifTransform::TransposeDiatonic(transform.as_str().trim_end_matches('d').parse()?),
+            Rule::inversion => MotifTransform::Inversion,
+            Rule::retrograde => MotifTransform::Retrograde,
+            Rule::augmentation => MotifTransform::Augmentation(factor(transform)?),
+            Rule::diminution => MotifTransform::Diminution(factor(transform)?),
+            _ => return Err(anyhow!("Unknown motif transform: {}", transform.as_str())),
+        });
+    }
+
+    Ok(MotifCall { name, transforms, span })
 }
 
 fn parse_part(pair: pest::iterators::Pair<Rule>) -> Result<Part> {
+    let start = pair.as_span().start();
     let mut inner = pair.into_inner();
 
     let name_pair = inner.next().ok_or_else(|| anyhow!("Part name missing"))?;
@@ -69,6 +149,7 @@ fn parse_part(pair: pest::iterators::Pair<Rule>) -> Result<Part> {
 
 
     let instrument_pair = inner.next().ok_or_else(|| anyhow!("Instrument name missing"))?;
+    let span = Span::new(start, instrument_pair.as_span().end());
     let instrument = match instrument_pair.as_rule() {
         Rule::instrument_name => {
             let inner_name = instrument_pair.into_inner().next().unwrap();
@@ -87,17 +168,14 @@ fn parse_part(pair: pest::iterators::Pair<Rule>) -> Result<Part> {
 
     let content = parse_part_content(content_pair)?;
 
-    Ok(Part { name, instrument, content })
+    Ok(Part { name, instrument, content, span })
 }
 
 fn parse_part_content(pair: pest::iterators::Pair<Rule>) -> Result<Vec<MeasureBlock>> {
     let mut blocks = Vec::new();
     for inner in pair.into_inner() {
-        match inner.as_rule() {
-            Rule::measure_block => {
-                blocks.extend(parse_measure_block(inner)?);
-            }
-            _ => {}
+        if inner.as_rule() == Rule::measure_block {
+            blocks.extend(parse_measure_block(inner)?);
         }
     }
     Ok(blocks)
@@ -105,50 +183,191 @@ fn parse_part_content(pair: pest::iterators::Pair<Rule>) -> Result<Vec<MeasureBl
 
 fn parse_measure_block(pair: pest::iterators::Pair<Rule>) -> Result<Vec<MeasureBlock>> {
     let mut blocks = Vec::new();
+    for inner in pair.into_inner() {
+        if let Some(block) = parse_block(inner)? {
+            blocks.push(block);
+        }
+    }
+    Ok(blocks)
+}
+
+fn parse_block(pair: pest::iterators::Pair<Rule>) -> Result<Option<MeasureBlock>> {
+    match pair.as_rule() {
+        Rule::measure => Ok(Some(MeasureBlock::Measure(parse_measure(pair)?))),
+        Rule::context_change => Ok(parse_context_change(pair)?.map(MeasureBlock::ContextChange)),
+        Rule::repeat => Ok(Some(MeasureBlock::Repeat(parse_repeat(pair)?))),
+        Rule::navigation => Ok(Some(MeasureBlock::Navigation(parse_navigation(pair)?))),
+        _ => Ok(None),
+    }
+}
+
+fn parse_context_change(pair: pest::iterators::Pair<Rule>) -> Result<Option<ContextChange>> {
+    let accelerando = pair.as_str().starts_with("Accel");
+    let span = Span::from(pair.as_span());
+    let inner = pair.into_inner().next().unwrap();
+    match inner.as_rule() {
+        Rule::tempo => Ok(Some(ContextChange::Tempo(parse_tempo(inner)?))),
+        Rule::tempo_ramp => Ok(Some(ContextChange::TempoRamp(parse_tempo_ramp(inner, accelerando, span)?))),
+        Rule::time_signature => {
+            let (num, den) = parse_time_signature(inner)?;
+            Ok(Some(ContextChange::TimeSignature(num, den)))
+        }
+        Rule::key_signature => {
+            Ok(Some(ContextChange::KeySignature(parse_key_signature(inner)?)))
+        }
+        Rule::swing_setting => {
+            // swing_setting is already what we want, don't descend into it again
+            Ok(Some(ContextChange::Swing(parse_swing_setting(inner)?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parse_tempo(pair: pest::iterators::Pair<Rule>) -> Result<Tempo> {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let bpm: f64 = inner.next().unwrap().as_str().parse()?;
+    if bpm <= 0.0 {
+        return Err(Diagnostic::error("Tempo must be above 0 BPM", span).into());
+    }
+    let beat = inner.next().map(parse_duration).transpose()?;
+    Ok(Tempo { bpm, beat })
+}
+
+fn parse_tempo_ramp(pair: pest::iterators::Pair<Rule>, accelerando: bool, span: Span) -> Result<TempoRamp> {
+    let mut inner = pair.into_inner();
+    let target = parse_tempo(inner.next().unwrap())?;
+    let beats = inner.next().unwrap().as_str().parse()?;
+    let curve = match inner.next().map(|p| p.as_str()) {
+        Some("exp") => TempoCurve::Exponential,
+        _ => TempoCurve::Linear,
+    };
+    Ok(TempoRamp { target, beats, curve, accelerando, span })
+}
+
+fn parse_repeat(pair: pest::iterators::Pair<Rule>) -> Result<Repeat> {
+    let mut body = Vec::new();
+    let mut endings = Vec::new();
+    let mut times = 2;
+
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::measure => {
-                blocks.push(MeasureBlock::Measure(parse_measure(inner)?));
-            }
-            Rule::context_change => {
-                let inner = inner.into_inner().next().unwrap();
-                match inner.as_rule() {
-                    Rule::integer => {
-                        let bpm = inner.as_str().parse()?;
-                        blocks.push(MeasureBlock::ContextChange(ContextChange::Tempo(bpm)));
+            Rule::repeat_body => {
+                for block_pair in inner.into_inner() {
+                    if let Some(block) = parse_block(block_pair)? {
+                        body.push(block);
                     }
-                    Rule::time_signature => {
-                        let (num, den) = parse_time_signature(inner)?;
-                        blocks.push(MeasureBlock::ContextChange(ContextChange::TimeSignature(num, den)));
-                    }
-                    Rule::key_signature => {
-                        let (root, scale) = parse_key_signature(inner)?;
-                        blocks.push(MeasureBlock::ContextChange(ContextChange::KeySignature(root, scale)));
-                    }
-                    Rule::swing_setting => {
-                        // swing_setting is already what we want, don't descend into it again
-                        blocks.push(MeasureBlock::ContextChange(ContextChange::Swing(parse_swing_setting(inner)?)));
-                    }
-                    _ => {}
                 }
             }
+            // The last measure, closed by `:|`; it has the same contents as any measure
+            Rule::repeat_tail => body.push(MeasureBlock::Measure(parse_measure(inner)?)),
+            Rule::repeat_times => {
+                times = inner.into_inner().next().unwrap().as_str().parse()?;
+                if times < 1 {
+                    return Err(anyhow!("Repeat count must be at least 1"));
+                }
+            }
+            Rule::ending => endings.push(parse_ending(inner)?),
             _ => {}
         }
     }
-    Ok(blocks)
+
+    if !endings.is_empty() {
+        // With endings, the number of passes is given by the highest ending number
+        times = endings.iter().flat_map(|e| e.numbers.iter().copied()).max().unwrap_or(2);
+    }
+
+    Ok(Repeat { times, body, endings })
+}
+
+fn parse_ending(pair: pest::iterators::Pair<Rule>) -> Result<Ending> {
+    let mut numbers = Vec::new();
+    let mut body = Vec::new();
+
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::integer => {
+                let number: u32 = inner.as_str().parse()?;
+                if number < 1 {
+                    return Err(anyhow!("Ending numbers start at 1"));
+                }
+                numbers.push(number);
+            }
+            _ => {
+                if let Some(block) = parse_block(inner)? {
+                    body.push(block);
+                }
+            }
+        }
+    }
+
+    Ok(Ending { numbers, body })
+}
+
+fn parse_navigation(pair: pest::iterators::Pair<Rule>) -> Result<Navigation> {
+    let inner = pair.into_inner().next().unwrap();
+    let jump_end = |p: pest::iterators::Pair<Rule>| match p.into_inner().next() {
+        Some(end) if end.as_str().ends_with("Fine") => JumpEnd::AlFine,
+        Some(_) => JumpEnd::AlCoda,
+        None => JumpEnd::Plain,
+    };
+
+    match inner.as_rule() {
+        Rule::da_capo => Ok(Navigation::DaCapo(jump_end(inner))),
+        Rule::dal_segno => Ok(Navigation::DalSegno(jump_end(inner))),
+        Rule::to_coda => Ok(Navigation::ToCoda),
+        Rule::segno => Ok(Navigation::Segno),
+        Rule::coda => Ok(Navigation::Coda),
+        Rule::fine => Ok(Navigation::Fine),
+        _ => Err(anyhow!("Unknown navigation marker: {}", inner.as_str())),
+    }
 }
 
 fn parse_measure(pair: pest::iterators::Pair<Rule>) -> Result<Measure> {
+    let span = Span::from(pair.as_span());
     let mut events = Vec::new();
+    let mut voices: Vec<Voice> = Vec::new();
+    let mut kind = MeasureKind::Regular;
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::music_event => {
-                events.push(parse_music_event(inner)?);
+            Rule::music_event => events.push(parse_music_event(inner)?),
+            Rule::voice => {
+                let voice = parse_voice(inner)?;
+                let repeated = match voice.number {
+                    0 => return Err(Diagnostic::error("Voices are numbered from 1", voice.span).into()),
+                    1 => !events.is_empty(),
+                    n => voices.iter().any(|v| v.number == n),
+                };
+                if repeated {
+                    let message = format!("Voice {} is written twice in this measure", voice.number);
+                    return Err(Diagnostic::error(message, voice.span).into());
+                }
+                if voice.number == 1 {
+                    events = voice.events;
+                } else {
+                    voices.push(voice);
+                }
+            }
+            Rule::measure_kind => {
+                kind = match inner.as_str() {
+                    "Pickup" => MeasureKind::Pickup,
+                    _ => MeasureKind::Irregular,
+                };
             }
             _ => {}
         }
     }
-    Ok(Measure { events })
+    Ok(Measure { events, voices, kind, span })
+}
+
+fn parse_voice(pair: pest::iterators::Pair<Rule>) -> Result<Voice> {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let label = inner.next().unwrap();
+    let number = label.into_inner().next().unwrap().as_str().parse()
+        .map_err(|_| Diagnostic::error("Voice number is too large", span))?;
+    let events = inner.map(parse_music_event).collect::<Result<Vec<_>>>()?;
+    Ok(Voice { number, events, span })
 }
 
 fn parse_music_event(pair: pest::iterators::Pair<Rule>) -> Result<Event> {
@@ -156,14 +375,62 @@ fn parse_music_event(pair: pest::iterators::Pair<Rule>) -> Result<Event> {
     match inner.as_rule() {
         Rule::note => Ok(Event::Note(parse_note(inner)?)),
         Rule::chord => Ok(parse_chord(inner)?),
-        Rule::rest => Ok(Event::Rest(parse_rest(inner)?)),
+        Rule::drum_hit => Ok(Event::Drum(parse_drum_hit(inner)?)),
+        Rule::rest => {
+            let (duration, held) = parse_rest(inner)?;
+            Ok(Event::Rest(duration, held))
+        }
         Rule::tuplet => Ok(Event::Tuplet(parse_tuplet(inner)?)),
         Rule::dynamic => Ok(Event::Dynamic(inner.as_str().to_string())),
+        Rule::tie => Ok(Event::Tie),
+        Rule::hairpin => match inner.into_inner().next().unwrap().as_rule() {
+            Rule::crescendo => Ok(Event::Crescendo),
+            _ => Ok(Event::Diminuendo),
+        },
+        Rule::motif_call => Ok(Event::MotifCall(parse_motif_call(inner)?)),
+        Rule::pedal => Ok(Event::Pedal(inner.into_inner().next().unwrap().as_rule() == Rule::pedal_down)),
+        Rule::controller => Ok(Event::Controller(parse_controller(inner)?)),
+        Rule::slide => Ok(Event::Slide(match inner.as_str() {
+            "gliss" => Slide::Glissando,
+            _ => Slide::Portamento,
+        })),
+        Rule::bend => Ok(Event::Bend(parse_bend(inner)?)),
         Rule::swing_setting => Err(anyhow!("Swing setting not allowed as music event")),
         _ => Err(anyhow!("Unknown event type")),
     }
 }
 
+fn parse_controller(pair: pest::iterators::Pair<Rule>) -> Result<Controller> {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let number = parse_midi_data(inner.next().unwrap(), "number")?;
+    let value = parse_midi_data(inner.next().unwrap(), "value")?;
+    let ramp = match (inner.next(), inner.next()) {
+        (Some(target), Some(duration)) => Some((parse_midi_data(target, "value")?, parse_duration(duration)?)),
+        _ => None,
+    };
+    Ok(Controller { number, value, ramp, span })
+}
+
+fn parse_bend(pair: pest::iterators::Pair<Rule>) -> Result<Bend> {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let semitones = inner.next().unwrap().as_str().parse()?;
+    let ramp = match (inner.next(), inner.next()) {
+        (Some(target), Some(duration)) => Some((target.as_str().parse()?, parse_duration(duration)?)),
+        _ => None,
+    };
+    Ok(Bend { semitones, ramp, span })
+}
+
+/// A 7-bit MIDI data byte, such as a controller number or value.
+fn parse_midi_data(pair: pest::iterators::Pair<Rule>, what: &str) -> Result<u8> {
+    pair.as_str().parse().ok().filter(|&v: &u8| v <= 127).ok_or_else(|| {
+        let message = format!("Controller {} must be between 0 and 127, got {}", what, pair.as_str());
+        Diagnostic::error(message, pair.as_span().into()).into()
+    })
+}
+
 fn parse_chord(pair: pest::iterators::Pair<Rule>) -> Result<Event> {
     let inner = pair.into_inner();
     let mut pitches = Vec::new();
@@ -176,7 +443,7 @@ fn parse_chord(pair: pest::iterators::Pair<Rule>) -> Result<Event> {
             Rule::pitch => pitches.push(parse_pitch(p)?),
             Rule::duration => duration = Some(parse_duration(p)?),
             Rule::dynamic => dynamic = Some(p.as_str().to_string()),
-            Rule::articulation => articulation = Some(p.as_str().to_string()),
+            Rule::articulation => articulation.get_or_insert_with(String::new).push_str(p.as_str()),
             _ => {}
         }
     }
@@ -185,30 +452,93 @@ fn parse_chord(pair: pest::iterators::Pair<Rule>) -> Result<Event> {
 }
 
 fn parse_note(pair: pest::iterators::Pair<Rule>) -> Result<Note> {
-    let mut inner = pair.into_inner();
-    let pitch = parse_pitch(inner.next().unwrap())?;
+    let span = Span::from(pair.as_span());
+    let mut pitch = None;
     let mut duration = None;
     let mut dynamic = None;
     let mut articulation = None;
+    let mut grace = None;
+    let mut ornament = None;
 
-    for p in inner {
+    for p in pair.into_inner() {
         match p.as_rule() {
+            Rule::grace_notes => grace = Some(parse_grace_notes(p)?),
+            Rule::pitch => pitch = Some(parse_pitch(p)?),
             Rule::duration => duration = Some(parse_duration(p)?),
             Rule::dynamic => dynamic = Some(p.as_str().to_string()),
-            Rule::articulation => articulation = Some(p.as_str().to_string()),
+            Rule::articulation => articulation.get_or_insert_with(String::new).push_str(p.as_str()),
+            Rule::ornament => {
+                ornament = Some(match p.as_str() {
+                    "tr" => Ornament::Trill,
+                    "mord" => Ornament::Mordent,
+                    "prall" => Ornament::InvertedMordent,
+                    "turn" => Ornament::Turn,
+                    other => return Err(anyhow!("Unknown ornament: {}", other)),
+                })
+            }
             _ => {}
         }
     }
 
     Ok(Note {
-        pitch,
+        pitch: pitch.ok_or_else(|| anyhow!("Note without a pitch"))?,
         duration,
         dynamic,
         articulation,
+        grace,
+        ornament,
+        span,
     })
 }
 
-fn parse_pitch(pair: pest::iterators::Pair<Rule>) -> Result<Pitch> {
+fn parse_grace_notes(pair: pest::iterators::Pair<Rule>) -> Result<GraceNotes> {
+    let mut pitches = Vec::new();
+    let mut acciaccatura = false;
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::acciaccatura => acciaccatura = true,
+            Rule::pitch => pitches.push(parse_pitch(p)?),
+            _ => {}
+        }
+    }
+    Ok(GraceNotes { pitches, acciaccatura })
+}
+
+fn parse_drum_hit(pair: pest::iterators::Pair<Rule>) -> Result<DrumHit> {
+    let span = Span::from(pair.as_span());
+    let mut drums = Vec::new();
+    let mut duration = None;
+    let mut dynamic = None;
+    let mut articulation = None;
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::drum_name => {
+                let name = p.as_str();
+                if get_drum_note(name).is_none() {
+                    let message = format!("Unknown drum: {}", name);
+                    let hint = "see the drum name table in the user guide";
+                    return Err(Diagnostic::error(message, p.as_span().into()).with_hint(hint).into());
+                }
+                drums.push(name.to_string());
+            }
+            Rule::duration => duration = Some(parse_duration(p)?),
+            Rule::dynamic => dynamic = Some(p.as_str().to_string()),
+            Rule::articulation => articulation.get_or_insert_with(String::new).push_str(p.as_str()),
+            _ => {}
+        }
+    }
+
+    Ok(DrumHit {
+        drums,
+        duration,
+        dynamic,
+        articulation,
+        span,
+    })
+}
+
+pub(crate) fn parse_pitch(pair: pest::iterators::Pair<Rule>) -> Result<Pitch> {
     let mut inner = pair.into_inner();
     let step_str = inner.next().unwrap().as_str();
     let step = step_str.chars().next().unwrap();
@@ -222,6 +552,11 @@ fn parse_pitch(pair: pest::iterators::Pair<Rule>) -> Result<Pitch> {
                 accidental = match p.as_str() {
                     "#" => Some(Accidental::Sharp),
                     "b" => Some(Accidental::Flat),
+                    "+" => Some(Accidental::QuarterSharp),
+                    "d" => Some(Accidental::QuarterFlat),
+                    "n" => Some(Accidental::Natural),
+                    "#+" => Some(Accidental::ThreeQuarterSharp),
+                    "db" => Some(Accidental::ThreeQuarterFlat),
                     _ => None,
                 };
             }
@@ -241,27 +576,66 @@ fn parse_pitch(pair: pest::iterators::Pair<Rule>) -> Result<Pitch> {
 
 fn parse_duration(pair: pest::iterators::Pair<Rule>) -> Result<Duration> {
     let mut inner = pair.into_inner();
-    let base_pair = inner.next().unwrap();
-    let base = match base_pair.as_str() {
-        "w" => BaseDuration::Whole,
-        "h" => BaseDuration::Half,
-        "q" => BaseDuration::Quarter,
-        "e" => BaseDuration::Eighth,
-        "s" => BaseDuration::Sixteenth,
-        _ => return Err(anyhow!("Unknown duration base")),
-    };
+    let first = inner.next().unwrap();
+    match first.as_rule() {
+        Rule::fraction => {
+            let mut parts = first.into_inner();
+            let num = parts.next().unwrap().as_str().parse()?;
+            let den = parts.next().unwrap().as_str().parse()?;
+            Ok(Duration::Fraction(num, den))
+        }
+        Rule::subdivision => {
+            let mut base = BaseDuration::Quarter;
+            let mut divisions = 1;
+            for p in first.into_inner() {
+                match p.as_rule() {
+                    Rule::base_duration => base = parse_base_duration(p.as_str())?,
+                    Rule::integer => divisions = p.as_str().parse()?,
+                    _ => {}
+                }
+            }
+            Ok(Duration::Subdivision(base, divisions))
+        }
+        _ => {
+            let base = parse_base_duration(first.as_str())?;
+            let dots = inner.count() as u8;
+            Ok(Duration::Base(base, dots))
+        }
+    }
+}
 
-    let dots = inner.count() as u8;
-    Ok(Duration::Base(base, dots))
+fn parse_base_duration(symbol: &str) -> Result<BaseDuration> {
+    match symbol {
+        "w" => Ok(BaseDuration::Whole),
+        "h" => Ok(BaseDuration::Half),
+        "q" => Ok(BaseDuration::Quarter),
+        "e" => Ok(BaseDuration::Eighth),
+        "s" => Ok(BaseDuration::Sixteenth),
+        "t" => Ok(BaseDuration::ThirtySecond),
+        "x" => Ok(BaseDuration::SixtyFourth),
+        _ => Err(anyhow!("Unknown duration base")),
+    }
 }
 
-fn parse_rest(pair: pest::iterators::Pair<Rule>) -> Result<Option<Duration>> {
-    let mut inner = pair.into_inner();
-    if let Some(duration_pair) = inner.next() {
-        Ok(Some(parse_duration(duration_pair)?))
-    } else {
-        Ok(None)
+fn parse_rest(pair: pest::iterators::Pair<Rule>) -> Result<(Option<Duration>, bool)> {
+    let mut duration = None;
+    let mut written = "q"; // The duration as written, for the hint below
+    let mut held = false;
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::rest_fermata => held = true,
+            // `r q .` was once a dotted rest; it no longer parses as one, so say how to write it
+            Rule::spaced_dot => {
+                let hint = format!("write a dotted rest as `r {}{}`", written, p.as_str());
+                return Err(Diagnostic::error("A dot after a rest must touch its duration", p.as_span().into()).with_hint(hint).into());
+            }
+            _ => {
+                written = p.as_str();
+                duration = Some(parse_duration(p)?);
+            }
+        }
     }
+    Ok((duration, held))
 }
 
 fn parse_tuplet(pair: pest::iterators::Pair<Rule>) -> Result<Tuplet> {
@@ -284,15 +658,51 @@ fn parse_time_signature(pair: pest::iterators::Pair<Rule>) -> Result<(u32, u32)>
     Ok((num, den))
 }
 
-fn parse_key_signature(pair: pest::iterators::Pair<Rule>) -> Result<(String, String)> {
+fn parse_key_signature(pair: pest::iterators::Pair<Rule>) -> Result<Key> {
     let mut inner = pair.into_inner();
     let pitch_class_pair = inner.next().unwrap();
     let scale_pair = inner.next().unwrap();
-    
-    let pitch_class = pitch_class_pair.as_str().trim().to_string();
-    let scale = scale_pair.as_str().trim_matches('"').to_string();
-    
-    Ok((pitch_class, scale))
+
+    let (step, accidental) = parse_tonic(pitch_class_pair.as_str().trim()).map_err(|e| {
+        let hint = "a key's tonic is a note name with an optional `#` or `b`";
+        Diagnostic::error(e.to_string(), Span::from(pitch_class_pair.as_span())).with_hint(hint)
+    })?;
+    let mode = parse_mode(scale_pair.as_str().trim_matches('"')).map_err(|e| {
+        let hint = "use \"Major\", \"Minor\", a church mode such as \"Dorian\", \"Octatonic\", \"Whole Tone\" or \"Chromatic\"";
+        Diagnostic::error(e.to_string(), Span::from(scale_pair.as_span())).with_hint(hint)
+    })?;
+    Ok(Key { step, accidental, mode })
+}
+
+fn parse_articulation_setting(pair: pest::iterators::Pair<Rule>) -> Result<ArticulationSetting> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+    let mut setting = ArticulationSetting { name, gate: None, velocity: None, hold: None };
+
+    for param in inner {
+        let param = param.into_inner().next().unwrap();
+        let value = param.clone().into_inner().next().unwrap().as_str();
+        match param.as_rule() {
+            Rule::gate_param => {
+                let gate: f64 = value.parse()?;
+                if gate <= 0.0 || gate > 1.0 {
+                    return Err(anyhow!("Articulation gate must be in (0, 1], got {}", gate));
+                }
+                setting.gate = Some(gate);
+            }
+            Rule::velocity_param => setting.velocity = Some(value.parse()?),
+            Rule::hold_param => {
+                let hold: f64 = value.parse()?;
+                if hold < 1.0 {
+                    return Err(anyhow!("Fermata hold must be at least 1.0, got {}", hold));
+                }
+                setting.hold = Some(hold);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(setting)
 }
 
 fn parse_swing_setting(pair: pest::iterators::Pair<Rule>) -> Result<Option<(BaseDuration, f64)>> {
@@ -315,14 +725,7 @@ fn parse_swing_setting(pair: pest::iterators::Pair<Rule>) -> Result<Option<(Base
     
     match first.as_rule() {
         Rule::base_duration => {
-            let base = match first.as_str() {
-                "w" => BaseDuration::Whole,
-                "h" => BaseDuration::Half,
-                "q" => BaseDuration::Quarter,
-                "e" => BaseDuration::Eighth,
-                "s" => BaseDuration::Sixteenth,
-                _ => return Err(anyhow!("Unknown duration base")),
-            };
+            let base = parse_base_duration(first.as_str())?;
             let float_pair = inner.next().ok_or_else(|| anyhow!("Missing swing ratio in: {}", pair_str))?;
             let ratio = float_pair.as_str().parse()?;
             Ok(Some((base, ratio)))
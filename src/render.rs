@@ -0,0 +1,386 @@
+//! Offline rendering of an `IrScore` to audio with a small built-in synthesizer.
+//!
+//! Each General MIDI instrument family is played by one oscillator shaped by an ADSR
+//! envelope; drums are synthesized from noise bursts and swept sine tones. Everything
+//! is deterministic, so the same score always renders to the same samples.
+
+use crate::ir::{IrEventKind, IrScore, IrTrack};
+use crate::walker::{DRUM_CHANNEL, SUSTAIN};
+use anyhow::{bail, Result};
+use std::f64::consts::PI;
+
+const CONTROL_STEP: usize = 32; // Samples between updates of pitch bend and volume
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub sample_rate: u32, // Samples per second
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions { sample_rate: 44100 }
+    }
+}
+
+/// Render a score to stereo samples, as (left, right) pairs in -1.0..=1.0.
+///
+/// Tracks are spread across the stereo field unless they set a pan (CC 10), loudness
+/// follows velocity, volume (CC 7) and expression (CC 11), the sustain pedal holds
+/// notes, and pitch bends move the notes of their channel. The mix is scaled down
+/// if it would clip.
+pub fn render(score: &IrScore, options: &RenderOptions) -> Result<Vec<[f32; 2]>> {
+    if options.sample_rate == 0 {
+        bail!("Sample rate must be above 0");
+    }
+    let tempo = TempoMap::new(score);
+    let parts: Vec<&IrTrack> = score.tracks.iter().filter(|track| track.instrument.is_some()).collect();
+
+    let mut voices = Vec::new();
+    for (index, track) in parts.iter().enumerate() {
+        let default_pan = match parts.len() {
+            1 => 0.0,
+            n => (index as f64 / (n - 1) as f64 - 0.5) * 1.2, // Keep clear of the extremes
+        };
+        voices.extend(track_voices(track, index, default_pan, &tempo));
+    }
+
+    let rate = options.sample_rate as f64;
+    let length = voices.iter().map(|voice| (voice.end * rate).ceil() as usize).max().unwrap_or(0);
+    let mut mix = vec![[0.0f64; 2]; length];
+    for voice in &voices {
+        voice.play(&mut mix, rate);
+    }
+
+    let peak = mix.iter().flatten().fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+    let scale = if peak > 0.99 { 0.99 / peak } else { 1.0 };
+    Ok(mix.into_iter().map(|[left, right]| [(left * scale) as f32, (right * scale) as f32]).collect())
+}
+
+/// Encode stereo samples as a 16-bit PCM WAV file.
+pub fn wav(samples: &[[f32; 2]], sample_rate: u32) -> Vec<u8> {
+    let data_length = (samples.len() * 4) as u32;
+    let mut bytes = Vec::with_capacity(44 + data_length as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes()); // Format chunk length
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // Channels
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 4).to_le_bytes()); // Bytes per second
+    bytes.extend_from_slice(&4u16.to_le_bytes()); // Bytes per frame
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_length.to_le_bytes());
+    for frame in samples {
+        for sample in frame {
+            bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Converts ticks to seconds, following every tempo change in the score.
+struct TempoMap {
+    segments: Vec<(u32, f64, f64)>, // (start tick, start second, seconds per tick)
+}
+
+impl TempoMap {
+    fn new(score: &IrScore) -> Self {
+        let mut changes: Vec<(u32, f64)> = score.tracks.iter()
+            .flat_map(|track| &track.events)
+            .filter_map(|event| match event.kind {
+                IrEventKind::Tempo(bpm) if bpm > 0.0 => Some((event.time, bpm)),
+                _ => None,
+            })
+            .collect();
+        changes.sort_by_key(|&(time, _)| time);
+
+        let per_tick = |bpm: f64| 60.0 / (bpm * score.ppq as f64);
+        let mut segments = vec![(0, 0.0, per_tick(120.0))]; // MIDI default tempo
+        for (time, bpm) in changes {
+            let &(start, seconds, rate) = segments.last().unwrap();
+            let at = seconds + (time - start) as f64 * rate;
+            if time == start {
+                segments.pop();
+            }
+            segments.push((time, at, per_tick(bpm)));
+        }
+        TempoMap { segments }
+    }
+
+    fn seconds(&self, tick: u32) -> f64 {
+        let index = self.segments.partition_point(|&(start, _, _)| start <= tick) - 1;
+        let (start, seconds, rate) = self.segments[index];
+        seconds + (tick - start) as f64 * rate
+    }
+}
+
+/// A controller value that changes in steps, by time in seconds.
+#[derive(Debug, Clone)]
+struct Automation {
+    steps: Vec<(f64, f64)>, // (second, value), in time order
+    initial: f64,
+}
+
+impl Automation {
+    fn new(initial: f64) -> Self {
+        Automation { steps: Vec::new(), initial }
+    }
+
+    fn at(&self, seconds: f64) -> f64 {
+        match self.steps.partition_point(|&(time, _)| time <= seconds) {
+            0 => self.initial,
+            index => self.steps[index - 1].1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wave {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+}
+
+/// How an instrument family sounds: an oscillator and its envelope.
+#[derive(Debug, Clone, Copy)]
+struct Preset {
+    wave: Wave,
+    attack: f64, // Seconds to full level
+    decay: f64, // Time constant, in seconds, of the fall towards the sustain level
+    sustain: f64, // Level held while the note lasts; 0 for sounds that die away
+    release: f64, // Seconds to silence after the note ends
+    gain: f64,
+}
+
+fn preset(program: u8) -> Preset {
+    let preset = |wave, attack, decay, sustain, release, gain| Preset { wave, attack, decay, sustain, release, gain };
+    match program / 8 {
+        0 => preset(Wave::Triangle, 0.005, 1.2, 0.0, 0.3, 0.9),       // Piano
+        1 => preset(Wave::Sine, 0.002, 0.5, 0.0, 0.3, 0.8),           // Chromatic percussion
+        2 => preset(Wave::Square, 0.01, 0.1, 0.9, 0.08, 0.35),        // Organ
+        3 => preset(Wave::Triangle, 0.003, 0.7, 0.05, 0.15, 0.9),     // Guitar
+        4 => preset(Wave::Triangle, 0.005, 0.5, 0.4, 0.1, 1.0),       // Bass
+        5 => preset(Wave::Saw, 0.06, 0.3, 0.8, 0.25, 0.45),           // Strings
+        6 => preset(Wave::Saw, 0.15, 0.5, 0.8, 0.4, 0.4),             // Ensemble
+        7 => preset(Wave::Saw, 0.04, 0.2, 0.75, 0.12, 0.45),          // Brass
+        8 => preset(Wave::Square, 0.03, 0.2, 0.8, 0.1, 0.35),         // Reed
+        9 => preset(Wave::Sine, 0.05, 0.3, 0.85, 0.15, 0.8),          // Pipe
+        10 => preset(Wave::Square, 0.01, 0.2, 0.8, 0.1, 0.35),        // Synth lead
+        11 => preset(Wave::Saw, 0.4, 1.0, 0.8, 0.6, 0.4),             // Synth pad
+        12 => preset(Wave::Sine, 0.1, 0.8, 0.5, 0.5, 0.7),            // Synth effects
+        13 => preset(Wave::Triangle, 0.003, 0.5, 0.1, 0.15, 0.9),     // Ethnic
+        14 => preset(Wave::Sine, 0.001, 0.2, 0.0, 0.1, 0.9),          // Percussive
+        _ => preset(Wave::Saw, 0.05, 0.5, 0.5, 0.3, 0.4),             // Sound effects
+    }
+}
+
+/// One note with everything needed to play it.
+struct Voice {
+    sound: Sound,
+    start: f64, // Seconds
+    release: f64, // When the key (or the pedal holding it) is let go
+    end: f64, // When the note has died away
+    gain: f64,
+    pan: f64, // -1 left to 1 right
+    level: Automation, // Channel volume times expression
+    bend: Automation, // Semitones
+}
+
+enum Sound {
+    Tone { preset: Preset, pitch: u8 },
+    Drum { key: u8, seed: u32 },
+}
+
+fn track_voices(track: &IrTrack, index: usize, default_pan: f64, tempo: &TempoMap) -> Vec<Voice> {
+    let mut events: Vec<_> = track.events.iter().collect();
+    events.sort_by_key(|event| event.time);
+
+    // Controllers first, since notes read them at any time
+    let mut pan = None;
+    let mut volume = Automation::new(100.0 / 127.0);
+    let mut expression = Automation::new(1.0);
+    let mut pedal: Vec<(f64, bool)> = Vec::new();
+    let mut bends: Vec<Automation> = vec![Automation::new(0.0); track.member_channels.len() + 1];
+    let mut parameter = (127, 127); // Registered parameter selected by CC 101 and 100
+    let mut bend_range = 2.0;
+    for event in &events {
+        let seconds = tempo.seconds(event.time);
+        match event.kind {
+            IrEventKind::ControlChange { controller, value } => {
+                let value = value as f64;
+                match controller {
+                    7 => volume.steps.push((seconds, value / 127.0)),
+                    10 if pan.is_none() => pan = Some((value - 64.0) / 63.0),
+                    11 => expression.steps.push((seconds, value / 127.0)),
+                    SUSTAIN => pedal.push((seconds, value >= 64.0)),
+                    101 => parameter.0 = value as u8,
+                    100 => parameter.1 = value as u8,
+                    6 if parameter == (0, 0) => bend_range = value,
+                    _ => {}
+                }
+            }
+            IrEventKind::PitchBend { value, member } => {
+                if let Some(bend) = bends.get_mut(member as usize) {
+                    bend.steps.push((seconds, value as f64 / 8192.0 * bend_range));
+                }
+            }
+            _ => {}
+        }
+    }
+    let pan = pan.unwrap_or(default_pan).clamp(-1.0, 1.0);
+    let mut level = Automation::new(volume.initial * expression.initial);
+    let mut changes: Vec<f64> = volume.steps.iter().chain(&expression.steps).map(|&(time, _)| time).collect();
+    changes.sort_by(f64::total_cmp);
+    level.steps = changes.into_iter().map(|time| (time, volume.at(time) * expression.at(time))).collect();
+
+    let percussion = track.channel == DRUM_CHANNEL;
+    let mut program = 0;
+    let mut voices = Vec::new();
+    for (number, event) in events.iter().enumerate() {
+        let (pitch, velocity, duration, member) = match event.kind {
+            IrEventKind::Note { pitch, velocity, duration, member, .. } => (pitch, velocity, duration, member),
+            IrEventKind::ProgramChange(new) => {
+                program = new;
+                continue;
+            }
+            _ => continue,
+        };
+        let start = tempo.seconds(event.time);
+        let mut release = tempo.seconds(event.time + duration);
+        // A pedal down when the key is let go holds the note until it is lifted
+        let down = pedal.iter().rev().find(|&&(time, _)| time <= release).is_some_and(|&(_, down)| down);
+        if down {
+            release = pedal.iter().find(|&&(time, down)| time > release && !down).map_or(release, |&(time, _)| time);
+        }
+        let (sound, end) = if percussion {
+            let seed = (index as u32 + 1).wrapping_mul(0x9E37_79B9) ^ (number as u32 + 1).wrapping_mul(0x85EB_CA6B);
+            (Sound::Drum { key: pitch, seed }, start + drum_length(pitch))
+        } else {
+            let preset = preset(program);
+            (Sound::Tone { preset, pitch }, release.max(start + preset.attack) + preset.release)
+        };
+        voices.push(Voice {
+            sound,
+            start,
+            release,
+            end,
+            gain: (velocity as f64 / 127.0).powi(2), // Velocity is heard roughly as loudness in decibels
+            pan,
+            level: level.clone(),
+            bend: bends.get(member as usize).cloned().unwrap_or_else(|| Automation::new(0.0)),
+        });
+    }
+    voices
+}
+
+impl Voice {
+    fn play(&self, mix: &mut [[f64; 2]], rate: f64) {
+        // Constant-power panning
+        let angle = (self.pan + 1.0) * PI / 4.0;
+        let (left, right) = (angle.cos(), angle.sin());
+        let first = (self.start * rate).round() as usize;
+        let last = ((self.end * rate).ceil() as usize).min(mix.len());
+
+        let mut phase = 0.0;
+        let mut filtered = 0.0;
+        let mut noise = Noise(match self.sound {
+            Sound::Drum { seed, .. } => seed | 1,
+            Sound::Tone { .. } => 1,
+        });
+        let mut level = 0.0;
+        let mut frequency = 0.0;
+        for (offset, frame) in mix[first.min(last)..last].iter_mut().enumerate() {
+            let seconds = self.start + offset as f64 / rate;
+            let t = seconds - self.start;
+            if offset % CONTROL_STEP == 0 {
+                level = self.level.at(seconds) * self.gain;
+                if let Sound::Tone { pitch, .. } = self.sound {
+                    frequency = 440.0 * 2f64.powf((pitch as f64 + self.bend.at(seconds) - 69.0) / 12.0);
+                }
+            }
+            let sample = match self.sound {
+                Sound::Tone { preset, .. } => {
+                    phase = (phase + frequency / rate).fract();
+                    let raw = match preset.wave {
+                        Wave::Sine => (2.0 * PI * phase).sin(),
+                        Wave::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+                        Wave::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+                        Wave::Saw => 2.0 * phase - 1.0,
+                    };
+                    // Soften the upper harmonics with a one-pole low-pass filter
+                    let cutoff = (frequency * 6.0).min(8000.0);
+                    filtered += (1.0 - (-2.0 * PI * cutoff / rate).exp()) * (raw - filtered);
+                    filtered * self.envelope(preset, t) * preset.gain
+                }
+                Sound::Drum { key, .. } => drum_sample(key, t, &mut noise),
+            };
+            let sample = sample * level * 0.3;
+            frame[0] += sample * left;
+            frame[1] += sample * right;
+        }
+    }
+
+    /// ADSR level at `t` seconds into the note.
+    fn envelope(&self, preset: Preset, t: f64) -> f64 {
+        let held = |t: f64| {
+            if t < preset.attack {
+                t / preset.attack
+            } else {
+                preset.sustain + (1.0 - preset.sustain) * (-(t - preset.attack) / preset.decay).exp()
+            }
+        };
+        let release_at = (self.release - self.start).max(preset.attack);
+        if t < release_at {
+            held(t)
+        } else {
+            held(release_at) * (1.0 - (t - release_at) / preset.release).max(0.0)
+        }
+    }
+}
+
+/// Deterministic white noise (xorshift).
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f64 / u32::MAX as f64 * 2.0 - 1.0
+    }
+}
+
+/// How long a drum rings, in seconds, whatever its written duration.
+fn drum_length(key: u8) -> f64 {
+    match key {
+        35 | 36 => 0.5,                      // Kick
+        42 | 44 => 0.1,                      // Closed and pedal hi-hat
+        46 => 0.5,                           // Open hi-hat
+        49 | 51 | 52 | 53 | 55 | 57 | 59 => 1.5, // Cymbals
+        _ => 0.35,
+    }
+}
+
+/// One sample of General MIDI drum `key`, `t` seconds after it is struck.
+fn drum_sample(key: u8, t: f64, noise: &mut Noise) -> f64 {
+    let tone = |frequency: f64| (2.0 * PI * frequency * t).sin();
+    match key {
+        // Kick: a sine dropping quickly in pitch
+        35 | 36 => {
+            let phase = 55.0 * t + 100.0 * (1.0 - (-t * 30.0).exp()) / 30.0;
+            (2.0 * PI * phase).sin() * (-t * 8.0).exp()
+        }
+        // Snares and claps: noise over a short tone
+        37..=40 => 0.6 * noise.next() * (-t * 18.0).exp() + 0.4 * tone(190.0) * (-t * 25.0).exp(),
+        42 | 44 => 0.5 * noise.next() * (-t * 60.0).exp(),
+        46 => 0.5 * noise.next() * (-t * 8.0).exp(),
+        49 | 52 | 55 | 57 => 0.4 * noise.next() * (-t * 3.0).exp(),
+        51 | 53 | 59 => 0.3 * noise.next() * (-t * 5.0).exp() + 0.1 * tone(3000.0) * (-t * 4.0).exp(),
+        // Toms: lower keys are lower drums
+        41 | 43 | 45 | 47 | 48 | 50 => tone(60.0 + (key as f64 - 41.0) * 15.0) * (-t * 10.0).exp(),
+        _ => 0.5 * noise.next() * (-t * 25.0).exp(),
+    }
+}
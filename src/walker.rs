@@ -1,27 +1,139 @@
 use crate::ast::*;
+use crate::diagnostics::{Diagnostic, Severity};
 use crate::ir::*;
-use crate::instruments::get_instrument_program;
+use crate::drums::get_drum_note;
+use crate::instruments::{get_instrument_program, is_keyboard, is_percussion, is_sustaining};
+use crate::keys::{key_fifths, step_alterations, step_index, STEPS};
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use crate::motifs::expand_motifs;
+use crate::accidentals::{apply_key, apply_key_to_motifs};
+use num_rational::Ratio;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-const PPQ: u32 = 480;
+/// Ticks per quarter note when the score's rhythms fit it.
+pub const DEFAULT_PPQ: u32 = 480;
+const MAX_PPQ: u32 = 0x7fff; // The MIDI header stores the resolution in 15 bits
+
+/// A point or span in musical time, in whole notes. Timing is kept exact and
+/// only rounded to ticks when an event is placed.
+pub(crate) type Position = Ratio<u64>;
+pub(crate) const DRUM_CHANNEL: u8 = 9; // General MIDI percussion (channel 10)
+pub(crate) const SUSTAIN: u8 = 64; // Sustain pedal controller
+
+/// Options controlling how a score is turned into IR.
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    /// Expand repeats and D.C./D.S. jumps into the order the music is played.
+    /// When false, parts are walked as written and the structure is kept as
+    /// `IrEventKind::Mark` events (used for notation export).
+    pub unroll_repeats: bool,
+    /// Treat measures whose length does not match the time signature as errors
+    /// rather than warnings.
+    pub strict: bool,
+    /// Ticks per quarter note, overriding the score's `PPQ:` header. When neither
+    /// is given, the smallest multiple of `DEFAULT_PPQ` that places every event
+    /// exactly is used (up to the MIDI limit).
+    pub ppq: Option<u32>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions { unroll_repeats: true, strict: false, ppq: None }
+    }
+}
 
 pub fn walk(score: &Score) -> Result<IrScore> {
+    walk_with_options(score, &WalkOptions::default())
+}
+
+pub fn walk_with_options(score: &Score, options: &WalkOptions) -> Result<IrScore> {
+    let (ir, warnings) = walk_with_diagnostics(score, options)?;
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(ir)
+}
+
+/// Walk a score, returning warnings as diagnostics instead of printing them.
+///
+/// Errors carry a `Diagnostic` too, retrievable with `err.downcast_ref::<Diagnostic>()`.
+pub fn walk_with_diagnostics(score: &Score, options: &WalkOptions) -> Result<(IrScore, Vec<Diagnostic>)> {
+    let header_ppq = score.headers.iter().rev().find_map(|h| match h {
+        Header::Ppq(ppq) => Some(*ppq),
+        _ => None,
+    });
+    if let Some(ppq) = options.ppq.or(header_ppq) {
+        if !(1..=MAX_PPQ).contains(&ppq) {
+            return Err(anyhow!("PPQ must be between 1 and {}, got {}", MAX_PPQ, ppq));
+        }
+        let (ir, diagnostics, _) = walk_at(score, options, ppq)?;
+        return Ok((ir, diagnostics));
+    }
+
+    // Walk at the default resolution, and again if some rhythm falls between its ticks
+    let (ir, diagnostics, resolution) = walk_at(score, options, DEFAULT_PPQ)?;
+    if (DEFAULT_PPQ as u64).is_multiple_of(resolution) {
+        return Ok((ir, diagnostics));
+    }
+    let (ir, diagnostics, _) = walk_at(score, options, automatic_ppq(resolution))?;
+    Ok((ir, diagnostics))
+}
+
+/// The PPQ for a score whose onsets all fall on multiples of `1/resolution` quarter notes.
+fn automatic_ppq(resolution: u64) -> u32 {
+    // Too fine for MIDI; round to the finest multiple of the default, and each part
+    // that no longer lands on its ticks is warned about
+    exact_ppq(resolution).unwrap_or(MAX_PPQ / DEFAULT_PPQ * DEFAULT_PPQ)
+}
+
+/// The smallest PPQ placing every multiple of `1/resolution` quarter notes exactly,
+/// preferring multiples of the default, if MIDI allows one.
+fn exact_ppq(resolution: u64) -> Option<u32> {
+    let lcm = DEFAULT_PPQ as u64 / gcd(DEFAULT_PPQ as u64, resolution) * resolution;
+    if lcm <= MAX_PPQ as u64 {
+        Some(lcm as u32)
+    } else if resolution <= MAX_PPQ as u64 {
+        Some(resolution as u32)
+    } else {
+        None
+    }
+}
+
+/// Walk a score at a fixed PPQ, also returning the resolution its rhythms need.
+fn walk_at(score: &Score, options: &WalkOptions, ppq: u32) -> Result<(IrScore, Vec<Diagnostic>, u64)> {
+    let mut diagnostics = Vec::new();
+    let mut resolution = 1;
     let mut tracks: Vec<IrTrack> = Vec::new();
-    let mut track_map: HashMap<String, (usize, u32)> = HashMap::new(); // Name -> (index, end_time)
+    let mut track_map: HashMap<String, (usize, u32, usize)> = HashMap::new(); // Name -> (index, end_time, written measures)
+    let mut tempo_marks = Vec::new(); // From every part, as tempo applies to the whole score
     
     // Create a conductor track for global events like Tempo and Time Signature
     let mut conductor_events = Vec::new();
 
     // Default Time Signature if not present
     let mut has_time_signature = false;
+    let mut initial_tempo = 120.0; // MIDI default when no tempo is given
 
     for header in &score.headers {
         match header {
-            Header::Tempo(bpm) => {
+            // MIDI expects the copyright notice before any other event
+            Header::Copyright(notice) => conductor_events.insert(0, IrEvent {
+                time: 0,
+                kind: IrEventKind::Copyright(notice.clone()),
+            }),
+            Header::Title(title) => conductor_events.push(IrEvent {
+                time: 0,
+                kind: IrEventKind::TrackName(title.clone()),
+            }),
+            Header::Text(text) => conductor_events.push(IrEvent {
+                time: 0,
+                kind: IrEventKind::Text(text.clone()),
+            }),
+            Header::Tempo(tempo) => {
+                initial_tempo = quarter_bpm(tempo)?;
                 conductor_events.push(IrEvent {
                     time: 0,
-                    kind: IrEventKind::Tempo(*bpm),
+                    kind: IrEventKind::Tempo(initial_tempo),
                 });
             }
             Header::TimeSignature(num, den) => {
@@ -31,6 +143,12 @@ pub fn walk(score: &Score) -> Result<IrScore> {
                     kind: IrEventKind::TimeSignature(*num, *den),
                 });
             }
+            Header::KeySignature(key) => {
+                conductor_events.push(IrEvent {
+                    time: 0,
+                    kind: IrEventKind::KeySignature(*key),
+                });
+            }
             _ => {}
         }
     }
@@ -42,78 +160,200 @@ pub fn walk(score: &Score) -> Result<IrScore> {
         });
     }
 
-    let mut global_time_signature = (4, 4);
-    let mut global_swing = None;
+    let mut defaults = PartDefaults {
+        time_signature: (4, 4),
+        swing: None,
+        key: None,
+        motifs: &score.motifs,
+        articulations: ArticulationTable::new(&score.headers)?,
+        ppq,
+        bend_range: 2, // General MIDI default
+        accidentals: AccidentalMode::Explicit,
+    };
     for header in &score.headers {
         match header {
-            Header::TimeSignature(num, den) => global_time_signature = (*num, *den),
-            Header::Swing(swing) => global_swing = *swing,
+            Header::TimeSignature(num, den) => defaults.time_signature = (*num, *den),
+            Header::Swing(swing) => defaults.swing = *swing,
+            Header::KeySignature(key) => defaults.key = Some(*key),
+            Header::BendRange(range) => defaults.bend_range = *range,
+            Header::Accidentals(mode) => defaults.accidentals = *mode,
             _ => {}
         }
     }
 
-    let mut next_channel = 0;
-
     for part in &score.parts {
-        if let Some(&(index, current_end_time)) = track_map.get(&part.name) {
+        let measures = measure_count(&part.content);
+        if let Some(&(index, current_end_time, measures_before)) = track_map.get(&part.name) {
             // Merge with existing track
-            let channel = tracks[index].channel;
-            let (mut new_track, duration) = walk_part(part, channel, global_time_signature, global_swing)?;
+            let (mut new_track, mut marks, duration, part_resolution) =
+                walk_part(part, measures_before, &defaults, options, &mut diagnostics)?;
+            resolution = lcm(resolution, part_resolution);
 
             // Shift events
             for event in &mut new_track.events {
                 event.time += current_end_time;
             }
+            for mark in &mut marks {
+                mark.shift(current_end_time);
+            }
+            tempo_marks.append(&mut marks);
 
             tracks[index].events.extend(new_track.events);
             
             // Update map
-            track_map.insert(part.name.clone(), (index, current_end_time + duration));
+            track_map.insert(part.name.clone(), (index, current_end_time + duration, measures_before + measures));
         } else {
             // New track
-            let channel = next_channel;
-            next_channel = (next_channel + 1) % 16; // Wrap around 0-15
-
-            let (new_track, duration) = walk_part(part, channel, global_time_signature, global_swing)?;
+            let (new_track, mut marks, duration, part_resolution) = walk_part(part, 0, &defaults, options, &mut diagnostics)?;
+            resolution = lcm(resolution, part_resolution);
+            tempo_marks.append(&mut marks);
             tracks.push(new_track);
-            track_map.insert(part.name.clone(), (tracks.len() - 1, duration));
+            track_map.insert(part.name.clone(), (tracks.len() - 1, duration, measures));
+        }
+    }
+
+    // Channels are assigned once each track is complete, since whether it can share one
+    // depends on everything it plays. Parts that play quarter tones also get member
+    // channels nobody else uses, on their own port.
+    let mut channels = ChannelAllocator::default();
+    let span_of = |track: &IrTrack| score.parts.iter().find(|p| p.name == track.name).map(|p| p.span).unwrap_or_default();
+    for track in &mut tracks {
+        let instrument = track.instrument.as_deref().unwrap_or_default();
+        let members = track.events.iter().filter_map(|e| match e.kind {
+            IrEventKind::Note { member, .. } => Some(member as usize),
+            _ => None,
+        }).max().unwrap_or(0);
+        let (port, channel) = channels.allocate(instrument, sends_channel_messages(track), members)
+            .map_err(|e| Diagnostic::locate(e, span_of(track)))?;
+        track.port = port;
+        track.channel = channel;
+        for _ in 0..members {
+            let Some(channel) = channels.spare(port) else {
+                let message = format!("Not enough free MIDI channels on port {} for the quarter tones in part '{}'", port, track.name);
+                let hint = "each quarter tone sounding at once needs a channel of its own on the port of the part's channel, \
+                    which it shares with the other parts of its instrument; use fewer instruments or thinner chords";
+                return Err(Diagnostic::error(message, span_of(track)).with_hint(hint).into());
+            };
+            track.member_channels.push(channel);
         }
     }
 
+    conductor_events.extend(conduct(initial_tempo, tempo_marks, ppq, &mut diagnostics));
+
     // Sort conductor events
-    conductor_events.sort_by(|a, b| a.time.cmp(&b.time));
+    conductor_events.sort_by_key(|a| a.time);
     conductor_events.dedup();
 
     tracks.insert(0, IrTrack {
         name: "Conductor".to_string(),
+        instrument: None,
+        port: 0,
         channel: 0, // Channel doesn't matter for Meta events, but 0 is fine
+        member_channels: Vec::new(),
         events: conductor_events,
     });
 
-    Ok(IrScore { tracks, ppq: PPQ })
+    Ok((IrScore { tracks, ppq }, diagnostics, resolution))
+}
+
+/// Assigns each part a MIDI port and channel.
+///
+/// Parts with the same program share a channel unless either sends control changes or
+/// pitch bends, which act on every note of the channel; such a part gets a channel of
+/// its own. Percussion always plays on the drum channel. A part takes the first port
+/// with room for its channel and the member channels its quarter tones need, so once
+/// the 15 melodic channels of a port are used up further parts spill onto the next.
+#[derive(Debug, Default)]
+struct ChannelAllocator {
+    shared: HashMap<u8, (u8, u8)>, // Program -> (port, channel) of the parts sharing it
+    used: HashSet<(u8, u8)>, // Every melodic (port, channel) given out
 }
 
-fn walk_part(part: &Part, channel: u8, initial_time_signature: (u32, u32), initial_swing: Option<(BaseDuration, f64)>) -> Result<(IrTrack, u32)> {
+impl ChannelAllocator {
+    const MAX_PORTS: usize = 128;
+
+    /// A channel for a part playing `instrument`; `exclusive` when the part sends
+    /// messages that would reach other parts on a shared channel, and `members` the
+    /// member channels it will ask `spare` for.
+    fn allocate(&mut self, instrument: &str, exclusive: bool, members: usize) -> Result<(u8, u8)> {
+        if is_percussion(instrument) {
+            return Ok((0, DRUM_CHANNEL));
+        }
+        let program = get_instrument_program(instrument).unwrap_or(0);
+        if !exclusive && let Some(&assigned) = self.shared.get(&program) {
+            return Ok(assigned);
+        }
+
+        let port = (0..Self::MAX_PORTS as u8).find(|&port| self.free(port).count() > members)
+            .ok_or_else(|| anyhow!("Too many parts for {} MIDI ports", Self::MAX_PORTS))?;
+        let channel = self.free(port).next().unwrap();
+        self.used.insert((port, channel));
+        if !exclusive {
+            self.shared.insert(program, (port, channel));
+        }
+        Ok((port, channel))
+    }
+
+    /// A melodic channel on `port` that no part has yet, handed out as a member channel.
+    fn spare(&mut self, port: u8) -> Option<u8> {
+        let channel = self.free(port).next()?;
+        self.used.insert((port, channel));
+        Some(channel)
+    }
+
+    fn free(&self, port: u8) -> impl Iterator<Item = u8> + '_ {
+        (0..16).filter(move |&channel| channel != DRUM_CHANNEL && !self.used.contains(&(port, channel)))
+    }
+}
+
+/// Whether a track sends control changes or pitch bends on its own channel, which
+/// would also shape the notes of any other part there. Quarter tones bend member
+/// channels only, and the bend range set up for them (RPN 0) is the score's, the same
+/// on every channel.
+fn sends_channel_messages(track: &IrTrack) -> bool {
+    track.events.iter().any(|e| match e.kind {
+        IrEventKind::ControlChange { controller, .. } => !matches!(controller, 6 | 38 | 100 | 101),
+        IrEventKind::PitchBend { member, .. } => member == 0,
+        _ => false,
+    })
+}
+
+/// Score-level settings that every part starts from.
+struct PartDefaults<'a> {
+    time_signature: (u32, u32),
+    swing: Option<(BaseDuration, f64)>,
+    key: Option<Key>,
+    motifs: &'a BTreeMap<String, Vec<Event>>,
+    articulations: ArticulationTable,
+    ppq: u32,
+    bend_range: u8,
+    accidentals: AccidentalMode,
+}
+
+/// Walk one `Part:` block. `measures_before` counts the written measures of earlier
+/// blocks with the same name, which this one continues.
+fn walk_part(
+    part: &Part,
+    measures_before: usize,
+    defaults: &PartDefaults,
+    options: &WalkOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<(IrTrack, Vec<TempoMark>, u32, u64)> {
+    let initial_time_signature = defaults.time_signature;
     let mut events = Vec::new();
-    let mut current_time = 0;
-    let mut current_velocity = 100; // Default velocity (mf)
     let mut current_time_signature = initial_time_signature;
-    let mut current_swing = initial_swing;
-    let mut measure_index = 0;
+    // Diagnostics number measures as written, so a repeated measure is checked once
+    let mut checked = HashSet::new();
+    let mut keyed_motifs = None; // Motifs spelled in the key they were last used in, for `Accidentals: Key`
+    let mut tied_over: HashMap<u8, Vec<Pitch>> = HashMap::new(); // Spelled pitches each voice ties into the next measure
 
-    // Add Program Change event if instrument is found
-    if let Some(program) = get_instrument_program(&part.instrument) {
-        events.push(IrEvent {
-            time: 0,
-            kind: IrEventKind::ProgramChange(program),
-        });
-    } else {
-        // Default to Piano (0) if not found
-        events.push(IrEvent {
-            time: 0,
-            kind: IrEventKind::ProgramChange(0),
-        });
-    }
+    // Add Program Change event, defaulting to Piano (0) if the instrument is not found
+    let program = get_instrument_program(&part.instrument).unwrap_or(0);
+    let percussion = is_percussion(&part.instrument);
+    events.push(IrEvent {
+        time: 0,
+        kind: IrEventKind::ProgramChange(program),
+    });
 
     // Emit initial Time Signature
     events.push(IrEvent {
@@ -121,58 +361,209 @@ fn walk_part(part: &Part, channel: u8, initial_time_signature: (u32, u32), initi
         kind: IrEventKind::TimeSignature(initial_time_signature.0, initial_time_signature.1),
     });
 
-    for block in &part.content {
-        match block {
-            MeasureBlock::Measure(measure) => {
-                measure_index += 1;
-                
-                // Verify measure duration
-                let expected_ticks = (current_time_signature.0 as u64 * PPQ as u64 * 4 / current_time_signature.1 as u64) as u32;
-                if let Ok(actual_ticks) = calculate_measure_duration(measure, PPQ) {
-                    if actual_ticks != expected_ticks {
-                        eprintln!("Warning: Measure {} in part '{}' has incorrect duration. Expected {} ticks, got {}.", 
-                            measure_index, part.name, expected_ticks, actual_ticks);
+    let mut state = PartState {
+        time: 0,
+        events,
+        velocity: 100, // Default velocity (mf)
+        swing: defaults.swing,
+        tempo_marks: Vec::new(),
+        ties: TieState::default(),
+        key: defaults.key,
+        voice: 1,
+        voice_ties: HashMap::new(),
+        articulations: &defaults.articulations,
+        hairpin: None,
+        pedal: false,
+        expression: is_sustaining(program) && !percussion,
+        percussion,
+        keyboard: is_keyboard(program) && !percussion,
+        bend_range: defaults.bend_range,
+        members: Vec::new(),
+        position: Position::from_integer(0),
+        resolution: 1,
+        ppq: defaults.ppq,
+    };
+
+    let steps = if options.unroll_repeats {
+        unroll(&part.content)
+            .map_err(|e| Diagnostic::locate(anyhow!("{} in part '{}'", e, part.name), part.span))?
+    } else {
+        let mut steps = Vec::new();
+        written(&part.content, 1, &mut steps);
+        steps
+    };
+
+    for step in steps {
+        match step {
+            Step::Measure(measure, number) => {
+                let number = measures_before + number;
+
+                // Spell key-relative pitches and expand motif invocations before timing
+                let key_relative = defaults.accidentals == AccidentalMode::Key;
+                if key_relative && keyed_motifs.as_ref().is_none_or(|(key, _)| *key != state.key) {
+                    let motifs = apply_key_to_motifs(defaults.motifs, state.key).map_err(|e| Diagnostic::locate(e, part.span))?;
+                    keyed_motifs = Some((state.key, motifs));
+                }
+                let motifs = keyed_motifs.as_ref().map_or(defaults.motifs, |(_, motifs)| motifs);
+
+                let expanded;
+                let measure = if key_relative || voices_of(measure).iter().any(|(_, events, _)| measure_contains_motif(events)) {
+                    let key = state.key;
+                    let mut expand = |number: u8, events: &[Event], span: Span| -> Result<Vec<Event>> {
+                        let mut events = events.to_vec();
+                        if key_relative {
+                            let tied = tied_over.entry(number).or_default();
+                            apply_key(&mut events, key, tied).map_err(|e| Diagnostic::locate(e, span))?;
+                        }
+                        expand_motifs(&events, motifs, key).map_err(|e| Diagnostic::locate(e, span))
+                    };
+                    let events = expand(1, &measure.events, measure.span)?;
+                    let mut voices = Vec::new();
+                    for voice in &measure.voices {
+                        voices.push(Voice { events: expand(voice.number, &voice.events, voice.span)?, ..voice.clone() });
+                    }
+                    expanded = Measure { events, voices, ..measure.clone() };
+                    &expanded
+                } else {
+                    measure
+                };
+                let voices = voices_of(measure);
+
+                // Verify the duration of each voice, the first time the measure is played
+                let expected = Position::new(current_time_signature.0 as u64, current_time_signature.1 as u64);
+                let beat = current_time_signature.1 as u64;
+                let beats = |length: Position| length * beat;
+                let unchecked = if checked.insert(number) { voices.as_slice() } else { &[] };
+                for &(voice, events, span) in unchecked {
+                    let label = if voice == 1 { String::new() } else { format!(" (voice {})", voice) };
+                    let problem = match (measure.kind, events_length(events)) {
+                        (MeasureKind::Pickup, _) if number > 1 => Some(Diagnostic::error(
+                            format!("Pickup measure {} in part '{}' is not the first measure", number, part.name),
+                            measure.span,
+                        ).with_hint("use `Irregular` for a short measure later in the piece")),
+                        (MeasureKind::Pickup, Ok(actual)) if actual == Position::from_integer(0) || actual >= expected => {
+                            let message = format!("Pickup in part '{}'{} should be shorter than a full measure ({} beats), got {}.",
+                                part.name, label, beats(expected), beats(actual));
+                            Some(Diagnostic::warning(message, span).with_hint("remove `Pickup` if this is a full measure"))
+                        }
+                        (MeasureKind::Regular, Ok(actual)) if actual != expected => {
+                            let message = format!("Measure {} in part '{}'{} has incorrect duration. Expected {} beats, got {}.",
+                                number, part.name, label, beats(expected), beats(actual));
+                            Some(Diagnostic::warning(message, span)
+                                .with_hint(duration_hint(expected, actual, beat)))
+                        }
+                        _ => None,
+                    };
+                    if let Some(mut diagnostic) = problem {
+                        if options.strict {
+                            diagnostic.severity = Severity::Error;
+                        }
+                        if diagnostic.severity == Severity::Error {
+                            return Err(diagnostic.into());
+                        }
+                        diagnostics.push(diagnostic);
                     }
                 }
 
-                for event in &measure.events {
-                    process_event(event, &mut current_time, &mut events, 1.0, &mut current_velocity, current_swing)?;
+                // Every voice starts at the barline; the next measure starts after the longest
+                let start = (state.time, state.position);
+                let mut end = start;
+                for &(voice, events, span) in &voices {
+                    (state.time, state.position) = start;
+                    switch_voice(&mut state, voice);
+                    for event in events {
+                        process_event(event, &mut state, Position::from_integer(1)).map_err(|e| Diagnostic::locate(e, span))?;
+                    }
+                    if state.position > end.1 {
+                        end = (state.time, state.position);
+                    }
                 }
+                switch_voice(&mut state, 1);
+                (state.time, state.position) = end;
             }
-            MeasureBlock::ContextChange(cc) => {
+            Step::Mark(mark) => {
+                state.events.push(IrEvent {
+                    time: state.time,
+                    kind: IrEventKind::Mark(mark),
+                });
+            }
+            Step::ContextChange(cc) => {
                 match cc {
                     ContextChange::TimeSignature(num, den) => {
                         current_time_signature = (*num, *den);
-                        events.push(IrEvent {
-                            time: current_time,
+                        state.events.push(IrEvent {
+                            time: state.time,
                             kind: IrEventKind::TimeSignature(*num, *den),
                         });
                     }
-                    ContextChange::KeySignature(root, scale) => {
-                        events.push(IrEvent {
-                            time: current_time,
-                            kind: IrEventKind::KeySignature {
-                                root: root.clone(),
-                                scale: scale.clone(),
-                            },
+                    ContextChange::KeySignature(key) => {
+                        state.key = Some(*key);
+                        state.events.push(IrEvent {
+                            time: state.time,
+                            kind: IrEventKind::KeySignature(*key),
                         });
                     }
-                    ContextChange::Tempo(bpm) => {
-                        events.push(IrEvent {
-                            time: current_time,
-                            kind: IrEventKind::Tempo(*bpm),
+                    ContextChange::Tempo(tempo) => {
+                        let bpm = quarter_bpm(tempo)?;
+                        state.tempo_marks.push(TempoMark::Set { time: state.time, bpm });
+                    }
+                    ContextChange::TempoRamp(ramp) => {
+                        let length = Position::from_integer(ramp.beats as u64) * duration_length(&ramp.target.beat)?;
+                        state.tempo_marks.push(TempoMark::Ramp {
+                            start: state.time,
+                            end: state.time + to_ticks(length, state.ppq),
+                            to: quarter_bpm(&ramp.target)?,
+                            curve: ramp.curve,
+                            accelerando: ramp.accelerando,
+                            span: ramp.span,
                         });
                     }
                     ContextChange::Swing(swing) => {
-                        current_swing = *swing;
+                        state.swing = *swing;
                     }
                 }
             }
         }
     }
 
+    // A hairpin still open at the end of the part moves one dynamic level
+    if let Some(hairpin) = state.hairpin.take() {
+        let target = hairpin.default_target();
+        finish_hairpin(&mut state, hairpin, target);
+    }
+
+    // Set the bend range on the part's channels (RPN 0) if it bends at all
+    if state.events.iter().any(|e| matches!(e.kind, IrEventKind::PitchBend { .. })) {
+        let setup = [(101, 0), (100, 0), (6, state.bend_range), (38, 0), (101, 127), (100, 127)];
+        for (i, (controller, value)) in setup.into_iter().enumerate() {
+            state.events.insert(1 + i, IrEvent {
+                time: 0,
+                kind: IrEventKind::ControlChange { controller, value },
+            });
+        }
+    }
+
+    // Lift the pedal at the end of the part rather than leaving it down for whatever plays next
+    if state.pedal {
+        let end = state.time;
+        control(&mut state, end, SUSTAIN, 0);
+    }
+
+    if !(defaults.ppq as u64).is_multiple_of(state.resolution) {
+        let message = format!("Timing in part '{}' is rounded to the nearest tick at {} PPQ", part.name, defaults.ppq);
+        let hint = match exact_ppq(state.resolution) {
+            Some(ppq) => format!("`PPQ: {}` keeps this part's rhythms exact, as would simpler tuplets", ppq),
+            None => format!("no PPQ up to {} places this part's rhythms exactly; use simpler tuplets", MAX_PPQ),
+        };
+        diagnostics.push(Diagnostic::warning(message, part.span).with_hint(hint));
+    }
+
+    let current_time = state.time;
+    let resolution = state.resolution;
+    let mut events = state.events;
+    // Hairpin and bend ramps add events behind the notes they shape
+    events.sort_by_key(|e| e.time);
 
-    
     // Dedup to remove redundant TimeSignatures (e.g. global default + explicit context change to same value)
     events.dedup();
 
@@ -181,10 +572,8 @@ fn walk_part(part: &Part, channel: u8, initial_time_signature: (u32, u32), initi
     let mut t0_ts_indices = Vec::new();
     
     for (i, event) in events.iter().enumerate() {
-        if event.time == 0 {
-            if let IrEventKind::TimeSignature(_, _) = event.kind {
-                t0_ts_indices.push(i);
-            }
+        if event.time == 0 && matches!(event.kind, IrEventKind::TimeSignature(_, _)) {
+            t0_ts_indices.push(i);
         }
     }
 
@@ -205,128 +594,1033 @@ fn walk_part(part: &Part, channel: u8, initial_time_signature: (u32, u32), initi
 
     Ok((IrTrack {
         name: part.name.clone(),
-        channel,
+        instrument: Some(part.instrument.clone()),
+        port: 0, // Port and channels are assigned once every part is walked
+        channel: 0,
+        member_channels: Vec::new(),
         events,
-    }, current_time))
+    }, state.tempo_marks, current_time, resolution))
 }
 
-fn process_event(
-    event: &Event,
-    current_time: &mut u32,
-    events: &mut Vec<IrEvent>,
-    time_scale: f64,
-    current_velocity: &mut u8,
-    current_swing: Option<(BaseDuration, f64)>,
-) -> Result<()> {
-    match event {
-        Event::Note(note) => {
-            let duration = calculate_duration(&note.duration, PPQ)?;
-            let mut scaled_duration = (duration as f64 * time_scale).round() as u32;
-            
-            // Apply swing
-            if let Some((swing_base, ratio)) = current_swing {
-                let swing_dur = calculate_duration(&Some(Duration::Base(swing_base.clone(), 0)), PPQ)?;
-                if duration == swing_dur {
-                    let is_onbeat = (*current_time % (swing_dur * 2)) == 0;
-                    if is_onbeat {
-                        scaled_duration = (duration as f64 * ratio * 2.0).round() as u32;
-                    } else {
-                        // Ensure the pair sums exactly to 2 * duration
-                        let onbeat_dur = (duration as f64 * ratio * 2.0).round() as u32;
-                        scaled_duration = (duration * 2) - onbeat_dur;
+/// A single item of a part's timeline, after repeat structure has been resolved.
+pub(crate) enum Step<'a> {
+    Measure(&'a Measure, usize), // Numbered as written, from 1 at the start of the block
+    ContextChange(&'a ContextChange),
+    Mark(Mark),
+}
+
+/// Flatten repeats, endings and D.C./D.S. jumps into the order the music is played.
+///
+/// On the return pass after a D.C./D.S., repeats are not taken and only their final
+/// ending is played, following the usual convention.
+fn unroll(blocks: &[MeasureBlock]) -> Result<Vec<Step<'_>>> {
+    let mut steps = Vec::new();
+    let find = |nav: Navigation, from: usize| {
+        blocks.iter().skip(from).position(|b| *b == MeasureBlock::Navigation(nav)).map(|i| i + from)
+    };
+
+    let jump = blocks.iter().position(|b| {
+        matches!(b, MeasureBlock::Navigation(Navigation::DaCapo(_) | Navigation::DalSegno(_)))
+    });
+    let Some(jump_index) = jump else {
+        expand(blocks, 1, true, &mut steps);
+        return Ok(steps);
+    };
+    let first = |index: usize| measure_count(&blocks[..index]) + 1;
+
+    expand(&blocks[..jump_index], 1, true, &mut steps);
+
+    let (target, end, name) = match blocks[jump_index] {
+        MeasureBlock::Navigation(Navigation::DaCapo(end)) => (0, end, "D.C."),
+        MeasureBlock::Navigation(Navigation::DalSegno(end)) => {
+            let segno = find(Navigation::Segno, 0)
+                .filter(|&i| i < jump_index)
+                .ok_or_else(|| anyhow!("D.S. without a preceding Segno"))?;
+            (segno, end, "D.S.")
+        }
+        _ => unreachable!("jump index should point at a D.C. or D.S."),
+    };
+
+    match end {
+        JumpEnd::Plain => {
+            let stop = find(Navigation::Fine, target).filter(|&i| i < jump_index).unwrap_or(jump_index);
+            expand(&blocks[target..stop], first(target), false, &mut steps);
+        }
+        JumpEnd::AlFine => {
+            let fine = find(Navigation::Fine, target)
+                .filter(|&i| i < jump_index)
+                .ok_or_else(|| anyhow!("{} al Fine without a Fine before the jump", name))?;
+            expand(&blocks[target..fine], first(target), false, &mut steps);
+        }
+        JumpEnd::AlCoda => {
+            let to_coda = find(Navigation::ToCoda, target)
+                .filter(|&i| i < jump_index)
+                .ok_or_else(|| anyhow!("{} al Coda without a To Coda before the jump", name))?;
+            let coda = find(Navigation::Coda, jump_index)
+                .ok_or_else(|| anyhow!("{} al Coda without a Coda after the jump", name))?;
+            expand(&blocks[target..to_coda], first(target), false, &mut steps);
+            expand(&blocks[coda + 1..], first(coda + 1), true, &mut steps);
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Play blocks whose first measure is written `first`; a repeated measure keeps its number.
+fn expand<'a>(blocks: &'a [MeasureBlock], first: usize, take_repeats: bool, steps: &mut Vec<Step<'a>>) {
+    let mut number = first;
+    for block in blocks {
+        match block {
+            MeasureBlock::Measure(measure) => steps.push(Step::Measure(measure, number)),
+            MeasureBlock::ContextChange(cc) => steps.push(Step::ContextChange(cc)),
+            MeasureBlock::Repeat(repeat) => {
+                let passes = if take_repeats { 1..=repeat.times } else { repeat.times..=repeat.times };
+                for pass in passes {
+                    expand(&repeat.body, number, take_repeats, steps);
+                    let mut ending_first = number + measure_count(&repeat.body);
+                    for ending in &repeat.endings {
+                        if ending.numbers.contains(&pass) {
+                            expand(&ending.body, ending_first, take_repeats, steps);
+                            break;
+                        }
+                        ending_first += measure_count(&ending.body);
                     }
                 }
             }
+            MeasureBlock::Navigation(_) => {}
+        }
+        number += measure_count(std::slice::from_ref(block));
+    }
+}
 
-            let pitch = calculate_pitch(&note.pitch)?;
-            
-            // Update velocity if dynamic is present
-            if let Some(dyn_str) = &note.dynamic {
-                *current_velocity = dynamic_to_velocity(dyn_str);
-            }
-
-            events.push(IrEvent {
-                time: *current_time,
-                kind: IrEventKind::Note {
-                    pitch,
-                    velocity: *current_velocity,
-                    duration: scaled_duration,
-                },
-            });
-            *current_time += scaled_duration;
-        }
-        Event::Chord(pitches, duration_opt, dynamic_opt, _articulation) => {
-            let duration = calculate_duration(duration_opt, PPQ)?;
-            let mut scaled_duration = (duration as f64 * time_scale).round() as u32;
-
-            // Apply swing
-            if let Some((swing_base, ratio)) = current_swing {
-                let swing_dur = calculate_duration(&Some(Duration::Base(swing_base.clone(), 0)), PPQ)?;
-                if duration == swing_dur {
-                    let is_onbeat = (*current_time % (swing_dur * 2)) == 0;
-                    if is_onbeat {
-                        scaled_duration = (duration as f64 * ratio * 2.0).round() as u32;
-                    } else {
-                        // Ensure the pair sums exactly to 2 * duration
-                        let onbeat_dur = (duration as f64 * ratio * 2.0).round() as u32;
-                        scaled_duration = (duration * 2) - onbeat_dur;
-                    }
+/// The number of measures written in blocks, counting a repeated measure once.
+fn measure_count(blocks: &[MeasureBlock]) -> usize {
+    blocks.iter().map(|block| match block {
+        MeasureBlock::Measure(_) => 1,
+        MeasureBlock::Repeat(repeat) => {
+            measure_count(&repeat.body) + repeat.endings.iter().map(|e| measure_count(&e.body)).sum::<usize>()
+        }
+        MeasureBlock::ContextChange(_) | MeasureBlock::Navigation(_) => 0,
+    }).sum()
+}
+
+/// Walk blocks in written order, keeping repeat structure as marks.
+pub(crate) fn written<'a>(blocks: &'a [MeasureBlock], first: usize, steps: &mut Vec<Step<'a>>) {
+    let mut number = first;
+    for block in blocks {
+        match block {
+            MeasureBlock::Measure(measure) => steps.push(Step::Measure(measure, number)),
+            MeasureBlock::ContextChange(cc) => steps.push(Step::ContextChange(cc)),
+            MeasureBlock::Repeat(repeat) => {
+                steps.push(Step::Mark(Mark::RepeatStart));
+                written(&repeat.body, number, steps);
+                if repeat.endings.is_empty() {
+                    steps.push(Step::Mark(Mark::RepeatEnd { times: repeat.times }));
                 }
-            }
-            
-            if let Some(dyn_str) = dynamic_opt {
-                *current_velocity = dynamic_to_velocity(dyn_str);
-            }
-
-            for pitch in pitches {
-                let midi_pitch = calculate_pitch(pitch)?;
-                events.push(IrEvent {
-                    time: *current_time,
-                    kind: IrEventKind::Note {
-                        pitch: midi_pitch,
-                        velocity: *current_velocity, 
-                        duration: scaled_duration,
-                    },
-                });
-            }
-            *current_time += scaled_duration;
-        }
-        Event::Rest(duration_opt) => {
-            let duration = calculate_duration(duration_opt, PPQ)?;
-            let mut scaled_duration = (duration as f64 * time_scale).round() as u32;
-
-            // Apply swing
-            if let Some((swing_base, ratio)) = current_swing {
-                let swing_dur = calculate_duration(&Some(Duration::Base(swing_base.clone(), 0)), PPQ)?;
-                if duration == swing_dur {
-                    let is_onbeat = (*current_time % (swing_dur * 2)) == 0;
-                    if is_onbeat {
-                        scaled_duration = (duration as f64 * ratio * 2.0).round() as u32;
-                    } else {
-                        // Ensure the pair sums exactly to 2 * duration
-                        let onbeat_dur = (duration as f64 * ratio * 2.0).round() as u32;
-                        scaled_duration = (duration * 2) - onbeat_dur;
+                let mut ending_first = number + measure_count(&repeat.body);
+                for (i, ending) in repeat.endings.iter().enumerate() {
+                    let last = i + 1 == repeat.endings.len();
+                    steps.push(Step::Mark(Mark::EndingStart(ending.numbers.clone())));
+                    written(&ending.body, ending_first, steps);
+                    ending_first += measure_count(&ending.body);
+                    steps.push(Step::Mark(Mark::EndingEnd {
+                        numbers: ending.numbers.clone(),
+                        discontinue: last,
+                    }));
+                    if !last {
+                        steps.push(Step::Mark(Mark::RepeatEnd { times: repeat.times }));
                     }
                 }
             }
+            MeasureBlock::Navigation(nav) => steps.push(Step::Mark(Mark::Navigation(*nav))),
+        }
+        number += measure_count(std::slice::from_ref(block));
+    }
+}
 
-            *current_time += scaled_duration;
+/// Mutable state threaded through the events of one part.
+struct PartState<'a> {
+    time: u32,
+    events: Vec<IrEvent>,
+    velocity: u8,
+    swing: Option<(BaseDuration, f64)>,
+    tempo_marks: Vec<TempoMark>, // Tempo changes and fermatas, realized for the whole score
+    ties: TieState,
+    key: Option<Key>, // Key in effect, for diatonic ornaments
+    voice: u8, // Voice receiving notes
+    voice_ties: HashMap<u8, TieState>, // Ties of the other voices
+    articulations: &'a ArticulationTable,
+    hairpin: Option<Hairpin>,
+    pedal: bool, // Sustain pedal is down
+    expression: bool, // Shape hairpins with CC11 as well as velocity
+    percussion: bool, // Drum names are only meaningful on the drum channel
+    keyboard: bool, // Cannot bend notes, so slides become runs
+    bend_range: u8, // Semitones at full pitch bend
+    members: Vec<Member>, // Member channels playing quarter tones, numbered from 1
+    position: Position, // Exact counterpart of `time`, ignoring swing
+    resolution: u64, // Smallest PPQ that places every onset so far exactly
+    ppq: u32,
+}
+
+fn process_event(event: &Event, state: &mut PartState, time_scale: Position) -> Result<()> {
+    if state.ties.slide.is_some() && matches!(event, Event::Chord(..) | Event::Drum(_) | Event::Rest(..)) {
+        return Err(anyhow!("A glissando must lead to a single note"));
+    }
+
+    match event {
+        Event::Note(note) => {
+            let tone = tone(&note.pitch).map_err(|e| {
+                let hint = "MIDI pitches range from C-1 to G9";
+                anyhow::Error::from(Diagnostic::error(e.to_string(), note.span).with_hint(hint))
+            })?;
+            if let Some((slide, from)) = state.ties.slide.take() {
+                glide(state, slide, from, tone, note.span)?;
+            }
+            if note.grace.is_some() || note.ornament.is_some() {
+                sound_ornamented(state, note, tone, time_scale).map_err(|e| Diagnostic::locate(e, note.span))?;
+            } else {
+                sound(state, &[tone], &note.duration, &note.dynamic, &note.articulation, time_scale)
+                    .map_err(|e| Diagnostic::locate(e, note.span))?;
+            }
+        }
+        Event::Chord(pitches, duration_opt, dynamic_opt, articulation_opt) => {
+            let tones = pitches.iter().map(tone).collect::<Result<Vec<_>>>()?;
+            sound(state, &tones, duration_opt, dynamic_opt, articulation_opt, time_scale)?;
+        }
+        Event::Drum(hit) => {
+            if !state.percussion {
+                let message = format!("Drum '{}' used outside a percussion part", hit.drums.join(" "));
+                let hint = "write drum parts with `Instrument: Drums`";
+                return Err(Diagnostic::error(message, hit.span).with_hint(hint).into());
+            }
+            let keys = hit.drums.iter()
+                .map(|name| get_drum_note(name).map(|key| (key, 0)).ok_or_else(|| anyhow!("Unknown drum: {}", name)))
+                .collect::<Result<Vec<_>>>()?;
+            sound(state, &keys, &hit.duration, &hit.dynamic, &hit.articulation, time_scale)
+                .map_err(|e| Diagnostic::locate(e, hit.span))?;
+        }
+        Event::Rest(duration_opt, held) => {
+            let start = state.time;
+            let slot = advance(state, duration_opt, time_scale)?;
+            if *held {
+                let factor = state.articulations.get("U")?.hold;
+                state.tempo_marks.push(TempoMark::Hold { start, end: start + slot, factor });
+            }
+            state.ties.open.clear();
+            state.ties.last_notes.clear();
         }
         Event::Tuplet(tuplet) => {
-            let new_scale = time_scale * (tuplet.q as f64 / tuplet.p as f64);
+            if tuplet.p == 0 {
+                return Err(anyhow!("Tuplet must contain at least one note value"));
+            }
+            let new_scale = time_scale * Position::new(tuplet.q as u64, tuplet.p as u64);
             for sub_event in &tuplet.events {
-                process_event(sub_event, current_time, events, new_scale, current_velocity, current_swing)?;
+                process_event(sub_event, state, new_scale)?;
             }
         }
         Event::Dynamic(dyn_str) => {
-            *current_velocity = dynamic_to_velocity(dyn_str);
+            set_dynamic(state, dyn_str);
+        }
+        Event::Crescendo | Event::Diminuendo => {
+            if let Some(hairpin) = state.hairpin.take() {
+                let target = hairpin.default_target();
+                finish_hairpin(state, hairpin, target);
+            }
+            state.hairpin = Some(Hairpin {
+                start: state.time,
+                from: state.velocity,
+                louder: matches!(event, Event::Crescendo),
+                first_event: state.events.len(),
+            });
+        }
+        Event::Tie => {
+            state.ties.open = std::mem::take(&mut state.ties.last_notes);
+        }
+        Event::MotifCall(call) => {
+            return Err(anyhow!("Motif '@{}' was not expanded", call.name));
+        }
+        Event::Pedal(down) => {
+            // Pressing a pedal that is already down changes it: lift, then press again
+            if state.pedal {
+                control(state, state.time, SUSTAIN, 0);
+            }
+            if *down {
+                control(state, state.time, SUSTAIN, 127);
+            }
+            state.pedal = *down;
+        }
+        Event::Slide(slide) => {
+            let &[from] = state.ties.last_notes.as_slice() else {
+                return Err(anyhow!("A glissando must start from a single note"));
+            };
+            state.ties.slide = Some((*slide, from));
         }
-        Event::Tie => {}
+        Event::Bend(bend) => {
+            let value = |semitones: f64| bend_value(semitones, state.bend_range, bend.span);
+            let start = state.time;
+            match &bend.ramp {
+                None => pitch_bend(state, 0, start, value(bend.semitones)?),
+                Some((target, duration)) => {
+                    let (from, to) = (value(bend.semitones)?, value(*target)?);
+                    let length = duration_length(&Some(duration.clone()))? * time_scale;
+                    bend_ramp(state, 0, start, start + to_ticks(length, state.ppq), from, to);
+                }
+            }
+        }
+        Event::Controller(cc) => match &cc.ramp {
+            None => control(state, state.time, cc.number, cc.value),
+            Some((target, duration)) => {
+                let length = duration_length(&Some(duration.clone()))? * time_scale;
+                let (start, end) = (state.time, state.time + to_ticks(length, state.ppq));
+                let mut last = None;
+                for time in (start..end).step_by((state.ppq / 16).max(1) as usize) {
+                    let progress = (time - start) as f64 / (end - start) as f64;
+                    let value = (cc.value as f64 + (*target as f64 - cc.value as f64) * progress).round() as u8;
+                    if last != Some(value) {
+                        control(state, time, cc.number, value);
+                        last = Some(value);
+                    }
+                }
+                if last != Some(*target) {
+                    control(state, end, cc.number, *target);
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Sound a note, chord or drum hit (as tones), applying its dynamic and articulation.
+fn sound(
+    state: &mut PartState,
+    tones: &[Tone],
+    duration_opt: &Option<Duration>,
+    dynamic_opt: &Option<String>,
+    articulation_opt: &Option<String>,
+    time_scale: Position,
+) -> Result<()> {
+    let stroke = strike(state, duration_opt, dynamic_opt, articulation_opt, time_scale)?;
+    let sounding = stroke.sounding(stroke.slot);
+
+    let mut sounded = Vec::with_capacity(tones.len());
+    for &tone in tones {
+        sounded.push(emit_note(state, true, stroke.start, tone, stroke.velocity, sounding));
     }
+    state.ties.open.clear();
+    state.ties.last_notes = sounded;
+
     Ok(())
 }
 
-fn dynamic_to_velocity(dynamic: &str) -> u8 {
+/// The time a sounded event occupies and how it is played.
+struct Stroke {
+    start: u32,
+    slot: u32, // Ticks until the next event
+    velocity: u8,
+    gate: f64,
+}
+
+impl Stroke {
+    /// Ticks that sound of a note filling `length` ticks at the end of the stroke.
+    fn sounding(&self, length: u32) -> u32 {
+        ((length as f64 * self.gate).round() as u32).max(1)
+    }
+}
+
+/// Move the part past a sounded event, applying its dynamic and articulation.
+fn strike(
+    state: &mut PartState,
+    duration_opt: &Option<Duration>,
+    dynamic_opt: &Option<String>,
+    articulation_opt: &Option<String>,
+    time_scale: Position,
+) -> Result<Stroke> {
+    // Update velocity if dynamic is present
+    if let Some(dyn_str) = dynamic_opt {
+        set_dynamic(state, dyn_str);
+    }
+
+    let start = state.time;
+    let slot = advance(state, duration_opt, time_scale)?;
+
+    let style = match articulation_opt {
+        Some(symbol) => state.articulations.get(symbol)?,
+        None => ArticulationStyle::default(),
+    };
+    let velocity = (state.velocity as i32 + style.velocity).clamp(1, 127) as u8;
+
+    if style.hold > 1.0 {
+        state.tempo_marks.push(TempoMark::Hold { start, end: start + slot, factor: style.hold });
+    }
+
+    Ok(Stroke { start, slot, velocity, gate: style.gate })
+}
+
+/// Sound a note with grace notes or an ornament as the notes that realize it.
+///
+/// Everything happens within the note's written length, so measures add up as
+/// written: appoggiaturas and ornaments divide the note's own time, and an
+/// acciaccatura shortens the note before it.
+fn sound_ornamented(state: &mut PartState, note: &Note, principal: Tone, time_scale: Position) -> Result<()> {
+    let stroke = strike(state, &note.duration, &note.dynamic, &note.articulation, time_scale)?;
+    let quick = (state.ppq / 8).max(1); // A thirty-second note
+    let mut start = stroke.start;
+    let mut slot = stroke.slot;
+    let mut realized = Vec::new(); // (time, length, tone) of each note played
+
+    if let Some(grace) = &note.grace {
+        let tones = grace.pitches.iter().map(tone).collect::<Result<Vec<_>>>()?;
+        let count = tones.len() as u32;
+        let before = if grace.acciaccatura {
+            take_from_previous(state, start, quick * count)
+        } else {
+            None
+        };
+        let (mut time, length) = match before {
+            Some(time) => (time, quick),
+            None => {
+                // An appoggiatura takes half the note, or two thirds of a dotted one;
+                // an acciaccatura that cannot come early is squeezed in on the beat
+                let share = if grace.acciaccatura {
+                    (quick * count).min(slot / 2)
+                } else if matches!(note.duration, Some(Duration::Base(_, dots)) if dots > 0) {
+                    slot * 2 / 3
+                } else {
+                    slot / 2
+                };
+                start += share;
+                slot -= share;
+                (stroke.start, share / count)
+            }
+        };
+        for tone in tones {
+            realized.push((time, length.max(1), tone));
+            time += length;
+        }
+    }
+
+    let graces = realized.len();
+    let (upper, lower) = neighbours(&note.pitch, state.key)?;
+    let (upper, lower) = ((upper, 0), (lower, 0));
+    match note.ornament {
+        None => realized.push((start, slot, principal)),
+        Some(Ornament::Trill) => {
+            let count = (slot / quick).max(2);
+            for i in 0..count {
+                let time = start + slot * i / count;
+                let next = start + slot * (i + 1) / count;
+                realized.push((time, next - time, if i % 2 == 0 { principal } else { upper }));
+            }
+        }
+        Some(Ornament::Mordent) | Some(Ornament::InvertedMordent) => {
+            let neighbour = if note.ornament == Some(Ornament::Mordent) { lower } else { upper };
+            let length = quick.min(slot / 4);
+            realized.push((start, length, principal));
+            realized.push((start + length, length, neighbour));
+            realized.push((start + 2 * length, slot - 2 * length, principal));
+        }
+        Some(Ornament::Turn) => {
+            for (i, tone) in [upper, principal, lower, principal].into_iter().enumerate() {
+                let time = start + slot * i as u32 / 4;
+                let next = start + slot * (i as u32 + 1) / 4;
+                realized.push((time, next - time, tone));
+            }
+        }
+    }
+
+    // Grace notes never continue a tie; the note's first sounding may
+    let mut last = 0;
+    let count = realized.len();
+    for (i, (time, length, tone)) in realized.into_iter().enumerate() {
+        let sounding = if i + 1 == count { stroke.sounding(length) } else { length.max(1) };
+        last = emit_note(state, i >= graces, time, tone, stroke.velocity, sounding);
+    }
+    state.ties.open.clear();
+    state.ties.last_notes = vec![last];
+
+    Ok(())
+}
+
+/// Cut `length` ticks off the end of the notes sounding before `time`, returning
+/// where the freed time starts, or `None` if there is not enough room.
+fn take_from_previous(state: &mut PartState, time: u32, length: u32) -> Option<u32> {
+    let start = time.checked_sub(length)?;
+    let previous = &state.ties.last_notes;
+    if previous.iter().any(|&index| state.events[index].time >= start) {
+        return None;
+    }
+    for &index in previous {
+        let onset = state.events[index].time;
+        if let IrEventKind::Note { duration, .. } = &mut state.events[index].kind {
+            *duration = (*duration).min(start - onset);
+        }
+    }
+    Some(start)
+}
+
+/// MIDI keys of the scale steps above and below a pitch in the key in effect.
+/// Keys without a signature (e.g. octatonic) use the natural steps, as in C major.
+fn neighbours(pitch: &Pitch, key: Option<Key>) -> Result<(u8, u8)> {
+    let fifths = key.and_then(|key| key_fifths(&key).ok()).unwrap_or(0);
+    let alterations = step_alterations(fifths);
+    let index = step_index(pitch.step)? as i32;
+    let principal = calculate_pitch(pitch)? as i32;
+
+    let neighbour = |offset: i32| -> Result<u8> {
+        let target = index + offset;
+        let step = target.rem_euclid(7) as usize;
+        let natural = Pitch { step: STEPS[step], accidental: None, octave: pitch.octave + target.div_euclid(7) };
+        let mut midi = calculate_pitch(&natural)? as i32 + alterations[step];
+        // A chromatic note can meet its neighbour (E# and F); keep them a step apart
+        if midi == principal {
+            midi += offset;
+        }
+        u8::try_from(midi).ok().filter(|&midi| midi <= 127)
+            .ok_or_else(|| anyhow!("Ornament on {}{} goes outside the MIDI range", pitch.step, pitch.octave))
+    };
+    Ok((neighbour(1)?, neighbour(-1)?))
+}
+
+/// Slide from the note at `from` into `(key, cents)`, which starts now.
+///
+/// The first note is held until the second starts. Instruments that can bend
+/// slide with pitch bend and return to the centre for the new note; keyboards
+/// play a run instead, diatonic when both notes are in the key.
+fn glide(state: &mut PartState, slide: Slide, from: usize, (key, cents): Tone, span: Span) -> Result<()> {
+    let start = state.events[from].time;
+    let end = state.time;
+    let length = match slide {
+        Slide::Glissando => (end - start) / 2,
+        Slide::Portamento => (state.ppq / 4).min(end - start),
+    };
+    let slide_start = end - length;
+    let IrEventKind::Note { pitch, velocity, duration, member, .. } = &mut state.events[from].kind else {
+        return Err(anyhow!("A glissando must start from a single note"));
+    };
+    let (pitch, velocity, member) = (*pitch, *velocity, *member);
+    *duration = end - start;
+    if member > 0 {
+        let held = &mut state.members[member as usize - 1];
+        held.until = held.until.max(end);
+    }
+
+    if state.keyboard {
+        let classes = scale_classes(state.key);
+        let diatonic = [pitch, key].iter().all(|p| classes.contains(&(p % 12)));
+        let run: Vec<u8> = if pitch < key { (pitch + 1..key).collect() } else { (key + 1..pitch).rev().collect() };
+        let run: Vec<u8> = run.into_iter().filter(|p| !diatonic || classes.contains(&(p % 12))).collect();
+        if run.is_empty() {
+            return Ok(());
+        }
+        if let IrEventKind::Note { duration, .. } = &mut state.events[from].kind {
+            *duration = (slide_start - start).max(1);
+        }
+        let count = run.len() as u32;
+        for (i, step) in run.into_iter().enumerate() {
+            let time = slide_start + length * i as u32 / count;
+            let next = slide_start + length * (i as u32 + 1) / count;
+            emit_note(state, false, time, (step, 0), velocity, (next - time).max(1));
+        }
+        return Ok(());
+    }
+
+    let semitones = key as f64 + cents as f64 / 100.0 - pitch as f64;
+    if semitones.abs() > state.bend_range as f64 {
+        let message = format!("Glissando of {} semitones is wider than the pitch bend range ({})", semitones.abs(), state.bend_range);
+        let hint = format!("widen the range with `BendRange: {}`", semitones.abs().ceil());
+        return Err(Diagnostic::error(message, span).with_hint(hint).into());
+    }
+    // A quarter-tone note slides on its own member channel, from where it was bent.
+    // The bend arrives a step before the note is released, so its target is heard.
+    let target = bend_value(semitones, state.bend_range, span)?;
+    let arrival = end - (state.ppq / 16).max(1).min(length);
+    match member {
+        0 => {
+            bend_ramp(state, 0, slide_start, arrival, 0, target);
+            // The new note starts unbent
+            pitch_bend(state, 0, end, 0);
+        }
+        n => {
+            let from = state.members[n as usize - 1].bend;
+            bend_ramp(state, n, slide_start, arrival, from, target);
+            state.members[n as usize - 1].bend = target;
+        }
+    }
+    Ok(())
+}
+
+/// Pitch classes of the key in effect (C major without one). Scales without a
+/// key signature, such as octatonic, give all twelve, so their runs are chromatic.
+fn scale_classes(key: Option<Key>) -> Vec<u8> {
+    let fifths = match key.map(|key| key_fifths(&key)) {
+        Some(Ok(fifths)) => fifths,
+        Some(Err(_)) => return (0..12).collect(),
+        None => 0,
+    };
+    let alterations = step_alterations(fifths);
+    const NATURAL: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+    (0..7).map(|i| (NATURAL[i] + alterations[i]).rem_euclid(12) as u8).collect()
+}
+
+/// A bend in semitones as a MIDI pitch bend value for the part's bend range.
+fn bend_value(semitones: f64, range: u8, span: Span) -> Result<i16> {
+    if semitones.abs() > range as f64 {
+        let message = format!("Bend of {} semitones is wider than the pitch bend range ({})", semitones, range);
+        let hint = format!("widen the range with `BendRange: {}`", semitones.abs().ceil());
+        return Err(Diagnostic::error(message, span).with_hint(hint).into());
+    }
+    Ok(bend_units(semitones, range))
+}
+
+fn bend_units(semitones: f64, range: u8) -> i16 {
+    (semitones / range as f64 * 8192.0).round().clamp(-8192.0, 8191.0) as i16
+}
+
+/// Push a pitch bend for the part's channel (member 0) or one of its member channels.
+fn pitch_bend(state: &mut PartState, member: u8, time: u32, value: i16) {
+    state.events.push(IrEvent {
+        time,
+        kind: IrEventKind::PitchBend { value, member },
+    });
+}
+
+/// Move the pitch bend from one value to another, a step every 64th note.
+fn bend_ramp(state: &mut PartState, member: u8, start: u32, end: u32, from: i16, to: i16) {
+    let step = (state.ppq / 16).max(1);
+    let mut last = None;
+    for time in (start..end).step_by(step as usize) {
+        let progress = (time - start) as f64 / (end - start) as f64;
+        let value = (from as f64 + (to - from) as f64 * progress).round() as i16;
+        if last != Some(value) {
+            pitch_bend(state, member, time, value);
+            last = Some(value);
+        }
+    }
+    if last != Some(to) {
+        pitch_bend(state, member, end, to);
+    }
+}
+
+/// Push a control change for the part's channel.
+fn control(state: &mut PartState, time: u32, controller: u8, value: u8) {
+    state.events.push(IrEvent {
+        time,
+        kind: IrEventKind::ControlChange { controller, value },
+    });
+}
+
+/// A part's say in the score's tempo, at ticks from the start of the part until
+/// every part is walked.
+#[derive(Debug, Clone)]
+enum TempoMark {
+    Set { time: u32, bpm: f64 },
+    Ramp { start: u32, end: u32, to: f64, curve: TempoCurve, accelerando: bool, span: Span }, // `Accel:` or `Rit:`
+    Hold { start: u32, end: u32, factor: f64 }, // A fermata slowing the tempo by `factor`
+}
+
+impl TempoMark {
+    fn start(&self) -> u32 {
+        match *self {
+            TempoMark::Set { time, .. } => time,
+            TempoMark::Ramp { start, .. } | TempoMark::Hold { start, .. } => start,
+        }
+    }
+
+    fn shift(&mut self, offset: u32) {
+        match self {
+            TempoMark::Set { time, .. } => *time += offset,
+            TempoMark::Ramp { start, end, .. } | TempoMark::Hold { start, end, .. } => {
+                *start += offset;
+                *end += offset;
+            }
+        }
+    }
+}
+
+/// Realize the tempo marks of every part as the score's tempo events.
+///
+/// Marks take effect in time order, whichever part they come from, so a new tempo
+/// cuts short a ramp in progress. Ramps change the tempo every sixteenth note, each
+/// step set to the tempo halfway through it. A fermata then slows whatever tempo the
+/// score has reached by its factor. The held beats still count towards a ramp in
+/// progress: its steps within the fermata are dropped, and the tempo jumps to where
+/// the ramp has got to when the fermata ends.
+fn conduct(initial: f64, mut marks: Vec<TempoMark>, ppq: u32, diagnostics: &mut Vec<Diagnostic>) -> Vec<IrEvent> {
+    marks.sort_by_key(TempoMark::start);
+
+    let mut ramps: Vec<Ramp> = Vec::new(); // A steady tempo is a ramp that has already ended
+    let tempo_at = |ramps: &[Ramp], time: u32| ramps.iter().rev().find(|r| r.start <= time).map_or(initial, |r| r.tempo_at(time));
+    let mut holds = Vec::new();
+    for mark in marks {
+        match mark {
+            TempoMark::Set { time, bpm } => ramps.push(Ramp { start: time, end: time, from: bpm, to: bpm, curve: TempoCurve::Linear }),
+            TempoMark::Ramp { start, end, to, curve, accelerando, span } => {
+                let from = tempo_at(&ramps, start);
+                if accelerando != (to > from) && to != from {
+                    let (written, instead) = if accelerando { ("Accel", "Rit") } else { ("Rit", "Accel") };
+                    let message = format!("`{}:` goes from {} to {} BPM", written, from, to);
+                    let hint = format!("use `{}:` for this change", instead);
+                    diagnostics.push(Diagnostic::warning(message, span).with_hint(hint));
+                }
+                ramps.push(Ramp { start, end, from, to, curve });
+            }
+            TempoMark::Hold { start, end, factor } => holds.push((start, end, factor)),
+        }
+    }
+
+    let step = (ppq / 4).max(1);
+    let mut events = Vec::new();
+    for (i, ramp) in ramps.iter().enumerate() {
+        let until = ramps.get(i + 1).map_or(u32::MAX, |next| next.start);
+        let steps = (ramp.start..ramp.end).step_by(step as usize).map(|time| (time, ramp.tempo_at(time + step / 2)));
+        for (time, bpm) in steps.chain([(ramp.end, ramp.to)]).filter(|&(time, _)| time < until) {
+            events.push(IrEvent { time, kind: IrEventKind::Tempo(bpm) });
+        }
+    }
+
+    // Parts holding the same fermata hold it once; the longest of those starting
+    // together comes last, so it decides when the tempo resumes
+    holds.sort_by_key(|&(start, end, _)| (start, end));
+    holds.dedup();
+    for (start, end, factor) in holds {
+        events.retain(|e| !(e.time > start && e.time < end));
+        events.push(IrEvent { time: start, kind: IrEventKind::Tempo(tempo_at(&ramps, start) / factor) });
+        events.push(IrEvent { time: end, kind: IrEventKind::Tempo(tempo_at(&ramps, end)) });
+    }
+    events.sort_by_key(|e| e.time);
+    events
+}
+
+/// A gradual change of tempo between two points in time.
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    start: u32,
+    end: u32,
+    from: f64,
+    to: f64,
+    curve: TempoCurve,
+}
+
+impl Ramp {
+    fn tempo_at(&self, time: u32) -> f64 {
+        if time >= self.end {
+            return self.to;
+        }
+        let progress = time.saturating_sub(self.start) as f64 / (self.end - self.start) as f64;
+        match self.curve {
+            TempoCurve::Linear => self.from + (self.to - self.from) * progress,
+            TempoCurve::Exponential => self.from * (self.to / self.from).powf(progress),
+        }
+    }
+}
+
+/// Tempo in quarter notes per minute, whatever beat the mark counts.
+pub(crate) fn quarter_bpm(tempo: &Tempo) -> Result<f64> {
+    let beat = duration_length(&tempo.beat)? * 4;
+    Ok(tempo.bpm * *beat.numer() as f64 / *beat.denom() as f64)
+}
+
+/// Apply a dynamic mark, ending any hairpin that leads to it.
+fn set_dynamic(state: &mut PartState, dynamic: &str) {
+    let velocity = dynamic_to_velocity(dynamic);
+    if let Some(hairpin) = state.hairpin.take() {
+        finish_hairpin(state, hairpin, velocity);
+    }
+    state.velocity = velocity;
+}
+
+/// A crescendo or diminuendo waiting for the dynamic it leads to.
+#[derive(Debug, Clone, Copy)]
+struct Hairpin {
+    start: u32,
+    from: u8, // Velocity when the hairpin began
+    louder: bool,
+    first_event: usize, // Index of the first event inside the hairpin
+}
+
+impl Hairpin {
+    /// The next dynamic level up or down, used when no dynamic ends the hairpin.
+    fn default_target(&self) -> u8 {
+        const LEVELS: [u8; 8] = [16, 32, 48, 64, 80, 96, 112, 127];
+        if self.louder {
+            LEVELS.iter().copied().find(|&v| v > self.from).unwrap_or(127)
+        } else {
+            LEVELS.iter().rev().copied().find(|&v| v < self.from).unwrap_or(16)
+        }
+    }
+}
+
+/// Shape the notes between the start of a hairpin and now towards `target`.
+///
+/// Notes take a velocity interpolated by their onset. Sustaining instruments keep
+/// the louder end's velocity instead and swell with a CC11 (expression) ramp, so
+/// that held notes change level too.
+fn finish_hairpin(state: &mut PartState, hairpin: Hairpin, target: u8) {
+    let end = state.time;
+    if end <= hairpin.start {
+        return;
+    }
+    let from = hairpin.from as f64;
+    let level_at = |time: u32| from + (target as f64 - from) * (time - hairpin.start) as f64 / (end - hairpin.start) as f64;
+    let loudest = hairpin.from.max(target);
+
+    for event in &mut state.events[hairpin.first_event..] {
+        if let IrEventKind::Note { velocity, .. } = &mut event.kind
+            && event.time < end
+        {
+            let level = if state.expression { loudest as f64 } else { level_at(event.time) };
+            let shift = level.round() as i32 - hairpin.from as i32;
+            *velocity = (*velocity as i32 + shift).clamp(1, 127) as u8;
+        }
+    }
+
+    if state.expression {
+        let mut last = None;
+        for time in (hairpin.start..end).step_by((state.ppq / 4).max(1) as usize) {
+            let value = (127.0 * level_at(time) / loudest as f64).round() as u8;
+            if last != Some(value) {
+                state.events.push(IrEvent {
+                    time,
+                    kind: IrEventKind::ControlChange { controller: 11, value },
+                });
+                last = Some(value);
+            }
+        }
+        // Later notes carry the new dynamic in their velocity
+        state.events.push(IrEvent {
+            time: end,
+            kind: IrEventKind::ControlChange { controller: 11, value: 127 },
+        });
+    }
+}
+
+/// Move the part past an event, returning the ticks it occupies after tuplet
+/// scaling and swing.
+///
+/// The end is rounded from the exact position, so rounding never accumulates.
+fn advance(state: &mut PartState, duration_opt: &Option<Duration>, time_scale: Position) -> Result<u32> {
+    let length = duration_length(duration_opt)? * time_scale;
+    let start = state.position;
+    state.position += length;
+    state.resolution = lcm(state.resolution, *(state.position * 4).denom());
+    let mut end = to_ticks(state.position, state.ppq);
+
+    // Swing lengthens the on-beat note; the off-beat note starts late and ends on the beat
+    if let Some((swing_base, ratio)) = state.swing
+        && length == duration_length(&Some(Duration::Base(swing_base, 0)))?
+        && (start / (length * 2)).is_integer()
+    {
+        let straight = end - to_ticks(start, state.ppq);
+        end = state.time + (straight as f64 * ratio * 2.0).round() as u32;
+    }
+
+    let slot = end - state.time;
+    state.time = end;
+    Ok(slot)
+}
+
+fn to_ticks(position: Position, ppq: u32) -> u32 {
+    (position * 4 * ppq as u64).round().to_integer() as u32
+}
+
+/// How an articulation is performed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ArticulationStyle {
+    gate: f64, // Fraction of the written duration that sounds
+    velocity: i32, // Added to the current dynamic's velocity
+    hold: f64, // Tempo divisor while the note sounds (fermata)
+}
+
+impl Default for ArticulationStyle {
+    fn default() -> Self {
+        ArticulationStyle { gate: 1.0, velocity: 0, hold: 1.0 }
+    }
+}
+
+/// Articulation symbols and how each is performed, after score header overrides.
+struct ArticulationTable {
+    styles: Vec<(&'static str, &'static str, ArticulationStyle)>, // (symbol, name, style)
+}
+
+impl ArticulationTable {
+    fn new(headers: &[Header]) -> Result<Self> {
+        let style = |gate, velocity, hold| ArticulationStyle { gate, velocity, hold };
+        let mut styles = vec![
+            (".", "staccato", style(0.5, 0, 1.0)),
+            ("'", "staccatissimo", style(0.25, 0, 1.0)),
+            ("-", "tenuto", style(1.0, 0, 1.0)),
+            (">", "accent", style(1.0, 20, 1.0)),
+            ("^", "marcato", style(0.75, 30, 1.0)),
+            ("U", "fermata", style(1.0, 0, 2.0)),
+        ];
+
+        for header in headers {
+            if let Header::Articulation(setting) = header {
+                let (_, _, style) = styles
+                    .iter_mut()
+                    .find(|(_, name, _)| *name == setting.name)
+                    .ok_or_else(|| anyhow!("Unknown articulation: {}", setting.name))?;
+                if let Some(gate) = setting.gate {
+                    style.gate = gate;
+                }
+                if let Some(velocity) = setting.velocity {
+                    style.velocity = velocity;
+                }
+                if let Some(hold) = setting.hold {
+                    style.hold = hold;
+                }
+            }
+        }
+
+        Ok(ArticulationTable { styles })
+    }
+
+    /// Style for one or more stacked symbols (e.g. ".>" for a staccato accent).
+    fn get(&self, symbols: &str) -> Result<ArticulationStyle> {
+        let mut combined = ArticulationStyle::default();
+        for symbol in symbols.chars() {
+            let (_, _, style) = self
+                .styles
+                .iter()
+                .find(|(s, _, _)| s.starts_with(symbol))
+                .ok_or_else(|| anyhow!("Unknown articulation: {}", symbol))?;
+            combined.gate = combined.gate.min(style.gate);
+            combined.velocity += style.velocity;
+            combined.hold = combined.hold.max(style.hold);
+        }
+        Ok(combined)
+    }
+}
+
+/// Make `voice` the one receiving notes, keeping each voice's ties apart.
+fn switch_voice(state: &mut PartState, voice: u8) {
+    if state.voice == voice {
+        return;
+    }
+    let ties = std::mem::take(&mut state.ties);
+    state.voice_ties.insert(state.voice, ties);
+    state.ties = state.voice_ties.remove(&voice).unwrap_or_default();
+    state.voice = voice;
+}
+
+/// Tracks notes that may be extended by a following tie or slide.
+#[derive(Debug, Default)]
+struct TieState {
+    /// Indices into the event list of the notes sounded by the most recent note or chord.
+    last_notes: Vec<usize>,
+    /// Notes followed by `~`, waiting to be continued by the next note or chord.
+    open: Vec<usize>,
+    /// A note followed by `gliss` or `port`, waiting for the note it slides into.
+    slide: Option<(Slide, usize)>,
+}
+
+/// A MIDI key and how far to bend it, in cents.
+type Tone = (u8, i16);
+
+fn tone(pitch: &Pitch) -> Result<Tone> {
+    Ok((calculate_pitch(pitch)?, pitch_cents(pitch)))
+}
+
+/// A spare channel that plays quarter tones, so that each can be bent on its own.
+#[derive(Debug)]
+struct Member {
+    bend: i16, // Pitch bend last set on the channel
+    until: u32, // End of the last note given to the channel
+}
+
+/// Push a note, or extend a tied note of the same tone that ends exactly at `time`
+/// (when `tied`). A quarter tone goes to a member channel bent to match.
+/// Returns the index of the event that now sounds this tone.
+fn emit_note(state: &mut PartState, tied: bool, time: u32, (pitch, cents): Tone, velocity: u8, duration: u32) -> usize {
+    let bend = (cents != 0).then(|| bend_units(cents as f64 / 100.0, state.bend_range));
+
+    if tied {
+        for &index in &state.ties.open {
+            let start = state.events[index].time;
+            if let IrEventKind::Note { pitch: tied_pitch, duration: tied_duration, member, .. } = &mut state.events[index].kind
+                && *tied_pitch == pitch
+                && start + *tied_duration == time
+                && (*member == 0 && bend.is_none() || *member > 0 && Some(state.members[*member as usize - 1].bend) == bend)
+            {
+                *tied_duration += duration;
+                if *member > 0 {
+                    state.members[*member as usize - 1].until = time + duration;
+                }
+                return index;
+            }
+        }
+    }
+
+    let member = match bend {
+        Some(bend) => take_member(state, time, time + duration, bend),
+        None => 0,
+    };
+    state.events.push(IrEvent {
+        time,
+        kind: IrEventKind::Note {
+            pitch,
+            velocity,
+            duration,
+            voice: state.voice,
+            member,
+        },
+    });
+    state.events.len() - 1
+}
+
+/// Give a quarter tone sounding from `time` to `until` a member channel that is free
+/// by then, preferring one already bent to `bend`, and bend it if it is not.
+fn take_member(state: &mut PartState, time: u32, until: u32, bend: i16) -> u8 {
+    let free = |member: &Member| member.until <= time;
+    let index = match state.members.iter().position(|m| free(m) && m.bend == bend) {
+        Some(index) => index,
+        None => match state.members.iter().position(free) {
+            Some(index) => index,
+            None => {
+                state.members.push(Member { bend: 0, until: 0 });
+                state.members.len() - 1
+            }
+        },
+    };
+
+    let member = index as u8 + 1;
+    if state.members[index].bend != bend {
+        pitch_bend(state, member, time, bend);
+    }
+    state.members[index] = Member { bend, until };
+    member
+}
+
+/// Suggest how to fix a measure whose length does not match the time signature,
+/// counting in `1/beat` notes when no single note value makes up the difference.
+fn duration_hint(expected: Position, actual: Position, beat: u64) -> String {
+    let difference = if actual < expected { expected - actual } else { actual - expected };
+    let values = [
+        ("w", 1, 1),
+        ("h.", 3, 4),
+        ("h", 1, 2),
+        ("q.", 3, 8),
+        ("q", 1, 4),
+        ("e.", 3, 16),
+        ("e", 1, 8),
+        ("s", 1, 16),
+        ("t", 1, 32),
+        ("x", 1, 64),
+    ];
+    let value = values.iter()
+        .find(|(_, num, den)| difference == Position::new(*num, *den))
+        .map(|(name, _, _)| *name);
+
+    let beats = difference * beat;
+    match (actual < expected, value) {
+        (true, Some(value)) => format!("the measure is short by `{}`; add a rest such as `r {}`", value, value),
+        (true, None) => format!("the measure is {} beats short", beats),
+        (false, Some(value)) => format!("the measure is too long by `{}`", value),
+        (false, None) => format!("the measure is {} beats too long", beats),
+    }
+}
+
+pub(crate) fn dynamic_to_velocity(dynamic: &str) -> u8 {
     match dynamic {
         "fff" => 127,
         "ff" => 112,
@@ -340,31 +1634,49 @@ fn dynamic_to_velocity(dynamic: &str) -> u8 {
     }
 }
 
-fn calculate_duration(duration_opt: &Option<Duration>, ppq: u32) -> Result<u32> {
-    match duration_opt {
-        Some(d) => match d {
-            Duration::Base(base, dots) => {
-                let mut ticks = match base {
-                    BaseDuration::Whole => ppq * 4,
-                    BaseDuration::Half => ppq * 2,
-                    BaseDuration::Quarter => ppq,
-                    BaseDuration::Eighth => ppq / 2,
-                    BaseDuration::Sixteenth => ppq / 4,
-                };
-                let mut add = ticks / 2;
-                for _ in 0..*dots {
-                    ticks += add;
-                    add /= 2;
-                }
-                Ok(ticks)
+/// Length of a written duration, before tuplet scaling.
+pub(crate) fn duration_length(duration_opt: &Option<Duration>) -> Result<Position> {
+    let (num, den) = match duration_opt {
+        None => (1, 4), // Default to quarter
+        Some(Duration::Base(base, dots)) => {
+            if *dots > 8 {
+                return Err(anyhow!("Too many dots on a duration ({})", dots));
             }
-            _ => Err(anyhow!("Unsupported duration type")),
-        },
-        None => Ok(ppq), // Default to quarter
+            // Each dot adds half of the previous value: 2 - 1/2^dots
+            let scale = 1u64 << dots;
+            (2 * scale - 1, base_denominator(*base) * scale)
+        }
+        Some(Duration::Subdivision(base, n)) => (1, base_denominator(*base) * *n as u64),
+        Some(Duration::Fraction(num, den)) => (*num as u64, *den as u64),
+    };
+    if num == 0 || den == 0 {
+        return Err(anyhow!("Duration must be longer than zero"));
+    }
+    Ok(Position::new(num, den))
+}
+
+/// Notes of this value in a whole note.
+pub(crate) fn base_denominator(base: BaseDuration) -> u64 {
+    match base {
+        BaseDuration::Whole => 1,
+        BaseDuration::Half => 2,
+        BaseDuration::Quarter => 4,
+        BaseDuration::Eighth => 8,
+        BaseDuration::Sixteenth => 16,
+        BaseDuration::ThirtySecond => 32,
+        BaseDuration::SixtyFourth => 64,
     }
 }
 
-fn calculate_pitch(pitch: &Pitch) -> Result<u8> {
+pub(crate) fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+pub(crate) fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+
+pub(crate) fn calculate_pitch(pitch: &Pitch) -> Result<u8> {
     let base = match pitch.step {
         'C' => 0,
         'D' => 2,
@@ -376,43 +1688,71 @@ fn calculate_pitch(pitch: &Pitch) -> Result<u8> {
         _ => return Err(anyhow!("Invalid pitch step")),
     };
     
+    // Quarter tones sound as the tempered pitch below or above, bent by `pitch_cents`
     let accidental = match &pitch.accidental {
-        Some(Accidental::Sharp) => 1,
-        Some(Accidental::Flat) => -1,
-        None => 0,
+        Some(Accidental::Sharp) | Some(Accidental::ThreeQuarterSharp) => 1,
+        Some(Accidental::Flat) | Some(Accidental::ThreeQuarterFlat) => -1,
+        Some(Accidental::Natural) | Some(Accidental::QuarterSharp) | Some(Accidental::QuarterFlat) | None => 0,
     };
 
     let midi = (pitch.octave + 1) * 12 + base + accidental;
-    if midi < 0 || midi > 127 {
+    if !(0..=127).contains(&midi) {
         return Err(anyhow!("Pitch out of MIDI range"));
     }
     Ok(midi as u8)
 }
 
-fn calculate_measure_duration(measure: &Measure, ppq: u32) -> Result<u32> {
-    let mut total_ticks = 0;
-    for event in &measure.events {
-        total_ticks += calculate_event_duration(event, ppq)?;
+/// How far a pitch lies from the key given by `calculate_pitch`, in cents.
+pub(crate) fn pitch_cents(pitch: &Pitch) -> i16 {
+    match pitch.accidental {
+        Some(Accidental::QuarterSharp) | Some(Accidental::ThreeQuarterSharp) => 50,
+        Some(Accidental::QuarterFlat) | Some(Accidental::ThreeQuarterFlat) => -50,
+        _ => 0,
+    }
+}
+
+fn measure_contains_motif(events: &[Event]) -> bool {
+    events.iter().any(|event| match event {
+        Event::MotifCall(_) => true,
+        Event::Tuplet(tuplet) => measure_contains_motif(&tuplet.events),
+        _ => false,
+    })
+}
+
+/// The voices of a measure with their numbers, voice 1 first.
+pub(crate) fn voices_of(measure: &Measure) -> Vec<(u8, &[Event], Span)> {
+    std::iter::once((1, measure.events.as_slice(), measure.span))
+        .chain(measure.voices.iter().map(|v| (v.number, v.events.as_slice(), v.span)))
+        .collect()
+}
+
+pub(crate) fn events_length(events: &[Event]) -> Result<Position> {
+    let mut total = Position::from_integer(0);
+    for event in events {
+        total += event_length(event)?;
     }
-    Ok(total_ticks)
+    Ok(total)
 }
 
-fn calculate_event_duration(event: &Event, ppq: u32) -> Result<u32> {
+fn event_length(event: &Event) -> Result<Position> {
     match event {
-        Event::Note(note) => calculate_duration(&note.duration, ppq),
-        Event::Chord(_, duration_opt, _, _) => calculate_duration(duration_opt, ppq),
-        Event::Rest(duration_opt) => calculate_duration(duration_opt, ppq),
+        Event::Note(note) => duration_length(&note.duration),
+        Event::Chord(_, duration_opt, _, _) => duration_length(duration_opt),
+        Event::Rest(duration_opt, _) => duration_length(duration_opt),
+        Event::Drum(hit) => duration_length(&hit.duration),
         Event::Tuplet(tuplet) => {
-            let mut content_ticks = 0;
+            if tuplet.p == 0 {
+                return Err(anyhow!("Tuplet must contain at least one note value"));
+            }
+            let mut content = Position::from_integer(0);
             for sub_event in &tuplet.events {
-                content_ticks += calculate_event_duration(sub_event, ppq)?;
+                content += event_length(sub_event)?;
             }
-            // Scale duration: content_ticks * (q / p)
-            // Use u64 to prevent overflow before division
-            Ok((content_ticks as u64 * tuplet.q as u64 / tuplet.p as u64) as u32)
+            // p notes in the time of q
+            Ok(content * Position::new(tuplet.q as u64, tuplet.p as u64))
         }
-        Event::Dynamic(_) => Ok(0),
-        _ => Ok(0),
+        Event::Dynamic(_) => Ok(Position::from_integer(0)),
+        _ => Ok(Position::from_integer(0)),
     }
 }
 
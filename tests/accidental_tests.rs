@@ -0,0 +1,119 @@
+use melos::ast::*;
+use melos::diagnostics::Diagnostic;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+
+fn pitches(input: &str) -> Vec<u8> {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { pitch, .. } => Some(pitch),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn test_parse_accidental_mode_and_natural() {
+    let input = r#"
+    Accidentals: Key
+    Key: Bb "Dorian"
+    Part: Piano Instrument: Piano { | Bn4 w | }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    assert_eq!(score.headers, vec![
+        Header::Accidentals(AccidentalMode::Key),
+        Header::KeySignature(Key { step: 'B', accidental: Some(Accidental::Flat), mode: Mode::Dorian }),
+    ]);
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    let Event::Note(note) = &measure.events[0] else { panic!("Expected note") };
+    assert_eq!(note.pitch.accidental, Some(Accidental::Natural));
+}
+
+#[test]
+fn test_explicit_mode_ignores_the_key() {
+    let input = r#"
+    Key: G "Major"
+    Part: Piano Instrument: Piano { | F4 h Fn4 h | }
+    "#;
+    assert_eq!(pitches(input), vec![65, 65]);
+}
+
+#[test]
+fn test_steps_take_the_key_and_accidentals_last_the_measure() {
+    let input = r#"
+    Accidentals: Key
+    Key: G "Major"
+    Part: Piano Instrument: Piano {
+        | F4 q Fn4 q F4 q F5 q |
+        | F4 q Bb4 q [B4 D5] q Tuplet(3:2) { B4 e Bn4 e B4 e } |
+    }
+    "#;
+    assert_eq!(pitches(input), vec![
+        66, 65, 65, 78,
+        // The natural ends at the barline; the flat carries into the chord and tuplet
+        66, 70, 70, 74, 70, 71, 71,
+    ]);
+}
+
+#[test]
+fn test_ties_hold_the_accidental_across_the_barline() {
+    let input = r#"
+    Accidentals: Key
+    Key: F "Major"
+    Part: Piano Instrument: Piano {
+        | E5 h C#5 h ~ |
+        | C5 h C5 h |
+        | D5 h Bn4 h ~ V2: D4 h Ab4 h ~ |
+        | B4 h B4 h V2: A4 w |
+    }
+    "#;
+    assert_eq!(pitches(input), vec![
+        // The tied C# continues into the second measure, then C is natural again
+        76, 73, 72,
+        // So do a natural against the key and a flat in another voice
+        74, 62, 71, 68, 70,
+    ]);
+}
+
+#[test]
+fn test_key_changes_and_keys_without_a_signature() {
+    let input = r#"
+    Accidentals: Key
+    Key: Eb "Minor"
+    Part: Piano Instrument: Piano {
+        | E4 h C4 h |
+        Key: C "Octatonic"
+        | E4 h Eb4 h |
+    }
+    "#;
+    assert_eq!(pitches(input), vec![63, 59, 64, 63]);
+}
+
+#[test]
+fn test_motifs_read_in_the_key_where_they_are_played() {
+    let input = r#"
+    Accidentals: Key
+    Motif: turn { F4 q E4 q F4 h }
+    Part: Piano Instrument: Piano {
+        Key: D "Major"
+        | @turn |
+        Key: F "Major"
+        | @turn(+1d) |
+    }
+    "#;
+    // F# in D major; moved up a step in F major, F E F becomes G F G
+    assert_eq!(pitches(input), vec![66, 64, 66, 67, 65, 67]);
+}
+
+#[test]
+fn test_unknown_mode_is_an_error() {
+    let input = r#"
+    Key: C "Blues"
+    Part: Piano Instrument: Piano { | C4 w | }
+    "#;
+    let err = parse(input).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.message, "Unknown mode \"Blues\"");
+}
@@ -0,0 +1,128 @@
+use melos::ast::*;
+use melos::diagnostics::Diagnostic;
+use melos::parser::parse;
+use melos::walker::walk;
+use melos::ir::*;
+
+mod common;
+use common::{compile, notes};
+
+#[test]
+fn test_parse_new_articulations() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 q ^ D4 q ' E4 q U F4 q - |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    let articulations: Vec<_> = measure.events.iter().filter_map(|e| match e {
+        Event::Note(note) => note.articulation.clone(),
+        _ => None,
+    }).collect();
+    assert_eq!(articulations, vec!["^", "'", "U", "-"]);
+}
+
+#[test]
+fn test_staccato_keeps_rhythmic_slot() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 q . C4 q - [C4 E4] h . |
+    }
+    "#;
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.velocity, n.duration)), vec![
+        (0, 100, 240),
+        (480, 100, 480),
+        (960, 100, 480),
+        (960, 100, 480),
+    ]);
+}
+
+#[test]
+fn test_dots_on_rests_touch_the_duration() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | r q. C4 e r h |
+    }
+    "#;
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.velocity, n.duration)), vec![(720, 100, 240)]);
+
+    // A spaced dot used to make a dotted rest, so it is an error rather than a new meaning
+    let input = "Part: Piano Instrument: Piano { | r q . C4 e r h | }";
+    let err = parse(input).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.message, "A dot after a rest must touch its duration");
+    assert_eq!(diagnostic.hint.as_deref(), Some("write a dotted rest as `r q.`"));
+    let span = diagnostic.span.unwrap();
+    assert_eq!(&input[span.start..span.end], ".");
+}
+
+#[test]
+fn test_accent_is_relative_to_dynamic() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 q p > C4 q C4 q ^ C4 q ff ^ |
+    }
+    "#;
+    let velocities: Vec<u8> = notes(&compile(input).tracks[1], |n| n.velocity);
+    // p = 48; accent +20; marcato +30, clamped to 127 at ff
+    assert_eq!(velocities, vec![68, 48, 78, 127]);
+}
+
+#[test]
+fn test_articulation_header_overrides() {
+    let input = r#"
+    Articulation: staccato gate 0.25
+    Articulation: accent velocity +10
+    Part: Piano Instrument: Piano {
+        | C4 q . C4 q mf > r h |
+    }
+    "#;
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.velocity, n.duration)), vec![
+        (0, 100, 120),
+        (480, 90, 480),
+    ]);
+}
+
+#[test]
+fn test_fermata_slows_tempo_for_note() {
+    let input = r#"
+    Tempo: 90
+    Articulation: fermata hold 3.0
+    Part: Piano Instrument: Piano {
+        | C4 h D4 h U |
+    }
+    "#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let tempos: Vec<(u32, f64)> = ir.tracks[0].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Tempo(bpm) => Some((e.time, bpm)),
+        _ => None,
+    }).collect();
+    assert_eq!(tempos, vec![(0, 90.0), (960, 30.0), (1920, 90.0)]);
+}
+
+#[test]
+fn test_unknown_articulation_header_errors() {
+    let input = r#"
+    Articulation: fermata gate 1.5
+    Part: Piano Instrument: Piano { | C4 w | }
+    "#;
+    assert!(parse(input).is_err());
+}
+
+#[test]
+fn test_stacked_articulations_combine() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 q mf . > C4 q ^ - r h |
+    }
+    "#;
+    // Shortest gate wins and velocity offsets add up
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.velocity, n.duration)), vec![
+        (0, 100, 240),
+        (480, 110, 360),
+    ]);
+}
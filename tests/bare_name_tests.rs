@@ -13,6 +13,7 @@ fn test_parse_bare_part_name() {
 
     let expected = Score {
         headers: vec![Header::Title("Bare Name Test".to_string())],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Acoustic Guitar".to_string(),
             instrument: "Acoustic Guitar (Steel)".to_string(),
@@ -22,13 +23,20 @@ fn test_parse_bare_part_name() {
                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                     dynamic: None,
                     articulation: None,
+                    grace: None,
+                    ornament: None,
+                    span: Span::default(),
                 })],
+                voices: vec![],
+                kind: MeasureKind::Regular,
+                span: Span::default(),
             })],
+            span: Span::default(),
         }],
     };
 
     let result = parse(input).expect("Failed to parse");
-    assert_eq!(result, expected);
+    assert_eq!(result.without_spans(), expected);
 }
 
 #[test]
@@ -42,6 +50,7 @@ fn test_parse_bare_part_name_with_special_chars() {
 
     let expected = Score {
         headers: vec![Header::Title("Special Chars Test".to_string())],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "First_Violin (Solo)".to_string(),
             instrument: "Violin".to_string(),
@@ -51,11 +60,18 @@ fn test_parse_bare_part_name_with_special_chars() {
                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                     dynamic: None,
                     articulation: None,
+                    grace: None,
+                    ornament: None,
+                    span: Span::default(),
                 })],
+                voices: vec![],
+                kind: MeasureKind::Regular,
+                span: Span::default(),
             })],
+            span: Span::default(),
         }],
     };
 
     let result = parse(input).expect("Failed to parse");
-    assert_eq!(result, expected);
+    assert_eq!(result.without_spans(), expected);
 }
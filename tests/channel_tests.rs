@@ -0,0 +1,94 @@
+use melos::parser::parse;
+use melos::walker::walk;
+use melos::codegen::generate;
+use melos::ir::*;
+use midly::{MetaMessage, TrackEventKind};
+
+const INSTRUMENTS: [&str; 17] = [
+    "Piano", "Celesta", "Violin", "Viola", "Cello", "Contrabass", "Trumpet", "Trombone", "Tuba",
+    "Oboe", "Bassoon", "Clarinet", "Piccolo", "Flute", "Sitar", "Koto", "Kalimba",
+];
+
+fn walk_parts(parts: &[(&str, &str)]) -> IrScore {
+    let input: String = parts.iter()
+        .map(|(name, instrument)| format!("Part: {} Instrument: {} {{ | C4 w | }}\n", name, instrument))
+        .collect();
+    walk(&parse(&input).expect("Failed to parse")).expect("Failed to walk")
+}
+
+fn outputs(ir: &IrScore) -> Vec<(u8, u8)> {
+    ir.tracks[1..].iter().map(|t| (t.port, t.channel)).collect()
+}
+
+#[test]
+fn test_parts_with_same_program_share_channel() {
+    let ir = walk_parts(&[
+        ("Violin1", "Violin"),
+        ("Cello", "Cello"),
+        ("Violin2", "Violin"),
+        ("Kit", "Drums"),
+        ("Toms", "Percussion"),
+    ]);
+    assert_eq!(outputs(&ir), vec![(0, 0), (0, 1), (0, 0), (0, 9), (0, 9)]);
+}
+
+#[test]
+fn test_parts_that_send_controllers_or_bends_get_their_own_channel() {
+    let input = r#"
+Part: Violin1 Instrument: Violin { | p < C4 h E4 h | G4 w f | }
+Part: Violin2 Instrument: Violin { | E4 w | }
+Part: Violin3 Instrument: Violin { | Ped E4 h * G4 h | }
+Part: Violin4 Instrument: Violin { | C4 h gliss D4 h | }
+Part: Violin5 Instrument: Violin { | G4 w | }
+"#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    // Only the two plain parts share a channel
+    assert_eq!(outputs(&ir), vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 1)]);
+}
+
+#[test]
+fn test_quarter_tones_keep_the_main_channel_shared() {
+    let input = r#"
+Part: Violin1 Instrument: Violin { | C4 h C+4 h | }
+Part: Violin2 Instrument: Violin { | E4 w | }
+"#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    // Only the member channel playing the quarter tone is the part's own
+    assert_eq!(outputs(&ir), vec![(0, 0), (0, 0)]);
+    assert_eq!(ir.tracks[1].member_channels, vec![1]);
+}
+
+#[test]
+fn test_seventeen_parts_do_not_collide() {
+    let ir = walk_parts(&INSTRUMENTS.map(|i| (i, i)));
+
+    let outputs = outputs(&ir);
+    let mut unique = outputs.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), 17);
+    assert!(outputs.iter().all(|&(_, channel)| channel != 9));
+    // The 16th distinct program moves to the second port
+    assert_eq!(outputs[14], (0, 15));
+    assert_eq!(outputs[15], (1, 0));
+    assert_eq!(outputs[16], (1, 1));
+}
+
+#[test]
+fn test_midi_port_events_only_when_needed() {
+    let port_events = |ir: &IrScore| -> Vec<u8> {
+        let smf = generate(ir).expect("Failed to generate");
+        smf.tracks.iter().filter_map(|track| track.iter().find_map(|e| match e.kind {
+            TrackEventKind::Meta(MetaMessage::MidiPort(port)) => Some(port.as_int()),
+            _ => None,
+        })).collect()
+    };
+
+    let small = walk_parts(&[("A", "Piano"), ("B", "Flute")]);
+    assert!(port_events(&small).is_empty());
+
+    let large = walk_parts(&INSTRUMENTS.map(|i| (i, i)));
+    let ports = port_events(&large);
+    assert_eq!(ports.len(), 18); // Conductor plus every part
+    assert_eq!(&ports[15..], &[0, 1, 1]);
+}
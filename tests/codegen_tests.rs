@@ -8,13 +8,18 @@ fn test_codegen_simple_score() {
         ppq: 480,
         tracks: vec![IrTrack {
             name: "Piano".to_string(),
+            instrument: None,
+            port: 0,
             channel: 0,
+            member_channels: vec![],
             events: vec![IrEvent {
                 time: 0,
                 kind: IrEventKind::Note {
                     pitch: 60,
                     velocity: 100,
                     duration: 480,
+                    voice: 1,
+                    member: 0,
                 },
             }],
         }],
@@ -70,7 +75,10 @@ fn test_codegen_program_change() {
         ppq: 480,
         tracks: vec![IrTrack {
             name: "Violin".to_string(),
+            instrument: None,
+            port: 0,
             channel: 0,
+            member_channels: vec![],
             events: vec![
                 IrEvent {
                     time: 0,
@@ -82,6 +90,8 @@ fn test_codegen_program_change() {
                         pitch: 60,
                         velocity: 100,
                         duration: 480,
+                        voice: 1,
+                        member: 0,
                     },
                 },
             ],
@@ -0,0 +1,35 @@
+// Helpers shared by the integration tests; each test crate uses only some of them.
+#![allow(dead_code)]
+
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+
+/// The fields of a note event a test can pick from.
+pub struct Note {
+    pub time: u32,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub duration: u32,
+    pub voice: u8,
+    pub member: u8,
+}
+
+pub fn compile(input: &str) -> IrScore {
+    walk(&parse(input).expect("Failed to parse")).expect("Failed to walk")
+}
+
+/// The track of the part with this name.
+pub fn track<'a>(ir: &'a IrScore, name: &str) -> &'a IrTrack {
+    ir.tracks.iter().find(|t| t.name == name).expect("Track not found")
+}
+
+/// The notes of a track, each reduced to the fields a test compares.
+pub fn notes<T>(track: &IrTrack, field: impl Fn(Note) -> T) -> Vec<T> {
+    track.events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { pitch, velocity, duration, voice, member } => {
+            Some(field(Note { time: e.time, pitch, velocity, duration, voice, member }))
+        }
+        _ => None,
+    }).collect()
+}
@@ -0,0 +1,112 @@
+use melos::ast::*;
+use melos::codegen::generate;
+use melos::diagnostics::Diagnostic;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+use midly::{MidiMessage, TrackEventKind};
+
+fn controls(ir: &IrScore) -> Vec<(u32, u8, u8)> {
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::ControlChange { controller, value } => Some((e.time, controller, value)),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn test_parse_pedal_and_controllers() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | Ped C4 h * CC(1, 64) E4 h CC(11, 40 -> 127 over w) |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(measure.events[0], Event::Pedal(true));
+    assert_eq!(measure.events[2], Event::Pedal(false));
+    let Event::Controller(modulation) = &measure.events[3] else { panic!("Expected controller") };
+    assert_eq!((modulation.number, modulation.value, &modulation.ramp), (1, 64, &None));
+    let Event::Controller(swell) = &measure.events[5] else { panic!("Expected controller") };
+    assert_eq!(swell.ramp, Some((127, Duration::Base(BaseDuration::Whole, 0))));
+}
+
+#[test]
+fn test_controller_value_out_of_range() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | CC(7, 200) C4 w | }
+    "#;
+    let err = parse(input).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.message, "Controller value must be between 0 and 127, got 200");
+}
+
+#[test]
+fn test_pedal_changes_and_lifts_at_the_end() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | Ped C4 h Ped E4 h |
+        | G4 w * |
+        | Ped C5 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(controls(&ir), vec![
+        (0, 64, 127),
+        (960, 64, 0),
+        (960, 64, 127),
+        (3840, 64, 0),
+        (3840, 64, 127),
+        // Still down when the part ends
+        (5760, 64, 0),
+    ]);
+}
+
+#[test]
+fn test_controller_ramp() {
+    let input = r#"
+    PPQ: 96
+    Part: Cello Instrument: Cello {
+        | CC(1, 0 -> 8 over q) C3 q CC(1, 0) r h. |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(controls(&ir), vec![
+        (0, 1, 0),
+        (6, 1, 1),
+        (18, 1, 2),
+        (30, 1, 3),
+        (42, 1, 4),
+        (54, 1, 5),
+        (66, 1, 6),
+        (78, 1, 7),
+        (90, 1, 8),
+        (96, 1, 0),
+    ]);
+}
+
+#[test]
+fn test_note_off_comes_before_pedal_on_the_same_tick() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 h Ped E4 h * |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    let smf = generate(&ir).unwrap();
+
+    let mut time = 0;
+    let mut at_half = Vec::new();
+    for event in &smf.tracks[1] {
+        time += event.delta.as_int();
+        if let TrackEventKind::Midi { message, .. } = event.kind
+            && time == 960
+        {
+            at_half.push(message);
+        }
+    }
+    assert!(matches!(at_half[0], MidiMessage::NoteOff { .. }));
+    assert!(matches!(at_half[1], MidiMessage::Controller { .. }));
+    assert!(matches!(at_half[2], MidiMessage::NoteOn { .. }));
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use tempfile::TempDir;
+use melos::diagnostics::{Diagnostic, Location, Severity, SourceMap};
+use melos::loader::load_source;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_diagnostics, WalkOptions};
+
+fn walk_error(input: &str) -> Diagnostic {
+    let err = walk(&parse(input).expect("Failed to parse")).unwrap_err();
+    err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic").clone()
+}
+
+fn location(diagnostic: &Diagnostic, sources: &SourceMap) -> Location {
+    sources.locate(diagnostic.span.expect("Expected a span").start).expect("Span outside source")
+}
+
+#[test]
+fn test_pitch_error_points_at_note() {
+    let input = "Part: Piano Instrument: Piano {\n    | C4 h C11 h |\n}\n";
+    let diagnostic = walk_error(input);
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert!(diagnostic.message.contains("Pitch out of MIDI range"), "{}", diagnostic.message);
+
+    let sources = SourceMap::single("piece.mel", input);
+    assert_eq!(location(&diagnostic, &sources), Location { file: "piece.mel".to_string(), line: 2, column: 12 });
+}
+
+#[test]
+fn test_measure_duration_warning_is_structured() {
+    let input = "Part: Piano Instrument: Piano {\n    | C4 w |\n    | C4 h D4 q |\n}\n";
+    let (_, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert_eq!(warnings.len(), 1);
+
+    let warning = &warnings[0];
+    assert_eq!(warning.severity, Severity::Warning);
+    assert!(warning.message.contains("Measure 2"), "{}", warning.message);
+    assert_eq!(warning.hint.as_deref(), Some("the measure is short by `q`; add a rest such as `r q`"));
+
+    let sources = SourceMap::single("piece.mel", input);
+    assert_eq!(location(warning, &sources).line, 3);
+}
+
+#[test]
+fn test_parse_error_has_location() {
+    let input = "Part: Piano Instrument: Piano {\n    | C4 q ? |\n}\n";
+    let err = parse(input).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    let sources = SourceMap::single("piece.mel", input);
+    assert_eq!(location(diagnostic, &sources).line, 2);
+}
+
+#[test]
+fn test_render_shows_source_line() {
+    let input = "Part: Kit Instrument: Piano {\n    | kick w |\n}\n";
+    let rendered = walk_error(input).render(&SourceMap::single("kit.mel", input));
+    assert_eq!(rendered, concat!(
+        "error: Drum 'kick' used outside a percussion part\n",
+        " --> kit.mel:2:7\n",
+        "  |\n",
+        "2 |     | kick w |\n",
+        "  |       ^^^^^^\n",
+        "  = help: write drum parts with `Instrument: Drums`\n",
+    ));
+}
+
+#[test]
+fn test_multi_file_locations_map_to_original_file() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("score.mel"), "Title: \"Split\"\nTempo: 90\n").unwrap();
+    fs::write(dir.path().join("piano.mel"), "Part: Piano Instrument: Piano {\n    | C4 w |\n    | @missing |\n}").unwrap();
+
+    let loaded = load_source(dir.path()).unwrap();
+    let err = walk(&parse(&loaded.source).unwrap()).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+
+    let location = location(diagnostic, &loaded.sources);
+    assert!(location.file.ends_with("piano.mel"), "{}", location.file);
+    assert_eq!((location.line, location.column), (3, 7));
+}
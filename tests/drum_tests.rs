@@ -0,0 +1,78 @@
+use melos::ast::*;
+use melos::parser::parse;
+use melos::walker::walk;
+
+mod common;
+use common::notes;
+
+#[test]
+fn test_parse_drum_hits() {
+    let input = r#"
+    Part: Kit Instrument: Drums {
+        | kick q f [snare hh] q > rim e r e hho q |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse").without_spans();
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(measure.events.len(), 5);
+    assert_eq!(measure.events[1], Event::Drum(DrumHit {
+        drums: vec!["snare".to_string(), "hh".to_string()],
+        duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
+        dynamic: None,
+        articulation: Some(">".to_string()),
+        span: Span::default(),
+    }));
+    assert_eq!(measure.events[3], Event::Rest(Some(Duration::Base(BaseDuration::Eighth, 0)), false));
+}
+
+#[test]
+fn test_unknown_drum_errors() {
+    let input = r#"
+    Part: Kit Instrument: Drums { | kik w | }
+    "#;
+    let err = parse(input).unwrap_err();
+    assert!(err.to_string().contains("Unknown drum"), "{}", err);
+}
+
+#[test]
+fn test_drums_map_to_general_midi_keys() {
+    let input = r#"
+    Part: Kit Instrument: Drums {
+        | kick q [snare hh] q kick q [snare crash] q |
+    }
+    "#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    assert_eq!(ir.tracks[1].channel, 9);
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch)), vec![
+        (0, 36), (480, 38), (480, 42), (960, 36), (1440, 38), (1440, 49),
+    ]);
+}
+
+#[test]
+fn test_melodic_parts_skip_drum_channel() {
+    let instruments = [
+        "Piano", "Celesta", "Glockenspiel", "Vibraphone", "Marimba", "Xylophone",
+        "Dulcimer", "Harpsichord", "Clavinet", "Guitar", "Banjo",
+    ];
+    let mut input = String::new();
+    for (i, instrument) in instruments.iter().enumerate() {
+        input.push_str(&format!("Part: P{} Instrument: {} {{ | C4 w | }}\n", i, instrument));
+    }
+    input.push_str("Part: Kit Instrument: Drums { | kick w | }\n");
+
+    let ir = walk(&parse(&input).expect("Failed to parse")).expect("Failed to walk");
+    let channels: Vec<u8> = ir.tracks[1..].iter().map(|t| t.channel).collect();
+    assert_eq!(channels, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 9]);
+}
+
+#[test]
+fn test_drum_names_rejected_in_melodic_parts() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | kick w | }
+    "#;
+    let err = walk(&parse(input).expect("Failed to parse")).unwrap_err();
+    assert!(err.to_string().contains("percussion"), "{}", err);
+}
@@ -0,0 +1,133 @@
+use melos::ast::*;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_diagnostics, WalkOptions};
+
+fn durations(ir: &IrScore) -> Vec<(u32, u32)> {
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { duration, .. } => Some((e.time, duration)),
+        _ => None,
+    }).collect()
+}
+
+fn measure_events(input: &str) -> Vec<Event> {
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    measure.events.clone()
+}
+
+fn duration_of(event: &Event) -> Option<Duration> {
+    match event {
+        Event::Note(note) => note.duration.clone(),
+        Event::Rest(duration, _) => duration.clone(),
+        _ => panic!("Expected note or rest"),
+    }
+}
+
+#[test]
+fn test_parse_durations() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 5/16 D4 q/3 E4 /7 F4 t G4 x A4 e.. r 1/8 r h/5 | }
+    "#;
+    let events = measure_events(input);
+    let parsed: Vec<_> = events.iter().map(duration_of).collect();
+    assert_eq!(parsed, vec![
+        Some(Duration::Fraction(5, 16)),
+        Some(Duration::Subdivision(BaseDuration::Quarter, 3)),
+        Some(Duration::Subdivision(BaseDuration::Quarter, 7)),
+        Some(Duration::Base(BaseDuration::ThirtySecond, 0)),
+        Some(Duration::Base(BaseDuration::SixtyFourth, 0)),
+        Some(Duration::Base(BaseDuration::Eighth, 2)),
+        Some(Duration::Fraction(1, 8)),
+        Some(Duration::Subdivision(BaseDuration::Half, 5)),
+    ]);
+}
+
+#[test]
+fn test_duration_letters_end_at_word_boundary() {
+    let input = r#"
+    Part: Kit Instrument: Drums { | kick snare tom1 x hh q | }
+    "#;
+    let events = measure_events(input);
+    let drums: Vec<_> = events.iter().map(|e| match e {
+        Event::Drum(hit) => (hit.drums.clone(), hit.duration.clone()),
+        _ => panic!("Expected drum hit"),
+    }).collect();
+    assert_eq!(drums, vec![
+        (vec!["kick".to_string()], None),
+        (vec!["snare".to_string()], None),
+        (vec!["tom1".to_string()], Some(Duration::Base(BaseDuration::SixtyFourth, 0))),
+        (vec!["hh".to_string()], Some(Duration::Base(BaseDuration::Quarter, 0))),
+    ]);
+}
+
+#[test]
+fn test_fraction_and_subdivision_ticks() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 1/3 D4 1/3 E4 1/12 F4 q/3 G4 q/3 A4 q/3 | }
+    "#;
+    let (ir, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(durations(&ir), vec![(0, 640), (640, 640), (1280, 160), (1440, 160), (1600, 160), (1760, 160)]);
+}
+
+#[test]
+fn test_short_and_double_dotted_values() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 h.. D4 s E4 t F4 x F4 x | }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(durations(&ir), vec![(0, 1680), (1680, 120), (1800, 60), (1860, 30), (1890, 30)]);
+}
+
+#[test]
+fn test_explicit_ppq() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w/7 r 6/7 | }
+    "#;
+    let score = parse(input).unwrap();
+    let options = WalkOptions { ppq: Some(448), ..Default::default() };
+    let (ir, warnings) = walk_with_diagnostics(&score, &options).unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(ir.ppq, 448);
+    assert_eq!(durations(&ir), vec![(0, 256)]);
+}
+
+#[test]
+fn test_too_coarse_ppq_rounds_with_warning() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w/7 r 6/7 | }
+    "#;
+    let options = WalkOptions { ppq: Some(480), ..Default::default() };
+    let (ir, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &options).unwrap();
+    assert_eq!(durations(&ir), vec![(0, 274)]);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].hint.as_deref(), Some("`PPQ: 3360` keeps this part's rhythms exact, as would simpler tuplets"));
+}
+
+#[test]
+fn test_rhythms_finer_than_midi_round_with_warning() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 1/200000 r 199999/200000 | }
+    "#;
+    let (ir, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert_eq!(ir.ppq, 32640);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].message, "Timing in part 'Piano' is rounded to the nearest tick at 32640 PPQ");
+    assert_eq!(
+        warnings[0].hint.as_deref(),
+        Some("no PPQ up to 32767 places this part's rhythms exactly; use simpler tuplets"),
+    );
+}
+
+#[test]
+fn test_fractions_are_checked_against_measure_length() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 3/8 D4 3/8 | }
+    "#;
+    let (_, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].hint.as_deref(), Some("the measure is short by `q`; add a rest such as `r q`"));
+}
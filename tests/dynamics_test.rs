@@ -24,12 +24,10 @@ fn test_dynamics_propagation() {
     let mut note_on_events = Vec::new();
 
     for event in track {
-        if let TrackEventKind::Midi { message, .. } = event.kind {
-            if let MidiMessage::NoteOn { vel, .. } = message {
-                if vel.as_int() > 0 {
-                    note_on_events.push(vel.as_int());
-                }
-            }
+        if let TrackEventKind::Midi { message: MidiMessage::NoteOn { vel, .. }, .. } = event.kind
+            && vel.as_int() > 0
+        {
+            note_on_events.push(vel.as_int());
         }
     }
 
@@ -0,0 +1,185 @@
+use melos::format::{format_files, format_score, format_source, FormatOptions};
+use melos::loader::load_source;
+use melos::parser::parse;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn format(input: &str) -> String {
+    format_source(input, &FormatOptions::default()).expect("Failed to format")
+}
+
+/// Formatting keeps the meaning of the score and settles on one layout.
+fn assert_round_trip(input: &str, options: &FormatOptions) {
+    let formatted = format_source(input, options).expect("Failed to format");
+    assert_eq!(
+        parse(&formatted).expect("Formatted source does not parse").without_spans(),
+        parse(input).unwrap().without_spans(),
+        "{}", formatted,
+    );
+    assert_eq!(format_source(&formatted, options).unwrap(), formatted);
+}
+
+const EVERYTHING: &str = r#"
+// Kitchen sink
+Title: "Sink"   // the title
+Tempo: 60 q.
+Time: 6/8
+Swing: e 0.67
+Articulation: accent velocity +10
+Articulation: staccato gate 0.25
+PPQ: 960
+BendRange: 12
+Accidentals: Key
+Key: Bb "Whole Tone"
+Motif: a { C4 e D4 e }
+Motif: b { Tuplet(3:2) { C4 e D4 e E4 e } }
+Part: Lead Instrument: "Acoustic Guitar (Nylon)" {
+  Pickup | r e |
+  | {D5} C5 q. mf .> tr [C4 E4] q. p - |
+  |: C4 q. ~ C4 q. | D4 q. D4 q. :| x3
+  Segno
+  |: E4 q. E4 q. |
+  Ending(1, 2) { | F4 q. F4 q. | }
+  // second time
+  Ending(3) { | G4 q. G4 q. |
+     Tempo: 80
+     | G4 q. G4 q. | }
+  Accel: 140 over 8 exp
+  Rit: 60 q. over 4
+  | V1: C5 q. D5 q. V2: E4 h. |
+  | V2: E4 h. |
+  Irregular | @a(+2, inv, aug3, -1d, dim) @b r U |
+  | Ped C4 q gliss D4 e * CC(11, 40 -> 127 over h.) Bend(-0.5) Bend(0 -> 2 over e) |
+  | C4 e < D4 e dim E4 e f r q. |
+  Swing: off
+  Time: 3/4 Key: C# "Minor"
+  | r 5/16 r q/3 r /3 C+4 1/16 Ddb4 e |
+  D.S. al Coda
+  To Coda
+  Coda
+  | kick q [snare hh] q f > |
+  Fine
+  D.C.
+}
+Motif: c { {/B4 C#5} D5 q prall }
+Part: Kit Instrument: Drums { |: kick h. :| }
+"#;
+
+#[test]
+fn test_every_construct_round_trips() {
+    assert_round_trip(EVERYTHING, &FormatOptions::default());
+    assert_round_trip(EVERYTHING, &FormatOptions { measures_per_line: 4 });
+    let score = parse(EVERYTHING).unwrap();
+    let printed = format_score(&score, &FormatOptions::default());
+    assert_eq!(parse(&printed).unwrap().without_spans(), score.without_spans());
+}
+
+#[test]
+fn test_every_score_round_trips() {
+    let mut paths = vec![PathBuf::from("scores")];
+    let mut checked = 0;
+    while let Some(path) = paths.pop() {
+        if path.is_dir() {
+            paths.extend(fs::read_dir(&path).unwrap().map(|entry| entry.unwrap().path()));
+            // A directory is one score when its files parse together
+            if let Ok(loaded) = load_source(&path)
+                && let Ok(score) = parse(&loaded.source)
+            {
+                let files = format_files(&loaded.source, &loaded.sources, &FormatOptions::default()).unwrap();
+                assert_eq!(parse(&files.concat()).unwrap().without_spans(), score.without_spans(), "{:?}", path);
+                checked += 1;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "mel") {
+            let source = fs::read_to_string(&path).unwrap();
+            // Files of a multi-file score may not parse alone
+            if parse(&source).is_ok() {
+                assert_round_trip(&source, &FormatOptions::default());
+                assert_round_trip(&source, &FormatOptions { measures_per_line: 3 });
+                checked += 1;
+            }
+        }
+    }
+    assert!(checked > 30);
+}
+
+#[test]
+fn test_layout_and_comments() {
+    let formatted = format(r#"
+    Title: "Song"
+    Part: Piano Instrument: Piano {
+        // Verse
+        | C4 q   D4 q E4 h |   // rising
+
+
+        | G4 w|
+        Tempo: 90
+        | [C4 E4 G4] h r h |
+        // Coda follows
+    }
+    = Bass part
+    Part: "Left hand" Instrument: Bass { | C2 w | }
+    "#);
+    assert_eq!(formatted, r#"Title: "Song"
+
+Part: Piano Instrument: Piano {
+    // Verse
+    | C4 q D4 q E4 h |  // rising
+
+    | G4 w |
+    Tempo: 90
+    | [C4 E4 G4] h r h |
+    // Coda follows
+}
+
+= Bass part
+Part: Left hand Instrument: Bass {
+    | C2 w |
+}
+"#);
+}
+
+#[test]
+fn test_measures_per_line_align_barlines() {
+    let input = r#"
+    Part: Flute Instrument: Flute {
+        | C5 w | D5 h E5 h | F5 w | G5 q G5 q G5 h |
+        | A5 w |
+        |: B5 w | C6 w :|
+    }
+    "#;
+    let formatted = format_source(input, &FormatOptions { measures_per_line: 2 }).unwrap();
+    assert_eq!(formatted, r#"Part: Flute Instrument: Flute {
+    | C5 w | D5 h E5 h      |
+    | F5 w | G5 q G5 q G5 h |
+    | A5 w |
+    |: B5 w | C6 w :|
+}
+"#);
+}
+
+#[test]
+fn test_directory_is_split_back_into_its_files() {
+    let dir = std::env::temp_dir().join(format!("melos_fmt_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("score.mel"), "Title: \"Split\"\nTempo: 100\n").unwrap();
+    fs::write(dir.join("violin.mel"), "// Melody\nPart: Violin Instrument: Violin { | E5 w | }").unwrap();
+    let loaded = load_source(Path::new(&dir)).unwrap();
+    let files = format_files(&loaded.source, &loaded.sources, &FormatOptions::default()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, vec![
+        "Title: \"Split\"\nTempo: 100\n".to_string(),
+        "// Melody\nPart: Violin Instrument: Violin {\n    | E5 w |\n}\n".to_string(),
+    ]);
+}
+
+#[test]
+fn test_part_names_are_quoted_only_when_needed() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w | }
+    Part: "Violin: solo" Instrument: Violin { | E5 w | }
+    "#;
+    let formatted = format(input);
+    assert!(formatted.contains("Part: Piano Instrument: Piano {"), "{}", formatted);
+    assert!(formatted.contains("Part: \"Violin: solo\" Instrument: Violin {"), "{}", formatted);
+    assert_eq!(parse(&formatted).unwrap().without_spans(), parse(input).unwrap().without_spans());
+}
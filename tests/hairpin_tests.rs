@@ -0,0 +1,102 @@
+use melos::ast::*;
+use melos::parser::parse;
+use melos::walker::walk;
+use melos::ir::*;
+
+fn track(input: &str) -> IrTrack {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    ir.tracks[1].clone()
+}
+
+fn velocities(input: &str) -> Vec<u8> {
+    track(input).events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { velocity, .. } => Some(velocity),
+        _ => None,
+    }).collect()
+}
+
+fn expression(input: &str) -> Vec<(u32, u8)> {
+    track(input).events.iter().filter_map(|e| match e.kind {
+        IrEventKind::ControlChange { controller: 11, value } => Some((e.time, value)),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn test_parse_hairpins() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 q p cresc D4 q < E4 q dim F4 q decresc |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    let hairpins: Vec<_> = measure.events.iter()
+        .filter(|e| matches!(e, Event::Crescendo | Event::Diminuendo))
+        .cloned()
+        .collect();
+    assert_eq!(hairpins, vec![Event::Crescendo, Event::Crescendo, Event::Diminuendo, Event::Diminuendo]);
+}
+
+#[test]
+fn test_crescendo_interpolates_to_next_dynamic() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | p cresc C4 q D4 q E4 q F4 q | G4 w f |
+    }
+    "#;
+    // p = 48 to f = 96 over four quarters
+    assert_eq!(velocities(input), vec![48, 60, 72, 84, 96]);
+}
+
+#[test]
+fn test_diminuendo_without_target_falls_one_level() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | mf dim C4 q D4 q E4 q F4 q |
+    }
+    "#;
+    // mf = 80 towards mp = 64, reached at the end of the part
+    assert_eq!(velocities(input), vec![80, 76, 72, 68]);
+}
+
+#[test]
+fn test_hairpin_keeps_articulation_offsets() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | p < C4 h D4 h > | E4 w f |
+    }
+    "#;
+    assert_eq!(velocities(input), vec![48, 92, 96]);
+}
+
+#[test]
+fn test_piano_hairpin_has_no_expression_ramp() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | p cresc C4 w | D4 w f |
+    }
+    "#;
+    assert!(expression(input).is_empty());
+}
+
+#[test]
+fn test_sustaining_instrument_swells_with_expression() {
+    let input = r#"
+    Part: Strings Instrument: Violin {
+        | p cresc C4 w | D4 w f |
+    }
+    "#;
+    // The held note is attacked at the louder end's velocity...
+    assert_eq!(velocities(input), vec![96, 96]);
+
+    // ...and CC11 rises from 48/96 of full scale to full over the bar
+    let ramp = expression(input);
+    assert_eq!(ramp.first(), Some(&(0, 64)));
+    assert_eq!(ramp.last(), Some(&(1920, 127)));
+    assert_eq!(ramp.len(), 17);
+    assert!(ramp.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+}
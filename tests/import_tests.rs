@@ -0,0 +1,126 @@
+use melos::codegen::generate;
+use melos::import::{import, ImportOptions};
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+use midly::Smf;
+
+fn compile(input: &str) -> Vec<u8> {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let mut bytes = Vec::new();
+    generate(&ir).expect("Failed to generate").write_std(&mut bytes).expect("Failed to write");
+    bytes
+}
+
+fn import_source(input: &str) -> String {
+    let bytes = compile(input);
+    import(&Smf::parse(&bytes).unwrap(), &ImportOptions::default()).expect("Failed to import")
+}
+
+/// Every note as (track, start, pitch, length, velocity), with times in 1/960ths of a quarter.
+fn notes(input: &str) -> Vec<(usize, u32, u8, u32, u8)> {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let scale = |ticks: u32| (ticks as u64 * 960 / ir.ppq as u64) as u32;
+    let mut notes: Vec<_> = ir.tracks.iter().enumerate().flat_map(|(track, t)| {
+        t.events.iter().filter_map(move |e| match e.kind {
+            IrEventKind::Note { pitch, velocity, duration, .. } => Some((track, e.time, pitch, duration, velocity)),
+            _ => None,
+        })
+    }).map(|(track, time, pitch, duration, velocity)| (track, scale(time), pitch, scale(duration), velocity)).collect();
+    notes.sort();
+    notes
+}
+
+#[test]
+fn test_round_trip_keeps_every_note() {
+    let input = r#"
+    Title: "Round Trip"
+    Tempo: 96
+    Time: 3/4
+    Key: Eb "Major"
+    Part: Cello Instrument: Cello {
+        | Eb3 q p [G3 Bb3] q Tuplet(3:2) { C4 e D4 e Eb4 e } |
+        | F3 h. ~ | F3 q r q Bb2 q ff |
+        Time: 4/4
+        | V1: C4 w V2: G3 h A3 h |
+    }
+    Part: Kit Instrument: Drums {
+        | kick q [snare hh] q kick q | r h. | kick h r q |
+        Time: 4/4
+        | Tuplet(5:4) { hh s hh s hh s hh s hh s } r h. |
+    }
+    "#;
+    let source = import_source(input);
+    assert_eq!(notes(&source), notes(input));
+}
+
+#[test]
+fn test_headers_and_parts() {
+    let source = import_source(r#"
+    Title: "Etude"
+    Tempo: 72.5
+    Time: 6/8
+    Key: F# "Minor"
+    Part: Left Hand Instrument: Piano { | C3 q. G3 q. | }
+    Part: Low Instrument: Contrabass { | C2 h. | }
+    "#);
+    assert!(source.starts_with("Title: \"Etude\"\nTempo: 72.5\nTime: 6/8\nKey: F# \"Minor\"\n"));
+    assert!(source.contains("Part: Left Hand Instrument: Piano {\n    | C3 q. G3 q. |\n}"));
+    assert!(source.contains("Part: Low Instrument: Contrabass {"));
+}
+
+#[test]
+fn test_chords_ties_and_spelling() {
+    let source = import_source(r#"
+    Key: Bb "Major"
+    Part: Piano Instrument: Piano {
+        | r h [Bb3 D4 F4] h ~ | [Bb3 D4 F4] 5/16 r 3/16 Eb4 h |
+    }
+    "#);
+    assert!(source.contains("| r h [Bb3 D4 F4] h ~ |\n    | [Bb3 D4 F4] q ~ [Bb3 D4 F4] s r e. Eb4 h |"));
+}
+
+#[test]
+fn test_overlapping_notes_become_voices() {
+    let input = r#"
+    Part: Organ Instrument: Organ {
+        | V1: C5 q D5 q E5 h V2: C4 w |
+        | G4 w |
+    }
+    "#;
+    let source = import_source(input);
+    assert!(source.contains("| V1: C4 w V2: C5 q D5 q E5 h |\n    | G4 w |"));
+    assert_eq!(notes(&source), notes(input));
+}
+
+#[test]
+fn test_onsets_snap_to_the_grid() {
+    // 121/480 of a quarter lands nearer the second sixteenth than a triplet
+    let input = r#"
+    PPQ: 480
+    Part: Flute Instrument: Flute { | C5 121/1920 D5 359/1920 E5 h. | }
+    "#;
+    let source = import_source(input);
+    assert!(source.contains("| C5 s D5 e. E5 h. |"));
+    let err = import(&Smf::parse(&compile(input)).unwrap(), &ImportOptions { grid: 3 }).unwrap_err();
+    assert_eq!(err.to_string(), "Grid must be 1, 2, 4, 8 or 16 steps per quarter note, got 3");
+}
+
+#[test]
+fn test_quarter_tones_are_read_back_into_their_part() {
+    let source = import_source(r#"
+    Part: Violin Instrument: Violin { | A4 q A+4 q Bd4 q C#+5 q | [C4 Ed4 G4] w | }
+    "#);
+    assert!(source.contains("Part: Violin Instrument: Violin {\n    | A4 q A+4 q Bd4 q Dd5 q |\n    | [C4 Ed4 G4] w |\n}"));
+    assert!(!source.contains("Violin 2"));
+    assert!(!source.contains("pitch bends"));
+}
+
+#[test]
+fn test_other_pitch_bends_are_counted() {
+    let source = import_source(r#"
+    Part: Violin Instrument: Violin { | C4 h Bend(1) D4 h | }
+    "#);
+    assert!(source.starts_with("// 1 pitch bends were ignored\n"));
+    assert!(source.contains("| C4 h D4 h |"));
+}
@@ -7,6 +7,7 @@ use pretty_assertions::assert_eq;
 fn test_walk_simple_score() {
     let ast = Score {
         headers: vec![],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Piano".to_string(),
             instrument: "Piano".to_string(),
@@ -16,8 +17,15 @@ fn test_walk_simple_score() {
                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                     dynamic: None,
                     articulation: None,
+                    grace: None,
+                    ornament: None,
+                    span: Span::default(),
                 })],
+                voices: vec![],
+                kind: MeasureKind::Regular,
+                span: Span::default(),
             })],
+            span: Span::default(),
         }],
     };
 
@@ -29,7 +37,10 @@ fn test_walk_simple_score() {
         tracks: vec![
             IrTrack {
                 name: "Conductor".to_string(),
+                instrument: None,
+                port: 0,
                 channel: 0,
+                member_channels: vec![],
                 events: vec![
                     IrEvent {
                         time: 0,
@@ -39,7 +50,10 @@ fn test_walk_simple_score() {
             },
             IrTrack {
             name: "Piano".to_string(),
+            instrument: Some("Piano".to_string()),
+            port: 0,
             channel: 0,
+            member_channels: vec![],
             events: vec![
                 IrEvent {
                     time: 0,
@@ -55,6 +69,8 @@ fn test_walk_simple_score() {
                     pitch: 60,
                     velocity: 100, // Default
                     duration: 480,
+                    voice: 1,
+                    member: 0,
                 },
             }],
         }],
@@ -68,6 +84,7 @@ fn test_walk_simple_score() {
 fn test_walk_tuplet_and_context() {
     let ast = Score {
         headers: vec![],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Violin".to_string(),
             instrument: "Violin".to_string(),
@@ -84,24 +101,37 @@ fn test_walk_tuplet_and_context() {
                                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)), // 480
                                     dynamic: None,
                                     articulation: None,
+                                    grace: None,
+                                    ornament: None,
+                                    span: Span::default(),
                                 }),
                                 Event::Note(Note {
                                     pitch: Pitch { step: 'D', accidental: None, octave: 5 }, // 74
                                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)), // 480
                                     dynamic: None,
                                     articulation: None,
+                                    grace: None,
+                                    ornament: None,
+                                    span: Span::default(),
                                 }),
                                 Event::Note(Note {
                                     pitch: Pitch { step: 'E', accidental: None, octave: 5 }, // 76
                                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)), // 480
                                     dynamic: None,
                                     articulation: None,
+                                    grace: None,
+                                    ornament: None,
+                                    span: Span::default(),
                                 }),
                             ],
                         }),
                     ],
+                    voices: vec![],
+                    kind: MeasureKind::Regular,
+                    span: Span::default(),
                 }),
             ],
+            span: Span::default(),
         }],
     };
 
@@ -115,7 +145,10 @@ fn test_walk_tuplet_and_context() {
         tracks: vec![
             IrTrack {
                 name: "Conductor".to_string(),
+                instrument: None,
+                port: 0,
                 channel: 0,
+                member_channels: vec![],
                 events: vec![
                     IrEvent {
                         time: 0,
@@ -125,7 +158,10 @@ fn test_walk_tuplet_and_context() {
             },
             IrTrack {
             name: "Violin".to_string(),
+            instrument: Some("Violin".to_string()),
+            port: 0,
             channel: 0,
+            member_channels: vec![],
             events: vec![
                 IrEvent {
                     time: 0,
@@ -141,6 +177,8 @@ fn test_walk_tuplet_and_context() {
                         pitch: 72,
                         velocity: 100,
                         duration: 320,
+                        voice: 1,
+                        member: 0,
                     },
                 },
                 IrEvent {
@@ -149,6 +187,8 @@ fn test_walk_tuplet_and_context() {
                         pitch: 74,
                         velocity: 100,
                         duration: 320,
+                        voice: 1,
+                        member: 0,
                     },
                 },
                 IrEvent {
@@ -157,6 +197,8 @@ fn test_walk_tuplet_and_context() {
                         pitch: 76,
                         velocity: 100,
                         duration: 320,
+                        voice: 1,
+                        member: 0,
                     },
                 },
             ],
@@ -0,0 +1,75 @@
+use melos::codegen::generate;
+use melos::ir::*;
+use melos::ast::{Accidental, Key, Mode};
+use melos::keys::{key_name, midi_key_signature, parse_key};
+use melos::parser::parse;
+use melos::walker::walk;
+use midly::{MetaMessage, TrackEventKind};
+
+fn key_signatures(input: &str) -> Vec<Vec<(i8, bool)>> {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let smf = generate(&ir).expect("Failed to generate");
+    smf.tracks.iter().map(|track| track.iter().filter_map(|e| match e.kind {
+        TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor)) => Some((sharps, minor)),
+        _ => None,
+    }).collect()).collect()
+}
+
+#[test]
+fn test_key_header_becomes_conductor_event() {
+    let input = r#"
+    Key: Eb "Major"
+    Part: Piano Instrument: Piano { | C4 w | }
+    "#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    assert!(ir.tracks[0].events.contains(&IrEvent {
+        time: 0,
+        kind: IrEventKind::KeySignature(Key { step: 'E', accidental: Some(Accidental::Flat), mode: Mode::Major }),
+    }));
+}
+
+#[test]
+fn test_key_signatures_are_emitted() {
+    let input = r#"
+    Key: Eb "Major"
+    Part: Piano Instrument: Piano {
+        | C4 w |
+        Key: G "minor"
+        | C4 w |
+        Key: D "Dorian"
+        | C4 w |
+    }
+    "#;
+    assert_eq!(key_signatures(input), vec![
+        vec![(-3, false)],
+        vec![(-2, true), (0, false)],
+    ]);
+}
+
+#[test]
+fn test_scale_without_signature_is_skipped() {
+    let input = r#"
+    Key: C "Octatonic"
+    Part: Piano Instrument: Piano { | C4 w | }
+    "#;
+    assert_eq!(key_signatures(input), vec![vec![], vec![]]);
+}
+
+#[test]
+fn test_midi_key_signature_mapping() {
+    let signature = |root, mode| midi_key_signature(&parse_key(root, mode).unwrap());
+    assert_eq!(signature("F#", "Major"), Some((6, false)));
+    assert_eq!(signature("A", "Aeolian"), Some((0, true)));
+    assert_eq!(signature("E", "Phrygian"), Some((0, false)));
+    // G# major has eight sharps; MIDI spells it as Ab major
+    assert_eq!(signature("G#", "Major"), Some((-4, false)));
+    assert_eq!(signature("C", "Whole Tone"), None);
+}
+
+#[test]
+fn test_key_names() {
+    assert_eq!(key_name(-3, false), "Eb major");
+    assert_eq!(key_name(-3, true), "C minor");
+    assert_eq!(key_name(6, false), "F# major");
+    assert_eq!(key_name(0, true), "A minor");
+}
@@ -1,7 +1,7 @@
 use std::fs;
 use std::path::PathBuf;
 use tempfile::TempDir;
-use melos::loader::{load_source, LoadedSource};
+use melos::loader::load_source;
 
 fn create_test_dir() -> TempDir {
     TempDir::new().expect("Failed to create temp dir")
@@ -0,0 +1,174 @@
+use lsp_types::{DiagnosticSeverity, HoverContents, Position, Range, SymbolKind, Url};
+use melos::loader::combine;
+use melos::lsp::Analysis;
+use std::path::Path;
+
+fn uri(name: &str) -> Url {
+    Url::parse(&format!("file:///scores/{}", name)).unwrap()
+}
+
+fn analyze(text: &str) -> Analysis {
+    Analysis::single(&uri("test.mel"), text)
+}
+
+/// The position of the `nth` occurrence of `needle`, plus `shift` columns.
+fn find(text: &str, needle: &str, nth: usize, shift: u32) -> Position {
+    let offset = text.match_indices(needle).nth(nth).expect("Needle not found").0;
+    let before = &text[..offset];
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(before.matches('\n').count() as u32, column as u32 + shift)
+}
+
+fn hover_text(analysis: &Analysis, position: Position) -> String {
+    match analysis.hover(position).expect("No hover").contents {
+        HoverContents::Markup(markup) => markup.value,
+        other => panic!("Unexpected hover {:?}", other),
+    }
+}
+
+fn labels(analysis: &Analysis, position: Position) -> Vec<String> {
+    analysis.completions(position).into_iter().map(|item| item.label).collect()
+}
+
+#[test]
+fn test_diagnostics_locate_problems() {
+    let text = "Part: Flute Instrument: Flute {\n    | C5 w |\n    | D5 h |\n}\n";
+    let diagnostics = analyze(text).diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+    assert_eq!(diagnostics[0].range, Range::new(Position::new(2, 4), Position::new(2, 12)));
+    assert!(diagnostics[0].message.starts_with("Measure 2 in part 'Flute' has incorrect duration."));
+    assert!(diagnostics[0].message.contains("\nhelp: "));
+
+    let text = "Part: Flute Instrument: Flute {\n    | C5 w | B9 w |\n}\n";
+    let diagnostics = analyze(text).diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(diagnostics[0].range.start, find(text, "B9", 0, 0));
+    assert!(diagnostics[0].message.contains("out of MIDI range"));
+
+    let text = "Part: Flute Instrument: Flute {\n    | C5 w |\n";
+    let diagnostics = analyze(text).diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].range.start, Position::new(2, 0));
+    assert!(analyze("Part: Flute Instrument: Flute { | C5 w | }").diagnostics().is_empty());
+}
+
+#[test]
+fn test_completion_follows_context() {
+    let text = "Title: \"Song\"\nT\nPart: Lead Instrument: Acou\n    | C4 q m\n";
+    let analysis = analyze(text);
+    let instruments = labels(&analysis, find(text, "Acou", 0, 4));
+    assert!(instruments.contains(&"Acoustic Grand Piano".to_string()));
+    assert!(instruments.contains(&"Piano".to_string()));
+    assert!(instruments.contains(&"Drums".to_string()));
+    assert_eq!(instruments.len(), 135);
+
+    let keywords = analysis.completions(Position::new(1, 1));
+    let tempo = keywords.iter().find(|item| item.label == "Tempo").unwrap();
+    assert_eq!(tempo.insert_text.as_deref(), Some("Tempo: "));
+    assert!(keywords.iter().any(|item| item.label == "Part"));
+
+    let dynamics = analysis.completions(find(text, " m", 0, 2));
+    assert_eq!(dynamics.iter().map(|item| item.label.as_str()).collect::<Vec<_>>(), ["ppp", "pp", "p", "mp", "mf", "f", "ff", "fff"]);
+    assert_eq!(dynamics[4].detail.as_deref(), Some("Velocity 80"));
+}
+
+#[test]
+fn test_hover_shows_pitch_and_measure_length() {
+    let text = r#"Time: 3/4
+Motif: rise { C4 e D4 e }
+Part: Piano Instrument: Piano {
+    | C#4 q [E4 G+4] h |
+    Time: 2/4
+    | @rise r e |
+    | V1: A4 h V2: {B3} C4 q |
+}
+"#;
+    let analysis = analyze(text);
+    assert_eq!(hover_text(&analysis, find(text, "C#4", 0, 1)), "`C#4`: MIDI 61\n\n**Measure 1** in 3/4: 3 of 3 beats");
+    assert_eq!(hover_text(&analysis, find(text, "G+4", 0, 0)), "`G+4`: MIDI 67 +50 cents\n\n**Measure 1** in 3/4: 3 of 3 beats");
+    assert_eq!(hover_text(&analysis, find(text, "r e", 0, 0)), "**Measure 2** in 2/4: 3/2 of 2 beats");
+    assert_eq!(hover_text(&analysis, find(text, "B3", 0, 0)), "`B3`: MIDI 59\n\n**Measure 3** in 2/4: V1 2 of 2 beats, V2 1 of 2 beats");
+    assert_eq!(hover_text(&analysis, find(text, "D4", 0, 0)), "`D4`: MIDI 62");
+    assert!(analysis.hover(Position::new(0, 2)).is_none());
+
+    // A context change lasts to the end of its block
+    let text = "Part: A Instrument: Flute {\n    Time: 3/4\n    | C5 h. |\n}\nPart: B Instrument: Flute { | D5 w | }\n";
+    assert_eq!(hover_text(&analyze(text), find(text, "D5", 0, 0)), "`D5`: MIDI 74\n\n**Measure 1** in 4/4: 4 of 4 beats");
+}
+
+#[test]
+fn test_hover_spells_key_relative_pitches() {
+    let text = r#"Accidentals: Key
+Key: D "Major"
+Motif: turn { F4 e G4 e }
+Part: Piano Instrument: Piano {
+    | F4 q Fn4 q F4 h |
+    Key: F "Major"
+    Pickup | V2: B4 q |
+}
+"#;
+    let analysis = analyze(text);
+    assert!(hover_text(&analysis, find(text, "F4", 1, 0)).starts_with("`F4` sounds as `F#4`: MIDI 66\n\n"));
+    assert!(hover_text(&analysis, find(text, "F4", 2, 0)).starts_with("`F4` sounds as `Fn4`: MIDI 65\n\n"));
+    assert_eq!(hover_text(&analysis, find(text, "F4", 0, 0)), "`F4` sounds as `F#4`: MIDI 66");
+    assert_eq!(hover_text(&analysis, find(text, "B4", 0, 0)), "`B4` sounds as `Bb4`: MIDI 70\n\n**Pickup measure 2** in 4/4: V1 0 of 4 beats, V2 1 of 4 beats");
+
+    let text = "Accidentals: Key\nPart: Flute Instrument: Flute {\n    | C#5 w ~ |\n    | C5 h C5 h |\n}\n";
+    let analysis = analyze(text);
+    assert!(hover_text(&analysis, find(text, "C5", 0, 0)).starts_with("`C5` sounds as `C#5`: MIDI 73\n\n"));
+    assert!(hover_text(&analysis, find(text, "C5", 1, 0)).starts_with("`C5`: MIDI 72\n\n"));
+}
+
+#[test]
+fn test_definition_and_symbols() {
+    let text = r#"Part: "Lead" Instrument: Flute {
+    Pickup | C5 q |
+    |: D5 w | E5 w :|
+}
+Part: Lead Instrument: Flute { | F5 w | }
+"#;
+    let analysis = analyze(text);
+    let location = analysis.definition(find(text, "Lead", 1, 2)).unwrap();
+    assert_eq!(location.uri, uri("test.mel"));
+    assert_eq!(location.range, Range::new(Position::new(0, 6), Position::new(0, 12)));
+    assert!(analysis.definition(find(text, "Flute", 1, 0)).is_none());
+
+    let symbols = analysis.symbols();
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols[0].name, "Lead");
+    assert_eq!(symbols[0].kind, SymbolKind::CLASS);
+    assert_eq!(symbols[0].detail.as_deref(), Some("Flute"));
+    assert_eq!(symbols[0].range, Range::new(Position::new(0, 0), Position::new(3, 1)));
+    let measures = symbols[0].children.as_ref().unwrap();
+    let names: Vec<_> = measures.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["Measure 1", "Measure 2", "Measure 3"]);
+    assert_eq!(measures[0].detail.as_deref(), Some("Pickup"));
+    assert_eq!(measures[1].range.start, find(text, "D5", 0, 0));
+    // The second block continues the part's numbering
+    assert_eq!(symbols[1].children.as_ref().unwrap()[0].name, "Measure 4");
+    assert_eq!(hover_text(&analysis, find(text, "F5", 0, 0)), "`F5`: MIDI 77\n\n**Measure 4** in 4/4: 4 of 4 beats");
+}
+
+#[test]
+fn test_files_are_checked_within_their_score() {
+    let files = vec![
+        (uri("duo/score.mel").to_string(), "Time: 3/4\n".to_string()),
+        (uri("duo/piano.mel").to_string(), "Part: Piano Instrument: Piano { | C4 h. | }\n".to_string()),
+        (uri("duo/violin.mel").to_string(), "Part: Violin Instrument: Violin {\n    | E5 w |\n}\n".to_string()),
+    ];
+    let loaded = combine(Path::new("duo"), files);
+    let headers = Analysis::new(loaded.source.clone(), loaded.sources.clone(), 0);
+    assert!(headers.diagnostics().is_empty());
+    assert!(headers.symbols().is_empty());
+    let piano = Analysis::new(loaded.source.clone(), loaded.sources.clone(), 1);
+    assert!(piano.diagnostics().is_empty());
+    let violin = Analysis::new(loaded.source.clone(), loaded.sources.clone(), 2);
+    let diagnostics = violin.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].range.start, Position::new(1, 4));
+    assert_eq!(violin.symbols()[0].name, "Violin");
+    let text = "Part: Violin Instrument: Violin {\n    | E5 w |\n}\n";
+    assert_eq!(hover_text(&violin, find(text, "E5", 0, 0)), "`E5`: MIDI 76\n\n**Measure 1** in 3/4: 4 of 3 beats");
+}
@@ -0,0 +1,56 @@
+use melos::codegen::generate;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+use midly::{MetaMessage, TrackEventKind};
+
+fn metas(input: &str) -> Vec<Vec<String>> {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let smf = generate(&ir).expect("Failed to generate");
+    smf.tracks.iter().map(|track| track.iter().filter_map(|e| match e.kind {
+        TrackEventKind::Meta(MetaMessage::TrackName(s)) => Some(format!("TrackName: {}", String::from_utf8_lossy(s))),
+        TrackEventKind::Meta(MetaMessage::InstrumentName(s)) => Some(format!("InstrumentName: {}", String::from_utf8_lossy(s))),
+        TrackEventKind::Meta(MetaMessage::Copyright(s)) => Some(format!("Copyright: {}", String::from_utf8_lossy(s))),
+        TrackEventKind::Meta(MetaMessage::Text(s)) => Some(format!("Text: {}", String::from_utf8_lossy(s))),
+        _ => None,
+    }).collect()).collect()
+}
+
+#[test]
+fn test_score_headers_become_conductor_events() {
+    let input = r#"
+    Title: "Nocturne"
+    Text: "Slowly"
+    Copyright: "(c) 2026 Someone"
+    Part: Piano Instrument: Piano { | C4 w | }
+    "#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let conductor = &ir.tracks[0];
+    assert_eq!(conductor.events[0].kind, IrEventKind::Copyright("(c) 2026 Someone".to_string()));
+    assert!(conductor.events.contains(&IrEvent { time: 0, kind: IrEventKind::TrackName("Nocturne".to_string()) }));
+    assert!(conductor.events.contains(&IrEvent { time: 0, kind: IrEventKind::Text("Slowly".to_string()) }));
+    assert_eq!(ir.tracks[1].instrument.as_deref(), Some("Piano"));
+}
+
+#[test]
+fn test_metadata_is_written() {
+    let input = r#"
+    Title: "Nocturne"
+    Copyright: "(c) 2026 Someone"
+    Part: Right Instrument: Piano { | C4 w | }
+    Part: Melody Instrument: Violin { | E5 w | }
+    "#;
+    assert_eq!(metas(input), vec![
+        vec!["Copyright: (c) 2026 Someone".to_string(), "TrackName: Nocturne".to_string()],
+        vec!["TrackName: Right".to_string(), "InstrumentName: Piano".to_string()],
+        vec!["TrackName: Melody".to_string(), "InstrumentName: Violin".to_string()],
+    ]);
+}
+
+#[test]
+fn test_untitled_score_has_unnamed_conductor() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w | }
+    "#;
+    assert_eq!(metas(input)[0], Vec::<String>::new());
+}
@@ -0,0 +1,132 @@
+use melos::ast::*;
+use melos::codegen::generate;
+use melos::diagnostics::Diagnostic;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+use midly::{MidiMessage, TrackEventKind};
+
+mod common;
+use common::notes;
+
+fn bends(ir: &IrScore) -> Vec<(u32, i16, u8)> {
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::PitchBend { value, member } => Some((e.time, value, member)),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn test_parse_quarter_tone_accidentals() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | C+4 q Ed4 q F#+4 q Bdb3 q |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    let accidentals: Vec<_> = measure.events.iter().filter_map(|event| match event {
+        Event::Note(note) => Some(note.pitch.accidental),
+        _ => None,
+    }).collect();
+    assert_eq!(accidentals, vec![
+        Some(Accidental::QuarterSharp),
+        Some(Accidental::QuarterFlat),
+        Some(Accidental::ThreeQuarterSharp),
+        Some(Accidental::ThreeQuarterFlat),
+    ]);
+}
+
+#[test]
+fn test_quarter_tones_play_bent_on_a_member_channel() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | C4 q C+4 q D+4 q Ed4 q |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    // Quarter tones sound as the tempered key below or above, bent half a semitone
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.member)), vec![(0, 60, 0), (480, 60, 1), (960, 62, 1), (1440, 64, 1)]);
+    // The member channel keeps its bend while the tuning stays the same
+    assert_eq!(bends(&ir), vec![(480, 2048, 1), (1440, -2048, 1)]);
+    assert_eq!(ir.tracks[1].member_channels, vec![1]);
+}
+
+#[test]
+fn test_chord_spreads_detunings_across_channels() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | [C4 Ed4 G+4] w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.member)), vec![(0, 60, 0), (0, 64, 1), (0, 67, 2)]);
+    assert_eq!(bends(&ir), vec![(0, -2048, 1), (0, 2048, 2)]);
+    assert_eq!(ir.tracks[1].member_channels, vec![1, 2]);
+
+    let smf = generate(&ir).unwrap();
+    let mut note_channels = Vec::new();
+    let mut program_channels = Vec::new();
+    for event in &smf.tracks[1] {
+        if let TrackEventKind::Midi { channel, message } = event.kind {
+            match message {
+                MidiMessage::NoteOn { key, .. } => note_channels.push((key.as_int(), channel.as_int())),
+                MidiMessage::ProgramChange { .. } => program_channels.push(channel.as_int()),
+                _ => {}
+            }
+        }
+    }
+    assert_eq!(note_channels, vec![(60, 0), (64, 1), (67, 2)]);
+    // Member channels play the part's instrument too
+    assert_eq!(program_channels, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_tied_quarter_tone_is_one_note() {
+    let input = r#"
+    Part: Cello Instrument: Cello {
+        | C+3 h ~ C+3 h | C3 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.member)), vec![(0, 48, 1), (1920, 48, 0)]);
+}
+
+#[test]
+fn test_motif_transposition_keeps_quarter_tones() {
+    let input = r#"
+    Motif: cell { Ed4 q C+4 q }
+    Part: Flute Instrument: Flute {
+        | @cell(+1) @cell(+1d) |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.member)), vec![(0, 65, 1), (480, 61, 1), (960, 65, 1), (1440, 62, 1)]);
+    assert_eq!(bends(&ir), vec![(0, -2048, 1), (480, 2048, 1), (960, -2048, 1), (1440, 2048, 1)]);
+}
+
+#[test]
+fn test_quarter_tones_spill_onto_the_next_port() {
+    let instruments = [
+        "Piano", "Harpsichord", "Clavinet", "Celesta", "Glockenspiel", "Vibraphone", "Marimba", "Xylophone",
+        "Dulcimer", "Accordion", "Harmonica", "Guitar", "Bass", "Violin", "Viola",
+    ];
+    let mut input = String::new();
+    for (i, instrument) in instruments.iter().enumerate() {
+        input.push_str(&format!("Part: P{} Instrument: {} {{ | C4 w | }}\n", i, instrument));
+    }
+
+    // A new instrument with quarter tones moves to a port with room for its member channel
+    let spilled = format!("{}Part: Q Instrument: Cello {{ | C+4 w | }}\n", input);
+    let ir = walk(&parse(&spilled).unwrap()).unwrap();
+    let track = ir.tracks.last().unwrap();
+    assert_eq!((track.port, track.channel, track.member_channels.clone()), (1, 0, vec![1]));
+
+    // A part sharing its channel must find member channels on that channel's port
+    let shared = format!("{}Part: Q Instrument: Piano {{ | C+4 w | }}\n", input);
+    let err = walk(&parse(&shared).unwrap()).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.message, "Not enough free MIDI channels on port 0 for the quarter tones in part 'Q'");
+}
@@ -0,0 +1,166 @@
+use melos::ast::*;
+use melos::parser::parse;
+use melos::walker::walk;
+
+mod common;
+use common::{compile, notes};
+
+fn pitches(input: &str) -> Vec<u8> {
+    notes(&compile(input).tracks[1], |n| n.pitch)
+}
+
+#[test]
+fn test_parse_motif_definition_and_call() {
+    let input = r#"
+    Motif: head { D4 q E4 q F4 q G4 q }
+    Part: Piano Instrument: Piano {
+        | @head(+7, inv, retro, aug, dim 3, -2d) |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse").without_spans();
+    assert_eq!(score.motifs["head"].len(), 4);
+
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(measure.events, vec![Event::MotifCall(MotifCall {
+        name: "head".to_string(),
+        transforms: vec![
+            MotifTransform::Transpose(7),
+            MotifTransform::Inversion,
+            MotifTransform::Retrograde,
+            MotifTransform::Augmentation(2),
+            MotifTransform::Diminution(3),
+            MotifTransform::TransposeDiatonic(-2),
+        ],
+        span: Span::default(),
+    })]);
+}
+
+#[test]
+fn test_duplicate_motif_errors() {
+    let input = r#"
+    Motif: a { C4 q }
+    Motif: a { D4 q }
+    Part: Piano Instrument: Piano { | @a r h. | }
+    "#;
+    assert!(parse(input).is_err());
+}
+
+#[test]
+fn test_motif_expands_in_place() {
+    let input = r#"
+    Motif: cell { C4 e D4 e }
+    Part: Piano Instrument: Piano {
+        | @cell @cell E4 h |
+    }
+    "#;
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 60, 240),
+        (240, 62, 240),
+        (480, 60, 240),
+        (720, 62, 240),
+        (960, 64, 960),
+    ]);
+}
+
+#[test]
+fn test_motif_may_follow_parts() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | @cell r h |
+    }
+    Motif: cell { C4 q D4 q }
+    "#;
+    assert_eq!(pitches(input), vec![60, 62]);
+}
+
+#[test]
+fn test_motif_semitone_transposition_and_inversion() {
+    let input = r#"
+    Motif: cell { C4 q E4 q G4 q C5 q }
+    Part: Piano Instrument: Piano {
+        | @cell(+2) |
+        | @cell(inv) |
+    }
+    "#;
+    assert_eq!(pitches(input), vec![62, 66, 69, 74, 60, 56, 53, 48]);
+}
+
+#[test]
+fn test_motif_diatonic_transposition_in_key() {
+    // D minor subject answered a fifth higher (4 steps): A Bb C ...
+    let input = r#"
+    Key: D "Minor"
+    Motif: subject { D4 q E4 q F4 q C#5 q }
+    Part: Piano Instrument: Piano {
+        | @subject(+4d) |
+    }
+    "#;
+    // C#5 is a raised leading tone; four steps up keeps the raise: G#5
+    assert_eq!(pitches(input), vec![69, 70, 72, 80]);
+}
+
+#[test]
+fn test_motif_diatonic_transposition_follows_key_changes() {
+    let input = r#"
+    Motif: cell { C4 q D4 q E4 q A4 q }
+    Part: Piano Instrument: Piano {
+        Key: F "Major"
+        | @cell(+1d) |
+    }
+    "#;
+    // C D E A -> D E F Bb, with B taking the key's flat
+    assert_eq!(pitches(input), vec![62, 64, 65, 70]);
+}
+
+#[test]
+fn test_motif_retrograde_and_augmentation() {
+    let input = r#"
+    Motif: cell { C4 q D4 e E4 e }
+    Part: Piano Instrument: Piano {
+        | @cell(retro, aug) |
+    }
+    "#;
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 64, 480),
+        (480, 62, 480),
+        (960, 60, 960),
+    ]);
+}
+
+#[test]
+fn test_motif_non_power_of_two_augmentation() {
+    let input = r#"
+    Time: 3/4
+    Motif: cell { C4 q }
+    Part: Piano Instrument: Piano {
+        | @cell(aug 3) |
+    }
+    "#;
+    assert_eq!(notes(&compile(input).tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 60, 1440)]);
+}
+
+#[test]
+fn test_unknown_motif_errors() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | @missing |
+    }
+    "#;
+    let err = walk(&parse(input).expect("Failed to parse")).unwrap_err();
+    assert!(err.to_string().contains("Unknown motif"), "{}", err);
+}
+
+#[test]
+fn test_recursive_motif_errors() {
+    let input = r#"
+    Motif: a { C4 q @b }
+    Motif: b { @a }
+    Part: Piano Instrument: Piano {
+        | @a |
+    }
+    "#;
+    assert!(walk(&parse(input).expect("Failed to parse")).is_err());
+}
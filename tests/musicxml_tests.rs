@@ -0,0 +1,139 @@
+use melos::musicxml::export;
+use melos::parser::parse;
+use melos::walker::walk;
+
+fn export_source(input: &str) -> String {
+    let score = parse(input).expect("Failed to parse");
+    let ir = walk(&score).expect("Failed to walk");
+    export(&score, &ir).expect("Failed to export")
+}
+
+/// The `<note>` elements of the export, in order.
+fn notes(xml: &str) -> Vec<&str> {
+    xml.split("<note>").skip(1).map(|note| &note[..note.find("</note>").unwrap()]).collect()
+}
+
+#[test]
+fn test_pitches_are_spelled_as_written() {
+    let xml = export_source(r#"
+    Part: Violin Instrument: Violin { | Eb4 q D#4 q Fn4 q C+4 q | }
+    "#);
+    let notes = notes(&xml);
+    assert!(notes[0].contains("<step>E</step>\n          <alter>-1</alter>"));
+    assert!(notes[1].contains("<step>D</step>\n          <alter>1</alter>"));
+    assert!(notes[2].contains("<accidental>natural</accidental>"));
+    assert!(notes[3].contains("<alter>0.5</alter>"));
+    assert!(notes[3].contains("<accidental>quarter-sharp</accidental>"));
+}
+
+#[test]
+fn test_key_relative_pitches_take_the_key() {
+    let xml = export_source(r#"
+    Accidentals: Key
+    Key: Bb "Major"
+    Part: Flute Instrument: Flute { | B4 h E4 h | }
+    "#);
+    assert!(xml.contains("<key>\n          <fifths>-2</fifths>\n          <mode>major</mode>\n        </key>"));
+    let notes = notes(&xml);
+    assert!(notes[0].contains("<step>B</step>\n          <alter>-1</alter>"));
+    assert!(notes[1].contains("<step>E</step>\n          <alter>-1</alter>"));
+}
+
+#[test]
+fn test_ties_hold_key_relative_accidentals_over_the_barline() {
+    let xml = export_source(r#"
+    Accidentals: Key
+    Part: Flute Instrument: Flute { | C#5 w ~ | C5 h C5 h | }
+    "#);
+    let notes = notes(&xml);
+    assert!(notes[1].contains("<step>C</step>\n          <alter>1</alter>"));
+    assert!(!notes[2].contains("<alter>"));
+}
+
+#[test]
+fn test_clef_follows_the_instrument() {
+    let xml = export_source(r#"
+    Part: Violin Instrument: Violin { | G3 w | }
+    Part: Viola Instrument: Viola { | C4 w | }
+    Part: Cello Instrument: Cello { | C3 w | }
+    Part: Left Instrument: Piano { | C2 h G2 h | }
+    Part: Kit Instrument: Drums { | kick w | }
+    "#);
+    let signs: Vec<&str> = xml.match_indices("<sign>").map(|(i, _)| &xml[i + 6..i + 7]).collect();
+    assert_eq!(signs, vec!["G", "C", "F", "F", "p"]);
+    assert!(xml.contains("<sign>C</sign>\n          <line>3</line>"));
+}
+
+#[test]
+fn test_dotted_and_tuplet_values() {
+    let xml = export_source(r#"
+    Part: Flute Instrument: Flute {
+        | C5 q. D5 e Tuplet(3:2) { E5 e F5 e G5 e } A5 q/3 B5 q/3 C6 q/3 |
+    }
+    "#);
+    let notes = notes(&xml);
+    assert!(notes[0].contains("<duration>720</duration>"));
+    assert!(notes[0].contains("<type>quarter</type>\n        <dot/>"));
+    for note in &notes[2..8] {
+        assert!(note.contains("<duration>160</duration>"));
+        assert!(note.contains("<type>eighth</type>"));
+        assert!(note.contains("<actual-notes>3</actual-notes>\n          <normal-notes>2</normal-notes>"));
+    }
+    // Only the written tuplet gets a bracket
+    assert!(notes[2].contains(r#"<tuplet type="start" number="1" bracket="yes"/>"#));
+    assert!(notes[4].contains(r#"<tuplet type="stop" number="1"/>"#));
+    assert_eq!(xml.matches("<tuplet ").count(), 2);
+}
+
+#[test]
+fn test_ties_across_barlines_and_split_durations() {
+    let xml = export_source(r#"
+    Part: Cello Instrument: Cello {
+        | r h [C3 G3] h ~ | [C3 E3] 5/16 r 3/16 r h |
+    }
+    "#);
+    let notes = notes(&xml);
+    // The tie joins only the pitch both chords share
+    assert!(notes[1].contains(r#"<tie type="start"/>"#));
+    assert!(!notes[2].contains("<tie "));
+    assert!(notes[3].contains(r#"<tie type="stop"/>"#) && notes[3].contains(r#"<tie type="start"/>"#));
+    assert!(!notes[4].contains(r#"<tie type="stop"/>"#));
+    // 5/16 is a quarter tied to a sixteenth
+    assert!(notes[3].contains("<type>quarter</type>"));
+    assert!(notes[5].contains("<type>16th</type>") && notes[5].contains(r#"<tied type="stop"/>"#));
+    // Rests are never tied
+    assert!(notes[7].contains("<rest/>") && notes[7].contains("<type>eighth</type>\n        <dot/>"));
+}
+
+#[test]
+fn test_dynamics_hairpins_and_tempo() {
+    let xml = export_source(r#"
+    Tempo: 72
+    Part: Piano Instrument: Piano {
+        | C4 q p < D4 q E4 q F4 q ff |
+        Tempo: 96
+        | G4 w |
+    }
+    "#);
+    assert!(xml.contains("<dynamics>\n            <p/>\n          </dynamics>"));
+    assert!(xml.contains("<dynamics>\n            <ff/>\n          </dynamics>"));
+    assert!(xml.contains(r#"<wedge type="crescendo"/>"#));
+    let stop = xml.find(r#"<wedge type="stop"/>"#).unwrap();
+    assert!(stop < xml.find("<ff/>").unwrap());
+    assert!(xml.contains("<per-minute>72</per-minute>"));
+    assert!(xml.contains(r#"<sound tempo="72"/>"#));
+    assert!(xml.contains(r#"<sound tempo="96"/>"#));
+}
+
+#[test]
+fn test_instruments_carry_their_midi_channel_and_program() {
+    let xml = export_source(r#"
+    Part: Flute Instrument: Flute { | C5 w | }
+    Part: Kit Instrument: Drums { | [kick hh] w | }
+    "#);
+    assert!(xml.contains("<midi-channel>1</midi-channel>\n        <midi-program>74</midi-program>"));
+    assert!(xml.contains(r#"<score-instrument id="P2-I36">"#));
+    assert!(xml.contains("<midi-channel>10</midi-channel>\n        <midi-unpitched>43</midi-unpitched>"));
+    assert!(xml.contains(r#"<instrument id="P2-I42"/>"#));
+    assert!(xml.contains("<notehead>x</notehead>"));
+}
@@ -0,0 +1,146 @@
+use melos::ast::*;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_diagnostics, WalkOptions};
+
+mod common;
+use common::notes;
+
+#[test]
+fn test_parse_grace_notes_and_ornaments() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | {D5} C5 q {/B4 C#5} D5 q E5 q > tr F5 q turn |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    let Event::Note(appoggiatura) = &measure.events[0] else { panic!("Expected note") };
+    let grace = appoggiatura.grace.as_ref().unwrap();
+    assert!(!grace.acciaccatura);
+    assert_eq!(grace.pitches, vec![Pitch { step: 'D', accidental: None, octave: 5 }]);
+
+    let Event::Note(acciaccatura) = &measure.events[1] else { panic!("Expected note") };
+    let grace = acciaccatura.grace.as_ref().unwrap();
+    assert!(grace.acciaccatura);
+    assert_eq!(grace.pitches.len(), 2);
+
+    let Event::Note(trill) = &measure.events[2] else { panic!("Expected note") };
+    assert_eq!(trill.articulation.as_deref(), Some(">"));
+    assert_eq!(trill.ornament, Some(Ornament::Trill));
+
+    let Event::Note(turn) = &measure.events[3] else { panic!("Expected note") };
+    assert_eq!(turn.ornament, Some(Ornament::Turn));
+}
+
+#[test]
+fn test_appoggiatura_takes_half_the_note() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | {D5} C5 h {E5} D5 h. r q |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 74, 480),
+        (480, 72, 480),
+        // A dotted note gives two thirds to the appoggiatura
+        (960, 76, 960),
+        (1920, 74, 480),
+    ]);
+}
+
+#[test]
+fn test_acciaccatura_takes_time_from_the_previous_note() {
+    let input = r#"
+    Part: Flute Instrument: Flute {
+        | E5 q {/D5} C5 q r h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 76, 420),
+        (420, 74, 60),
+        (480, 72, 480),
+    ]);
+}
+
+#[test]
+fn test_acciaccatura_at_the_start_falls_on_the_beat() {
+    let input = r#"
+    Part: Flute Instrument: Flute {
+        | {/D5} C5 q r q r h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 74, 60), (60, 72, 420)]);
+}
+
+#[test]
+fn test_trill_alternates_with_the_upper_neighbour_in_the_key() {
+    let input = r#"
+    Key: D "Major"
+    Part: Flute Instrument: Flute {
+        | E5 e tr r e r h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    // F# is the step above E in D major
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 76, 60), (60, 78, 60), (120, 76, 60), (180, 78, 60)]);
+}
+
+#[test]
+fn test_trill_in_a_scale_without_signature_uses_the_natural_step() {
+    let input = r#"
+    Key: C "Octatonic"
+    Part: Flute Instrument: Flute {
+        | E5 e tr r e r h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 76, 60), (60, 77, 60), (120, 76, 60), (180, 77, 60)]);
+}
+
+#[test]
+fn test_mordents_and_turn() {
+    let input = r#"
+    Key: F "Major"
+    Part: Flute Instrument: Flute {
+        | C5 q mord C5 q prall C5 q turn r q |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 72, 60), (60, 70, 60), (120, 72, 360),
+        (480, 72, 60), (540, 74, 60), (600, 72, 360),
+        // Bb is the step above A, and below C, in F major
+        (960, 74, 120), (1080, 72, 120), (1200, 70, 120), (1320, 72, 120),
+    ]);
+}
+
+#[test]
+fn test_ornaments_leave_measure_lengths_unchanged() {
+    let input = r#"
+    Part: Flute Instrument: Flute {
+        | E5 q {/D5} C5 q tr {F5 E5} D5 h |
+        | C5 w |
+    }
+    "#;
+    let options = WalkOptions { strict: true, ..WalkOptions::default() };
+    let (ir, diagnostics) = walk_with_diagnostics(&parse(input).unwrap(), &options).unwrap();
+    assert!(diagnostics.is_empty());
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)).last(), Some(&(1920, 72, 1920)));
+}
+
+#[test]
+fn test_motif_transposition_moves_grace_notes() {
+    let input = r#"
+    Motif: sigh { {F5} E5 h }
+    Part: Flute Instrument: Flute {
+        | @sigh(+2) r h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 79, 480), (480, 78, 480)]);
+}
@@ -13,6 +13,7 @@ fn test_parse_simple_score() {
 
     let expected = Score {
         headers: vec![Header::Title("Test Score".to_string())],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Piano".to_string(),
             instrument: "Piano".to_string(),
@@ -22,13 +23,20 @@ fn test_parse_simple_score() {
                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                     dynamic: None,
                     articulation: None,
+                    grace: None,
+                    ornament: None,
+                    span: Span::default(),
                 })],
+                voices: vec![],
+                kind: MeasureKind::Regular,
+                span: Span::default(),
             })],
+            span: Span::default(),
         }],
     };
 
     let result = parse(input).expect("Failed to parse");
-    assert_eq!(result, expected);
+    assert_eq!(result.without_spans(), expected);
 }
 
 #[test]
@@ -48,9 +56,10 @@ fn test_parse_advanced_features() {
     let expected = Score {
         headers: vec![
             Header::Title("Advanced".to_string()),
-            Header::Tempo(120),
+            Header::Tempo(Tempo { bpm: 120.0, beat: None }),
             Header::TimeSignature(4, 4),
         ],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Flute".to_string(),
             instrument: "Flute".to_string(),
@@ -62,12 +71,18 @@ fn test_parse_advanced_features() {
                             duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                             dynamic: None,
                             articulation: None,
+                            grace: None,
+                            ornament: None,
+                            span: Span::default(),
                         }),
-                        Event::Rest(Some(Duration::Base(BaseDuration::Quarter, 0))),
+                        Event::Rest(Some(Duration::Base(BaseDuration::Quarter, 0)), false),
                     ],
+                    voices: vec![],
+                    kind: MeasureKind::Regular,
+                    span: Span::default(),
                 }),
                 MeasureBlock::ContextChange(ContextChange::TimeSignature(3, 4)),
-                MeasureBlock::ContextChange(ContextChange::KeySignature("G".to_string(), "Major".to_string())),
+                MeasureBlock::ContextChange(ContextChange::KeySignature(Key { step: 'G', accidental: None, mode: Mode::Major })),
                 MeasureBlock::Measure(Measure {
                     events: vec![
                         Event::Tuplet(Tuplet {
@@ -79,27 +94,40 @@ fn test_parse_advanced_features() {
                                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                                     dynamic: None,
                                     articulation: None,
+                                    grace: None,
+                                    ornament: None,
+                                    span: Span::default(),
                                 }),
                                 Event::Note(Note {
                                     pitch: Pitch { step: 'E', accidental: None, octave: 5 },
                                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                                     dynamic: None,
                                     articulation: None,
+                                    grace: None,
+                                    ornament: None,
+                                    span: Span::default(),
                                 }),
                                 Event::Note(Note {
                                     pitch: Pitch { step: 'F', accidental: Some(Accidental::Sharp), octave: 5 },
                                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                                     dynamic: None,
                                     articulation: None,
+                                    grace: None,
+                                    ornament: None,
+                                    span: Span::default(),
                                 }),
                             ],
                         }),
                     ],
+                    voices: vec![],
+                    kind: MeasureKind::Regular,
+                    span: Span::default(),
                 }),
             ],
+            span: Span::default(),
         }],
     };
 
     let result = parse(input).expect("Failed to parse");
-    assert_eq!(result, expected);
+    assert_eq!(result.without_spans(), expected);
 }
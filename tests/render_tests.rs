@@ -0,0 +1,102 @@
+use melos::parser::parse;
+use melos::render::{render, wav, RenderOptions};
+use melos::walker::walk;
+
+const RATE: u32 = 8000;
+
+fn samples(input: &str) -> Vec<[f32; 2]> {
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    render(&ir, &RenderOptions { sample_rate: RATE }).expect("Failed to render")
+}
+
+fn seconds(samples: &[[f32; 2]]) -> f64 {
+    samples.len() as f64 / RATE as f64
+}
+
+/// Root mean square of one channel between two times in seconds.
+fn rms(samples: &[[f32; 2]], channel: usize, from: f64, to: f64) -> f64 {
+    let range = &samples[(from * RATE as f64) as usize..(to * RATE as f64) as usize];
+    (range.iter().map(|frame| (frame[channel] as f64).powi(2)).sum::<f64>() / range.len() as f64).sqrt()
+}
+
+/// Frequency of a steady tone between two times, from its rising zero crossings.
+fn frequency(samples: &[[f32; 2]], from: f64, to: f64) -> f64 {
+    let range = &samples[(from * RATE as f64) as usize..(to * RATE as f64) as usize];
+    let crossings: Vec<usize> = range.windows(2).enumerate()
+        .filter(|(_, pair)| pair[0][0] < 0.0 && pair[1][0] >= 0.0)
+        .map(|(i, _)| i)
+        .collect();
+    let periods = (crossings.len() - 1) as f64;
+    periods * RATE as f64 / (crossings[crossings.len() - 1] - crossings[0]) as f64
+}
+
+#[test]
+fn test_tempo_map_sets_the_length() {
+    // A pipe ends 0.15 s after its last note is released
+    let slow = samples("Tempo: 60\nPart: Flute Instrument: Flute { | C5 w | }");
+    assert!((seconds(&slow) - 4.15).abs() < 0.01, "{}", seconds(&slow));
+    let changing = samples("Tempo: 60\nPart: Flute Instrument: Flute { | C5 w | Tempo: 240 | C5 w | }");
+    assert!((seconds(&changing) - 5.15).abs() < 0.01, "{}", seconds(&changing));
+    // Four beats speeding up from 60 to 120 BPM take between two and four seconds
+    let ramp = samples("Tempo: 60\nPart: Flute Instrument: Flute { Accel: 120 over 4 | C5 w | C5 w | }");
+    assert!(seconds(&ramp) > 4.5 && seconds(&ramp) < 5.5, "{}", seconds(&ramp));
+}
+
+#[test]
+fn test_notes_sound_at_their_pitch() {
+    let audio = samples("Tempo: 60\nPart: Flute Instrument: Flute { | A4 h E5 h | }");
+    assert!((frequency(&audio, 0.5, 1.8) - 440.0).abs() < 4.0);
+    assert!((frequency(&audio, 2.5, 3.8) - 659.26).abs() < 6.0);
+
+    let bent = samples("Tempo: 60\nBendRange: 12\nPart: Flute Instrument: Flute { | Bend(12) A4 w | }");
+    assert!((frequency(&bent, 0.5, 3.5) - 880.0).abs() < 8.0);
+}
+
+#[test]
+fn test_velocity_and_panning() {
+    let audio = samples(r#"
+    Tempo: 60
+    Part: Left Instrument: Flute { | A4 w pp | A4 w ff | }
+    Part: Right Instrument: Organ { | r w | r w | }
+    "#);
+    assert!(rms(&audio, 0, 4.5, 7.5) > 4.0 * rms(&audio, 0, 0.5, 3.5));
+    // The first part sits left of the second
+    assert!(rms(&audio, 0, 4.5, 7.5) > 2.0 * rms(&audio, 1, 4.5, 7.5));
+
+    let pan = samples("Tempo: 60\nPart: Flute Instrument: Flute { | CC(10, 127) A4 w | }");
+    assert!(rms(&pan, 0, 0.5, 3.5) < 0.001);
+    assert!(rms(&pan, 1, 0.5, 3.5) > 0.01);
+}
+
+#[test]
+fn test_drums_and_pedal() {
+    let drums = samples("Tempo: 60\nPart: Kit Instrument: Drums { | kick q snare q hh q crash q | }");
+    assert!(rms(&drums, 0, 0.0, 0.3) > 0.01);
+    assert!(rms(&drums, 0, 3.0, 3.5) > 0.01);
+    assert!(seconds(&drums) > 4.4);
+
+    // The pedal holds a piano note past its written end
+    let dry = samples("Tempo: 60\nPart: Piano Instrument: Piano { | C4 q r h. | }");
+    let held = samples("Tempo: 60\nPart: Piano Instrument: Piano { | Ped C4 q r h * | }");
+    assert!(seconds(&dry) < 1.5);
+    assert!(rms(&held, 0, 2.0, 3.0) > 0.001);
+}
+
+#[test]
+fn test_rendering_is_repeatable() {
+    let score = "Part: Kit Instrument: Drums { | [kick hh] q snare q hh q snare q | }\nPart: Bass Instrument: Bass { | C2 h G2 h | }";
+    let first = samples(score);
+    assert_eq!(first, samples(score));
+    assert!(first.iter().flatten().all(|sample| sample.abs() <= 1.0));
+
+    let bytes = wav(&first, RATE);
+    assert_eq!(bytes.len(), 44 + first.len() * 4);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), RATE);
+    assert_eq!(&bytes[36..40], b"data");
+
+    let ir = walk(&parse(score).unwrap()).unwrap();
+    let err = render(&ir, &RenderOptions { sample_rate: 0 }).unwrap_err();
+    assert_eq!(err.to_string(), "Sample rate must be above 0");
+}
@@ -0,0 +1,181 @@
+use melos::ast::*;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_options, WalkOptions};
+use melos::wasm::compile_to_musicxml;
+use melos::ir::*;
+
+fn pitches(ir: &IrScore) -> Vec<u8> {
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { pitch, .. } => Some(pitch),
+        _ => None,
+    }).collect()
+}
+
+fn played(input: &str) -> Vec<u8> {
+    pitches(&walk(&parse(input).expect("Failed to parse")).expect("Failed to walk"))
+}
+
+#[test]
+fn test_parse_repeat_with_endings() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 w |: D4 w | E4 w |
+        Ending(1) { | F4 w | }
+        Ending(2) { | G4 w | }
+        | A4 w |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse");
+    let content = &score.parts[0].content;
+    assert_eq!(content.len(), 3);
+    let MeasureBlock::Repeat(repeat) = &content[1] else {
+        panic!("Expected repeat, got {:?}", content[1]);
+    };
+    assert_eq!(repeat.times, 2);
+    assert_eq!(repeat.body.len(), 2);
+    assert_eq!(repeat.endings.len(), 2);
+    assert_eq!(repeat.endings[1].numbers, vec![2]);
+}
+
+#[test]
+fn test_parse_navigation_markers() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 w | Segno | D4 w | To Coda | E4 w | D.S. al Coda Coda | F4 w |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse");
+    let navigation: Vec<_> = score.parts[0].content.iter().filter_map(|b| match b {
+        MeasureBlock::Navigation(nav) => Some(*nav),
+        _ => None,
+    }).collect();
+    assert_eq!(navigation, vec![
+        Navigation::Segno,
+        Navigation::ToCoda,
+        Navigation::DalSegno(JumpEnd::AlCoda),
+        Navigation::Coda,
+    ]);
+}
+
+#[test]
+fn test_simple_repeat_unrolls() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w | D4 w :| E4 w |
+    }
+    "#;
+    assert_eq!(played(input), vec![60, 62, 60, 62, 64]);
+}
+
+#[test]
+fn test_repeat_count() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w :| x3
+        | D4 w |
+    }
+    "#;
+    assert_eq!(played(input), vec![60, 60, 60, 62]);
+}
+
+#[test]
+fn test_repeat_endings_unroll() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w |
+        Ending(1, 2) { | D4 w | }
+        Ending(3) { | E4 w | }
+        | F4 w |
+    }
+    "#;
+    assert_eq!(played(input), vec![60, 62, 60, 62, 60, 64, 65]);
+}
+
+#[test]
+fn test_da_capo_al_fine_skips_repeats() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w :| D4 w | Fine | E4 w | D.C. al Fine
+    }
+    "#;
+    assert_eq!(played(input), vec![60, 60, 62, 64, 60, 62]);
+}
+
+#[test]
+fn test_dal_segno_al_coda() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 w | Segno | D4 w | To Coda | E4 w | D.S. al Coda Coda | F4 w |
+    }
+    "#;
+    assert_eq!(played(input), vec![60, 62, 64, 62, 65]);
+}
+
+#[test]
+fn test_unrolled_timeline_is_linear() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w :|
+    }
+    "#;
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let times: Vec<u32> = ir.tracks[1].events.iter()
+        .filter(|e| matches!(e.kind, IrEventKind::Note { .. }))
+        .map(|e| e.time)
+        .collect();
+    assert_eq!(times, vec![0, 1920]);
+}
+
+#[test]
+fn test_dal_segno_without_segno_errors() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 w | D.S.
+    }
+    "#;
+    let err = walk(&parse(input).expect("Failed to parse")).unwrap_err();
+    assert!(err.to_string().contains("Segno"), "{}", err);
+}
+
+#[test]
+fn test_written_walk_keeps_marks() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w |
+        Ending(1) { | D4 w | }
+        Ending(2) { | E4 w | }
+    }
+    "#;
+    let options = WalkOptions { unroll_repeats: false, ..Default::default() };
+    let ir = walk_with_options(&parse(input).expect("Failed to parse"), &options).expect("Failed to walk");
+    assert_eq!(pitches(&ir), vec![60, 62, 64]);
+
+    let marks: Vec<(u32, Mark)> = ir.tracks[1].events.iter().filter_map(|e| match &e.kind {
+        IrEventKind::Mark(mark) => Some((e.time, mark.clone())),
+        _ => None,
+    }).collect();
+    assert_eq!(marks, vec![
+        (0, Mark::RepeatStart),
+        (1920, Mark::EndingStart(vec![1])),
+        (3840, Mark::EndingEnd { numbers: vec![1], discontinue: false }),
+        (3840, Mark::RepeatEnd { times: 2 }),
+        (3840, Mark::EndingStart(vec![2])),
+        (5760, Mark::EndingEnd { numbers: vec![2], discontinue: true }),
+    ]);
+}
+
+#[test]
+fn test_musicxml_repeat_barlines() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        |: C4 w | D4 w :| x3
+    }
+    "#;
+    let xml = compile_to_musicxml(input).expect("Failed to export");
+    assert!(xml.contains(r#"<repeat direction="forward"/>"#));
+    assert!(xml.contains(r#"<repeat direction="backward" times="3"/>"#));
+    // Written form: two measures, not unrolled
+    assert_eq!(xml.matches("<measure ").count(), 2);
+}
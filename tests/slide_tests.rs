@@ -0,0 +1,174 @@
+use melos::ast::*;
+use melos::codegen::generate;
+use melos::diagnostics::Diagnostic;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::walk;
+use midly::{MidiMessage, TrackEventKind};
+
+mod common;
+use common::notes;
+
+fn bends(ir: &IrScore) -> Vec<(u32, i16)> {
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::PitchBend { value, .. } => Some((e.time, value)),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn test_parse_slides_and_bends() {
+    let input = r#"
+    BendRange: 12
+    Part: Trombone Instrument: Trombone {
+        | C3 h gliss G3 q port A3 q | Bend(-0.5) C3 h Bend(0 -> 2 over h) C3 h |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    assert_eq!(score.headers, vec![Header::BendRange(12)]);
+    let MeasureBlock::Measure(first) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(first.events[1], Event::Slide(Slide::Glissando));
+    assert_eq!(first.events[3], Event::Slide(Slide::Portamento));
+
+    let MeasureBlock::Measure(second) = &score.parts[0].content[1] else {
+        panic!("Expected measure");
+    };
+    let Event::Bend(bend) = &second.events[0] else { panic!("Expected bend") };
+    assert_eq!((bend.semitones, &bend.ramp), (-0.5, &None));
+    let Event::Bend(curve) = &second.events[2] else { panic!("Expected bend") };
+    assert_eq!(curve.ramp, Some((2.0, Duration::Base(BaseDuration::Half, 0))));
+}
+
+#[test]
+fn test_glissando_bends_through_the_second_half() {
+    let input = r#"
+    BendRange: 12
+    Part: Trombone Instrument: Trombone {
+        | C3 h gliss G3 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 48, 960), (960, 55, 960)]);
+
+    let bends = bends(&ir);
+    assert_eq!(bends[0], (480, 0));
+    assert!(bends.windows(2).take(bends.len() - 2).all(|pair| pair[1].1 > pair[0].1));
+    // Seven semitones of twelve, reached before the note is released, then back
+    // to the centre for the new note
+    assert_eq!(&bends[bends.len() - 2..], &[(930, 4779), (960, 0)]);
+
+    // The bend range is set with RPN 0 before anything plays
+    let setup: Vec<(u8, u8)> = ir.tracks[1].events.iter().take_while(|e| e.time == 0).filter_map(|e| match e.kind {
+        IrEventKind::ControlChange { controller, value } => Some((controller, value)),
+        _ => None,
+    }).collect();
+    assert_eq!(setup, vec![(101, 0), (100, 0), (6, 12), (38, 0), (101, 127), (100, 127)]);
+}
+
+#[test]
+fn test_portamento_slides_just_before_the_next_note() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | A4 h port B4 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    let bends = bends(&ir);
+    assert_eq!(bends.first(), Some(&(840, 0)));
+    assert_eq!(&bends[bends.len() - 2..], &[(930, 8191), (960, 0)]);
+
+    let smf = generate(&ir).unwrap();
+    let written = smf.tracks[1].iter().filter(|e| matches!(
+        e.kind,
+        TrackEventKind::Midi { message: MidiMessage::PitchBend { .. }, .. }
+    )).count();
+    assert_eq!(written, bends.len());
+}
+
+#[test]
+fn test_glissando_wider_than_the_bend_range() {
+    let input = r#"
+    Part: Violin Instrument: Violin { | C4 h gliss G4 h | }
+    "#;
+    let err = walk(&parse(input).unwrap()).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.message, "Glissando of 7 semitones is wider than the pitch bend range (2)");
+    assert_eq!(diagnostic.hint.as_deref(), Some("widen the range with `BendRange: 7`"));
+}
+
+#[test]
+fn test_keyboard_glissando_is_a_diatonic_run() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 h gliss C5 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 60, 480),
+        (480, 62, 80),
+        (560, 64, 80),
+        (640, 65, 80),
+        (720, 67, 80),
+        (800, 69, 80),
+        (880, 71, 80),
+        (960, 72, 960),
+    ]);
+    assert!(bends(&ir).is_empty());
+}
+
+#[test]
+fn test_keyboard_glissando_to_a_chromatic_note_is_chromatic() {
+    let input = r#"
+    Part: Harp Instrument: Harp {
+        | F#4 h gliss C4 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 66, 480),
+        (480, 65, 96),
+        (576, 64, 96),
+        (672, 63, 96),
+        (768, 62, 96),
+        (864, 61, 96),
+        (960, 60, 960),
+    ]);
+}
+
+#[test]
+fn test_keyboard_glissando_in_a_scale_without_signature_is_chromatic() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        Key: C "Octatonic"
+        | C4 h gliss E4 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration)), vec![(0, 60, 480), (480, 61, 160), (640, 62, 160), (800, 63, 160), (960, 64, 960)]);
+}
+
+#[test]
+fn test_explicit_bends() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | Bend(-0.5) C4 h Bend(0 -> 2 over h) C4 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    let bends = bends(&ir);
+    assert_eq!(bends[0], (0, -2048));
+    assert_eq!(bends[1], (960, 0));
+    assert_eq!(bends.last(), Some(&(1920, 8191)));
+}
+
+#[test]
+fn test_glissando_into_a_chord_is_an_error() {
+    let input = r#"
+    Part: Violin Instrument: Violin { | C4 h gliss [D4 F4] h | }
+    "#;
+    let err = walk(&parse(input).unwrap()).unwrap_err();
+    assert!(format!("{:#}", err).contains("A glissando must lead to a single note"));
+}
@@ -0,0 +1,114 @@
+use melos::ast::*;
+use melos::diagnostics::{Diagnostic, Severity};
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::{walk_with_diagnostics, WalkOptions};
+
+fn check(input: &str, strict: bool) -> anyhow::Result<(IrScore, Vec<Diagnostic>)> {
+    let options = WalkOptions { strict, ..Default::default() };
+    walk_with_diagnostics(&parse(input).expect("Failed to parse"), &options)
+}
+
+#[test]
+fn test_parse_measure_kinds() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        Pickup | G4 q | C5 w | Irregular | D5 h | E5 w |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    let kinds: Vec<MeasureKind> = score.parts[0].content.iter().filter_map(|b| match b {
+        MeasureBlock::Measure(m) => Some(m.kind),
+        _ => None,
+    }).collect();
+    assert_eq!(kinds, vec![MeasureKind::Pickup, MeasureKind::Regular, MeasureKind::Irregular, MeasureKind::Regular]);
+}
+
+#[test]
+fn test_wrong_length_is_warning_by_default() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 h | D4 w | }
+    "#;
+    let (_, warnings) = check(input, false).expect("Should compile");
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].severity, Severity::Warning);
+}
+
+#[test]
+fn test_strict_rejects_wrong_length() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w | D4 h | }
+    "#;
+    let err = check(input, true).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert!(diagnostic.message.contains("Measure 2"), "{}", diagnostic.message);
+}
+
+#[test]
+fn test_strict_accepts_pickup_and_irregular_bars() {
+    let input = r#"
+    Time: 3/4
+    Part: Piano Instrument: Piano {
+        Pickup | G4 q |
+        | C5 h. | Irregular | D5 h | E5 h. |
+    }
+    "#;
+    let (ir, warnings) = check(input, true).expect("Should compile");
+    assert!(warnings.is_empty());
+    let times: Vec<u32> = ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { .. } => Some(e.time),
+        _ => None,
+    }).collect();
+    assert_eq!(times, vec![0, 480, 1920, 2880]);
+}
+
+#[test]
+fn test_pickup_must_come_first() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w | Pickup | G4 q | }
+    "#;
+    let err = check(input, false).unwrap_err();
+    assert!(err.to_string().contains("not the first measure"), "{}", err);
+}
+
+#[test]
+fn test_measures_are_counted_as_written() {
+    // The pickup stays the first measure on the second pass of a repeat
+    let input = r#"
+    Part: Piano Instrument: Piano { |: Pickup | G4 q | C5 w | D5 h :| }
+    "#;
+    let (_, warnings) = check(input, false).expect("Should compile");
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].message.starts_with("Measure 3 in part 'Piano'"), "{}", warnings[0].message);
+
+    // Endings and the music after a coda keep their written numbers
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 w |: D4 w | Ending(1) { | E4 w | } Ending(2) { | F4 h | }
+        | C4 w | Segno | D4 w | To Coda | E4 w | D.S. al Coda Coda | F4 h |
+    }
+    "#;
+    let (_, warnings) = check(input, false).expect("Should compile");
+    let messages: Vec<_> = warnings.iter().map(|w| w.message.split(" in part").next().unwrap()).collect();
+    assert_eq!(messages, vec!["Measure 4", "Measure 8"]);
+
+    // A block continuing a part carries on its numbering
+    let input = r#"
+    Part: Piano Instrument: Piano { | C4 w | }
+    Part: Piano Instrument: Piano { Pickup | G4 q | }
+    "#;
+    let err = check(input, false).unwrap_err();
+    assert!(err.to_string().contains("Pickup measure 2 in part 'Piano' is not the first measure"), "{}", err);
+}
+
+#[test]
+fn test_full_length_pickup_warns() {
+    let input = r#"
+    Part: Piano Instrument: Piano { Pickup | C4 w | }
+    "#;
+    let (_, warnings) = check(input, false).expect("Should compile");
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].message.contains("Pickup"), "{}", warnings[0].message);
+    assert!(check(input, true).is_err());
+}
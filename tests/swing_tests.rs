@@ -55,17 +55,17 @@ fn test_walk_swing_timing() {
     
     assert_eq!(events.len(), 2);
     
-    if let IrEventKind::Note { duration: d1, .. } = events[0].kind {
-        if let IrEventKind::Note { duration: d2, .. } = events[1].kind {
-            // Total duration should be 480 (one quarter beat)
-            // PPQ is 480. Eighth is 240.
-            // Swung: 240 * 0.66 * 2 = 316.8 (approx 316-317)
-            // Unswung: 240 * (1-0.66) * 2 = 163.2 (approx 163-164)
-            // 316 + 164 = 480.
-            
-            assert!(d1 > 240);
-            assert!(d2 < 240);
-            assert_eq!(d1 + d2, 480);
-        }
+    if let IrEventKind::Note { duration: d1, .. } = events[0].kind
+        && let IrEventKind::Note { duration: d2, .. } = events[1].kind
+    {
+        // Total duration should be 480 (one quarter beat)
+        // PPQ is 480. Eighth is 240.
+        // Swung: 240 * 0.66 * 2 = 316.8 (approx 316-317)
+        // Unswung: 240 * (1-0.66) * 2 = 163.2 (approx 163-164)
+        // 316 + 164 = 480.
+        
+        assert!(d1 > 240);
+        assert!(d2 < 240);
+        assert_eq!(d1 + d2, 480);
     }
 }
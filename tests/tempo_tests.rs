@@ -0,0 +1,211 @@
+use melos::ast::*;
+use melos::codegen::generate;
+use melos::diagnostics::Severity;
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_diagnostics, WalkOptions};
+use midly::{MetaMessage, TrackEventKind};
+
+fn tempos(track: &IrTrack) -> Vec<(u32, f64)> {
+    track.events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Tempo(bpm) => Some((e.time, bpm)),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn test_parse_fractional_and_dotted_tempo() {
+    let input = r#"
+    Tempo: 92.5
+    Part: Piano Instrument: Piano {
+        | C4 w |
+        Tempo: 60 q.
+        | C4 w |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    assert_eq!(score.headers, vec![Header::Tempo(Tempo { bpm: 92.5, beat: None })]);
+    assert_eq!(
+        score.parts[0].content[1],
+        MeasureBlock::ContextChange(ContextChange::Tempo(Tempo {
+            bpm: 60.0,
+            beat: Some(Duration::Base(BaseDuration::Quarter, 1)),
+        }))
+    );
+
+    let ir = walk(&score).unwrap();
+    // Sixty dotted quarters a minute are ninety quarters
+    assert_eq!(tempos(&ir.tracks[0]), vec![(0, 92.5), (1920, 90.0)]);
+    assert!(tempos(&ir.tracks[1]).is_empty());
+
+    let smf = generate(&ir).unwrap();
+    let first = smf.tracks[0].iter().find_map(|e| match e.kind {
+        TrackEventKind::Meta(MetaMessage::Tempo(mpq)) => Some(mpq.as_int()),
+        _ => None,
+    });
+    assert_eq!(first, Some(648_648));
+}
+
+#[test]
+fn test_linear_accelerando() {
+    let input = r#"
+    Tempo: 60
+    Part: Piano Instrument: Piano {
+        | C4 w |
+        Accel: 120 over 1
+        | C4 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(tempos(&ir.tracks[0]), vec![
+        (0, 60.0),
+        (1920, 67.5),
+        (2040, 82.5),
+        (2160, 97.5),
+        (2280, 112.5),
+        (2400, 120.0),
+    ]);
+}
+
+#[test]
+fn test_exponential_ritardando_over_dotted_beats() {
+    let input = r#"
+    Tempo: 80 q.
+    Time: 6/8
+    Part: Piano Instrument: Piano {
+        Rit: 40 q. over 2 exp
+        | C4 q. C4 q. |
+    }
+    "#;
+    let score = parse(input).unwrap();
+    let MeasureBlock::ContextChange(ContextChange::TempoRamp(ramp)) = &score.parts[0].content[0] else {
+        panic!("Expected a tempo ramp");
+    };
+    assert_eq!(ramp.beats, 2);
+    assert_eq!(ramp.curve, TempoCurve::Exponential);
+    assert!(!ramp.accelerando);
+
+    let ir = walk(&score).unwrap();
+    // The ramp follows the header's tempo
+    let ramp = &tempos(&ir.tracks[0])[1..];
+    // Two dotted quarters, in sixteenth-note steps
+    assert_eq!(ramp.len(), 13);
+    assert!(ramp.windows(2).all(|pair| pair[1].1 < pair[0].1));
+    assert!(ramp[0].1 < 120.0);
+    assert_eq!(ramp.last(), Some(&(1440, 60.0)));
+    // Halfway through, the tempo is the geometric mean of 120 and 60
+    let middle = ramp.iter().find(|(time, _)| *time == 720).unwrap().1;
+    assert!((middle - (120.0f64 * 60.0).sqrt()).abs() < 3.0);
+}
+
+#[test]
+fn test_new_tempo_cuts_a_ramp_short() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        Accel: 180 over 8
+        | C4 w |
+        Tempo: 100
+        | C4 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    let ramp = tempos(&ir.tracks[0]);
+    assert!(ramp.iter().all(|(time, _)| *time <= 1920));
+    assert_eq!(ramp.last(), Some(&(1920, 100.0)));
+}
+
+#[test]
+fn test_ramp_in_the_wrong_direction_warns() {
+    let input = r#"
+    Tempo: 120
+    Part: Piano Instrument: Piano {
+        Accel: 60 over 4
+        | C4 w |
+    }
+    "#;
+    let (_, diagnostics) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].hint.as_deref(), Some("use `Rit:` for this change"));
+    // The warning points at the ramp itself
+    let span = diagnostics[0].span.unwrap();
+    assert_eq!(&input[span.start..span.end], "Accel: 60 over 4");
+}
+
+#[test]
+fn test_fermata_on_rest() {
+    let input = r#"
+    Tempo: 90
+    Part: Piano Instrument: Piano {
+        | C4 h r h U |
+    }
+    "#;
+    let score = parse(input).unwrap();
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(measure.events[1], Event::Rest(Some(Duration::Base(BaseDuration::Half, 0)), true));
+
+    let ir = walk(&score).unwrap();
+    assert_eq!(tempos(&ir.tracks[0]), vec![(0, 90.0), (960, 45.0), (1920, 90.0)]);
+}
+
+#[test]
+fn test_fermata_interrupts_a_ramp() {
+    let input = r#"
+    Tempo: 60
+    Part: Piano Instrument: Piano {
+        Accel: 120 over 4
+        | C4 q C4 q U C4 h |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    let ramp = tempos(&ir.tracks[0]);
+    // The fermata halves the tempo reached so far; the ramp carries on from where it
+    // has got to by the end of the held beat
+    assert!(ramp.contains(&(480, 37.5)));
+    assert!(!ramp.iter().any(|(time, _)| *time > 480 && *time < 960));
+    assert!(ramp.contains(&(960, 90.0)));
+    assert_eq!(ramp.last(), Some(&(1920, 120.0)));
+}
+
+#[test]
+fn test_tempo_applies_to_the_whole_score() {
+    let input = r#"
+    Part: A Instrument: Piano {
+        Tempo: 60
+        | C4 w | C4 w | C4 w |
+    }
+    Part: B Instrument: Flute {
+        | C5 w | C5 w | C5 h r h U |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    // B's fermata halves the tempo A set, then returns to it
+    assert_eq!(tempos(&ir.tracks[0]), vec![(0, 60.0), (4800, 30.0), (5760, 60.0)]);
+    assert!(ir.tracks[1..].iter().all(|track| tempos(track).is_empty()));
+}
+
+#[test]
+fn test_ramp_starts_from_the_tempo_of_any_part() {
+    let input = r#"
+    Part: A Instrument: Piano {
+        | C4 w |
+        Tempo: 60
+        | C4 w |
+    }
+    Part: B Instrument: Flute {
+        | C5 w |
+        Accel: 120 over 1
+        | C5 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(tempos(&ir.tracks[0]), vec![
+        (1920, 67.5),
+        (2040, 82.5),
+        (2160, 97.5),
+        (2280, 112.5),
+        (2400, 120.0),
+    ]);
+}
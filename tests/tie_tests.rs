@@ -0,0 +1,90 @@
+use melos::ast::*;
+use melos::parser::parse;
+use melos::walker::walk;
+
+mod common;
+use common::{notes, track};
+
+#[test]
+fn test_parse_tie() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 h ~ C4 h |
+    }
+    "#;
+
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(measure) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(measure.events.len(), 3);
+    assert_eq!(measure.events[1], Event::Tie);
+}
+
+#[test]
+fn test_tie_across_barline() {
+    let input = r#"
+    Time: 3/4
+    Part: Organ Instrument: "Church Organ" {
+        | E4 q C4 h ~ | C4 q D4 h |
+    }
+    "#;
+
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    assert_eq!(notes(track(&ir, "Organ"), |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 64, 480),
+        (480, 60, 1440),
+        (1920, 62, 960),
+    ]);
+}
+
+#[test]
+fn test_tie_chord_matches_per_pitch() {
+    let input = r#"
+    Part: Strings Instrument: "String Ensemble 1" {
+        | [C4 E4 G4] h ~ [C4 F4 A4] h |
+    }
+    "#;
+
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    let mut result = notes(track(&ir, "Strings"), |n| (n.time, n.pitch, n.duration));
+    result.sort();
+    assert_eq!(result, vec![
+        (0, 60, 1920),
+        (0, 64, 960),
+        (0, 67, 960),
+        (960, 65, 960),
+        (960, 69, 960),
+    ]);
+}
+
+#[test]
+fn test_tie_into_tuplet() {
+    let input = r#"
+    Part: Violin Instrument: Violin {
+        | G4 h ~ Tuplet(3:2) { G4 q A4 q B4 q } |
+    }
+    "#;
+
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    assert_eq!(notes(track(&ir, "Violin"), |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 67, 1280),
+        (1280, 69, 320),
+        (1600, 71, 320),
+    ]);
+}
+
+#[test]
+fn test_tie_interrupted_by_rest_is_ignored() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C4 h ~ r q C4 q |
+    }
+    "#;
+
+    let ir = walk(&parse(input).expect("Failed to parse")).expect("Failed to walk");
+    assert_eq!(notes(track(&ir, "Piano"), |n| (n.time, n.pitch, n.duration)), vec![
+        (0, 60, 960),
+        (1440, 60, 480),
+    ]);
+}
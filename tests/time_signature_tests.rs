@@ -7,6 +7,7 @@ use pretty_assertions::assert_eq;
 fn test_global_header_time_signature() {
     let ast = Score {
         headers: vec![Header::TimeSignature(3, 4)],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Piano".to_string(),
             instrument: "Piano".to_string(),
@@ -16,8 +17,15 @@ fn test_global_header_time_signature() {
                     duration: Some(Duration::Base(BaseDuration::Quarter, 0)),
                     dynamic: None,
                     articulation: None,
+                    grace: None,
+                    ornament: None,
+                    span: Span::default(),
                 })],
+                voices: vec![],
+                kind: MeasureKind::Regular,
+                span: Span::default(),
             })],
+            span: Span::default(),
         }],
     };
 
@@ -41,15 +49,20 @@ fn test_global_header_time_signature() {
 fn test_inline_time_signature_single_part() {
     let ast = Score {
         headers: vec![],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Piano".to_string(),
             instrument: "Piano".to_string(),
             content: vec![
                 MeasureBlock::ContextChange(ContextChange::TimeSignature(5, 8)),
                 MeasureBlock::Measure(Measure {
-                    events: vec![Event::Rest(None)],
+                    events: vec![Event::Rest(None, false)],
+                    voices: vec![],
+                    kind: MeasureKind::Regular,
+                    span: Span::default(),
                 }),
             ],
+            span: Span::default(),
         }],
     };
 
@@ -70,24 +83,27 @@ fn test_inline_time_signature_single_part() {
 fn test_inline_time_signature_multiple_parts_merge() {
     let ast = Score {
         headers: vec![],
+        motifs: Default::default(),
         parts: vec![
             Part {
                 name: "Piano".to_string(),
                 instrument: "Piano".to_string(),
                 content: vec![
                     MeasureBlock::ContextChange(ContextChange::TimeSignature(4, 4)),
-                    MeasureBlock::Measure(Measure { events: vec![Event::Rest(None)] }),
+                    MeasureBlock::Measure(Measure { events: vec![Event::Rest(None, false)], voices: vec![], kind: MeasureKind::Regular, span: Span::default() }),
                     MeasureBlock::ContextChange(ContextChange::TimeSignature(3, 4)),
                 ],
+                span: Span::default(),
             },
             Part {
                 name: "Violin".to_string(),
                 instrument: "Violin".to_string(),
                 content: vec![
                     MeasureBlock::ContextChange(ContextChange::TimeSignature(4, 4)),
-                    MeasureBlock::Measure(Measure { events: vec![Event::Rest(None)] }),
+                    MeasureBlock::Measure(Measure { events: vec![Event::Rest(None, false)], voices: vec![], kind: MeasureKind::Regular, span: Span::default() }),
                     MeasureBlock::ContextChange(ContextChange::TimeSignature(3, 4)),
                 ],
+                span: Span::default(),
             },
         ],
     };
@@ -111,14 +127,16 @@ fn test_inline_time_signature_multiple_parts_merge() {
 fn test_complex_time_signatures() {
     let ast = Score {
         headers: vec![],
+        motifs: Default::default(),
         parts: vec![Part {
             name: "Piano".to_string(),
             instrument: "Piano".to_string(),
             content: vec![
                 MeasureBlock::ContextChange(ContextChange::TimeSignature(11, 8)),
-                MeasureBlock::Measure(Measure { events: vec![Event::Rest(None)] }),
+                MeasureBlock::Measure(Measure { events: vec![Event::Rest(None, false)], voices: vec![], kind: MeasureKind::Regular, span: Span::default() }),
                 MeasureBlock::ContextChange(ContextChange::TimeSignature(7, 16)),
             ],
+            span: Span::default(),
         }],
     };
 
@@ -0,0 +1,75 @@
+use melos::ir::*;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_diagnostics, WalkOptions};
+
+fn onsets(ir: &IrScore) -> Vec<(u32, u32)> {
+    ir.tracks[1].events.iter().filter_map(|e| match e.kind {
+        IrEventKind::Note { duration, .. } => Some((e.time, duration)),
+        _ => None,
+    }).collect()
+}
+
+const NESTED: &str = r#"
+Part: Piano Instrument: Piano {
+    | Tuplet(3:2) { Tuplet(7:4) { C4 e D4 e E4 e F4 e G4 e A4 e B4 e } C5 q } C5 h |
+}
+"#;
+
+#[test]
+fn test_nested_tuplets_choose_exact_ppq() {
+    let (ir, warnings) = walk_with_diagnostics(&parse(NESTED).unwrap(), &WalkOptions::default()).unwrap();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(ir.ppq, 3360);
+    assert_eq!(onsets(&ir), vec![
+        (0, 640), (640, 640), (1280, 640), (1920, 640), (2560, 640), (3200, 640), (3840, 640),
+        (4480, 2240),
+        (6720, 6720),
+    ]);
+}
+
+#[test]
+fn test_rounding_does_not_accumulate() {
+    let options = WalkOptions { ppq: Some(480), ..Default::default() };
+    let (ir, warnings) = walk_with_diagnostics(&parse(NESTED).unwrap(), &options).unwrap();
+    assert_eq!(warnings.len(), 1);
+    let times: Vec<u32> = onsets(&ir).iter().map(|(time, _)| *time).collect();
+    assert_eq!(times, vec![0, 91, 183, 274, 366, 457, 549, 640, 960]);
+}
+
+#[test]
+fn test_default_ppq_is_kept_when_exact() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | Tuplet(3:2) { C4 e C4 e C4 e } Tuplet(5:4) { C4 s C4 s C4 s C4 s C4 s } r h | }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(ir.ppq, 480);
+}
+
+#[test]
+fn test_ppq_header_and_override() {
+    let input = r#"
+    PPQ: 960
+    Part: Piano Instrument: Piano { | C4 q r h. | }
+    "#;
+    let score = parse(input).unwrap();
+    let ir = walk(&score).unwrap();
+    assert_eq!(ir.ppq, 960);
+    assert_eq!(onsets(&ir), vec![(0, 960)]);
+
+    let options = WalkOptions { ppq: Some(96), ..Default::default() };
+    let (ir, _) = walk_with_diagnostics(&score, &options).unwrap();
+    assert_eq!(ir.ppq, 96);
+    assert_eq!(onsets(&ir), vec![(0, 96)]);
+}
+
+#[test]
+fn test_nested_tuplets_fill_measure() {
+    let input = r#"
+    Time: 3/4
+    Part: Piano Instrument: Piano {
+        | Tuplet(3:2) { Tuplet(5:4) { C4 s C4 s C4 s C4 s C4 s } C4 h } C4 q |
+    }
+    "#;
+    let (_, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+}
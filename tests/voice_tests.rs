@@ -0,0 +1,109 @@
+use melos::ast::*;
+use melos::diagnostics::Diagnostic;
+use melos::parser::parse;
+use melos::walker::{walk, walk_with_diagnostics, WalkOptions};
+use melos::wasm::compile_to_musicxml;
+
+mod common;
+use common::notes;
+
+#[test]
+fn test_parse_voices() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C5 h B4 h V2: E4 w |
+        | V1: C5 w V2: G4 h F4 h V3: C3 w |
+    }
+    "#;
+    let score = parse(input).expect("Failed to parse");
+    let MeasureBlock::Measure(first) = &score.parts[0].content[0] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(first.events.len(), 2);
+    assert_eq!(first.voices.len(), 1);
+    assert_eq!(first.voices[0].number, 2);
+
+    let MeasureBlock::Measure(second) = &score.parts[0].content[1] else {
+        panic!("Expected measure");
+    };
+    assert_eq!(second.events.len(), 1);
+    let numbers: Vec<u8> = second.voices.iter().map(|v| v.number).collect();
+    assert_eq!(numbers, vec![2, 3]);
+}
+
+#[test]
+fn test_repeated_voice_is_an_error() {
+    let input = r#"
+    Part: Piano Instrument: Piano { | C5 w V1: E4 w | }
+    "#;
+    let err = parse(input).unwrap_err();
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Expected a diagnostic");
+    assert_eq!(diagnostic.message, "Voice 1 is written twice in this measure");
+}
+
+#[test]
+fn test_voices_share_the_track() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C5 h B4 h V2: E4 q F4 q G4 h |
+        | C5 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(ir.tracks.len(), 2);
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration, n.voice)), vec![
+        (0, 72, 960, 1),
+        (0, 64, 480, 2),
+        (480, 65, 480, 2),
+        (960, 71, 960, 1),
+        (960, 67, 960, 2),
+        (1920, 72, 1920, 1),
+    ]);
+}
+
+#[test]
+fn test_each_voice_is_checked() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C5 w V2: E4 h |
+        | C5 w |
+    }
+    "#;
+    let (ir, warnings) = walk_with_diagnostics(&parse(input).unwrap(), &WalkOptions::default()).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].message, "Measure 1 in part 'Piano' (voice 2) has incorrect duration. Expected 4 beats, got 2.");
+    // The next measure still starts after the longest voice
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration, n.voice)).last(), Some(&(1920, 72, 1920, 1)));
+}
+
+#[test]
+fn test_ties_stay_within_a_voice() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C5 w ~ V2: C4 h C5 h |
+        | C5 w V2: C4 w |
+    }
+    "#;
+    let ir = walk(&parse(input).unwrap()).unwrap();
+    assert_eq!(notes(&ir.tracks[1], |n| (n.time, n.pitch, n.duration, n.voice)), vec![
+        (0, 72, 3840, 1),
+        (0, 60, 960, 2),
+        (960, 72, 960, 2),
+        (1920, 60, 1920, 2),
+    ]);
+}
+
+#[test]
+fn test_musicxml_voices() {
+    let input = r#"
+    Part: Piano Instrument: Piano {
+        | C5 h B4 h V2: E4 w |
+        | C5 w |
+    }
+    "#;
+    let xml = compile_to_musicxml(input).expect("Failed to export");
+    assert_eq!(xml.matches("<backup>").count(), 1);
+    assert!(xml.contains("<backup>\n        <duration>1920</duration>\n      </backup>"));
+    assert_eq!(xml.matches("<voice>2</voice>").count(), 1);
+    assert_eq!(xml.matches("<voice>1</voice>").count(), 3);
+}